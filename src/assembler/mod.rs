@@ -0,0 +1,676 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::IoResult;
+use std::num;
+
+#[cfg(test)]
+mod tests;
+
+// Programs are assembled to run from the usual load address
+const LOAD_ADDRESS:     u16  = 0x200;
+const MAX_PROGRAM_SIZE: uint = 65536 - 0x200;
+// How deeply macros can expand into other macros
+const MAX_MACRO_DEPTH:  uint = 64;
+
+// An error in the source, with the line it was found on
+#[derive(PartialEq)]
+pub struct AsmError {
+    pub line    : uint,
+    pub message : String
+}
+
+impl fmt::Show for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// An assembled program, ready to be written out as a .ch8 file
+pub struct Program {
+    pub bytes      : Vec<u8>,
+    pub source_map : SourceMap
+}
+
+// The source line that the code at addr was assembled from
+#[derive(Clone, Show, PartialEq)]
+pub struct SourceLine {
+    pub addr : u16,
+    pub line : uint,
+    pub text : String
+}
+
+// Maps addresses back to source lines, so the debugger can show them
+#[derive(Show, PartialEq)]
+pub struct SourceMap {
+    lines : Vec<SourceLine>
+}
+
+impl SourceMap {
+
+    // The line the instruction at addr belongs to, code generated by a macro
+    // maps back to the line it was used on
+    pub fn lookup(&self, addr: u16) -> Option<&SourceLine> {
+        self.lines.iter().filter(|l| l.addr <= addr).last()
+    }
+
+    pub fn lines(&self) -> &[SourceLine] {
+        self.lines.as_slice()
+    }
+
+    // Write the map as text, one "ADDR LINE SOURCE" entry per line
+    pub fn write(&self, w: &mut Writer) -> IoResult<()> {
+        for l in self.lines.iter() {
+            try!(writeln!(w, "{:04X} {} {}", l.addr, l.line, l.text));
+        }
+        Ok(())
+    }
+
+    // Read back a map written by write
+    pub fn parse(text: &str) -> Result<SourceMap, String> {
+        let mut lines = Vec::new();
+        for entry in text.lines() {
+            let (addr, rest) = match entry.find(' ') {
+                Some(pos) => (entry.slice_to(pos), entry.slice_from(pos + 1)),
+                None => return Err(format!("invalid source map entry: {}", entry))
+            };
+            let (line, source) = match rest.find(' ') {
+                Some(pos) => (rest.slice_to(pos), rest.slice_from(pos + 1)),
+                None => (rest, "")
+            };
+            match (num::from_str_radix::<u16>(addr, 16), from_str::<uint>(line)) {
+                (Some(addr), Some(line)) => lines.push(SourceLine { addr: addr, line: line, text: source.to_string() }),
+                _ => return Err(format!("invalid source map entry: {}", entry))
+            }
+        }
+        Ok(SourceMap { lines: lines })
+    }
+}
+
+// Assemble Octo style source. Execution starts at the label main.
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    let mut asm = Assembler::new(source);
+    try!(asm.run());
+    Ok(Program { bytes: asm.rom, source_map: SourceMap { lines: asm.map } })
+}
+
+#[derive(Clone)]
+struct Token {
+    text  : String,
+    line  : uint,
+    // how many macro expansions produced this token
+    depth : uint
+}
+
+struct Macro {
+    params : Vec<String>,
+    body   : Vec<Token>
+}
+
+// A reference to a label that hadn't been defined yet when it was used
+struct Fixup {
+    offset : uint,
+    label  : String,
+    line   : uint,
+    long   : bool
+}
+
+// An open loop, with the jumps out of it left by while statements
+struct Loop {
+    start : u16,
+    exits : Vec<uint>
+}
+
+struct Assembler<'a> {
+    source  : Vec<&'a str>,
+    // tokens still to be assembled, in reverse order
+    tokens  : Vec<Token>,
+    rom     : Vec<u8>,
+    labels  : HashMap<String, u16>,
+    consts  : HashMap<String, int>,
+    aliases : HashMap<String, uint>,
+    macros  : HashMap<String, Macro>,
+    fixups  : Vec<Fixup>,
+    loops   : Vec<Loop>,
+    map     : Vec<SourceLine>,
+    line    : uint
+}
+
+impl<'a> Assembler<'a> {
+
+    fn new(source: &'a str) -> Assembler<'a> {
+        let lines: Vec<&str> = source.lines().collect();
+        let mut tokens = Vec::new();
+        for (idx, line) in lines.iter().enumerate() {
+            let code = match line.find('#') {
+                Some(pos) => line.slice_to(pos),
+                None => *line
+            };
+            for word in code.words() {
+                tokens.push(Token { text: word.to_string(), line: idx + 1, depth: 0 });
+            }
+        }
+        tokens.reverse();
+
+        Assembler {
+            source  : lines,
+            tokens  : tokens,
+            rom     : Vec::new(),
+            labels  : HashMap::new(),
+            consts  : HashMap::new(),
+            aliases : HashMap::new(),
+            macros  : HashMap::new(),
+            fixups  : Vec::new(),
+            loops   : Vec::new(),
+            map     : Vec::new(),
+            line    : 1
+        }
+    }
+
+    fn run(&mut self) -> Result<(), AsmError> {
+        // the program starts with a jump to main
+        let entry = Token { text: "main".to_string(), line: 0, depth: 0 };
+        try!(self.emit_addr(0x1000, &entry));
+
+        while !self.tokens.is_empty() {
+            let start = self.rom.len();
+            let tok = try!(self.next());
+            try!(self.statement(&tok));
+            if self.rom.len() > MAX_PROGRAM_SIZE {
+                return Err(self.error("program does not fit in memory".to_string()));
+            }
+
+            if self.rom.len() > start {
+                let text = self.source[tok.line - 1].trim().to_string();
+                self.map.push(SourceLine { addr: LOAD_ADDRESS + start as u16, line: tok.line, text: text });
+            }
+        }
+
+        if !self.loops.is_empty() {
+            return Err(self.error("loop without again".to_string()));
+        }
+
+        for fixup in self.fixups.iter() {
+            let addr = match self.labels.get(&fixup.label) {
+                Some(addr) => *addr,
+                None => return Err(AsmError { line: fixup.line,
+                                              message: format!("undefined label: {}", fixup.label) })
+            };
+            if fixup.long {
+                self.rom[fixup.offset] = (addr >> 8) as u8;
+                self.rom[fixup.offset + 1] = addr as u8;
+            } else {
+                if addr > 0xFFF {
+                    return Err(AsmError { line: fixup.line,
+                                          message: format!("address out of range: {}", fixup.label) });
+                }
+                self.rom[fixup.offset] |= ((addr >> 8) & 0xF) as u8;
+                self.rom[fixup.offset + 1] = addr as u8;
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, tok: &Token) -> Result<(), AsmError> {
+        match tok.text.as_slice() {
+            ":" => {
+                let name = try!(self.next());
+                if self.labels.contains_key(&name.text) {
+                    return Err(self.error(format!("label defined twice: {}", name.text)));
+                }
+                let addr = try!(self.addr());
+                self.labels.insert(name.text, addr);
+            },
+            ":const" => {
+                let name = try!(self.next());
+                let value = try!(self.next());
+                let value = try!(self.value(&value));
+                self.consts.insert(name.text, value);
+            },
+            ":alias" => {
+                let name = try!(self.next());
+                let reg = try!(self.next());
+                let reg = try!(self.register(&reg));
+                self.aliases.insert(name.text, reg);
+            },
+            ":macro" => try!(self.define_macro()),
+            ":call" => {
+                let target = try!(self.next());
+                try!(self.emit_addr(0x2000, &target));
+            },
+            ":byte" => {
+                let value = try!(self.next());
+                let value = try!(self.byte(&value));
+                self.rom.push(value);
+            },
+            ":org" => {
+                let addr = try!(self.next());
+                let addr = try!(self.value(&addr));
+                if addr < (LOAD_ADDRESS as uint + self.rom.len()) as int || addr > 0xFFFF {
+                    return Err(self.error(format!("invalid :org address: {}", addr)));
+                }
+                while LOAD_ADDRESS as int + (self.rom.len() as int) < addr {
+                    self.rom.push(0);
+                }
+            },
+            "clear"        => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left"  => self.emit(0x00FC),
+            "exit"         => self.emit(0x00FD),
+            "lores"        => self.emit(0x00FE),
+            "hires"        => self.emit(0x00FF),
+            "audio"        => self.emit(0xF002),
+            "scroll-down" | "scroll-up" | "plane" => {
+                let n = try!(self.next());
+                let n = try!(self.nibble(&n));
+                self.emit(match tok.text.as_slice() {
+                    "scroll-down" => 0x00C0 | n,
+                    "scroll-up"   => 0x00D0 | n,
+                    _             => 0xF001 | n << 8
+                });
+            },
+            "jump" | "jump0" => {
+                let target = try!(self.next());
+                try!(self.emit_addr(if tok.text.as_slice() == "jump" { 0x1000 } else { 0xB000 }, &target));
+            },
+            "sprite" => {
+                let (x, y) = (try!(self.next()), try!(self.next()));
+                let (x, y) = (try!(self.register(&x)), try!(self.register(&y)));
+                let n = try!(self.next());
+                let n = try!(self.nibble(&n));
+                self.emit(0xD000 | (x << 8) as u16 | (y << 4) as u16 | n);
+            },
+            "save" | "load" => {
+                let x = try!(self.next());
+                let x = try!(self.register(&x));
+                let store = tok.text.as_slice() == "save";
+                if self.peek() == Some("-") {
+                    try!(self.next());
+                    let y = try!(self.next());
+                    let y = try!(self.register(&y));
+                    self.emit(if store { 0x5002 } else { 0x5003 } | (x << 8) as u16 | (y << 4) as u16);
+                } else {
+                    self.emit(if store { 0xF055 } else { 0xF065 } | (x << 8) as u16);
+                }
+            },
+            "bcd" | "saveflags" | "loadflags" => {
+                let x = try!(self.next());
+                let x = try!(self.register(&x));
+                self.emit(match tok.text.as_slice() {
+                    "bcd"       => 0xF033,
+                    "saveflags" => 0xF075,
+                    _           => 0xF085
+                } | (x << 8) as u16);
+            },
+            "delay" | "buzzer" | "pitch" => {
+                try!(self.expect(":="));
+                let x = try!(self.next());
+                let x = try!(self.register(&x));
+                self.emit(match tok.text.as_slice() {
+                    "delay"  => 0xF015,
+                    "buzzer" => 0xF018,
+                    _        => 0xF03A
+                } | (x << 8) as u16);
+            },
+            "i" => try!(self.i_statement()),
+            "loop" => {
+                let start = try!(self.addr());
+                self.loops.push(Loop { start: start, exits: Vec::new() });
+            },
+            "again" => {
+                let lp = match self.loops.pop() {
+                    Some(lp) => lp,
+                    None => return Err(self.error("again without loop".to_string()))
+                };
+                if lp.start > 0xFFF {
+                    return Err(self.error(format!("address out of range: {:X}", lp.start)));
+                }
+                self.emit(0x1000 | lp.start);
+                // while jumps out to just past the again
+                let end = LOAD_ADDRESS as uint + self.rom.len();
+                if end > 0xFFF && !lp.exits.is_empty() {
+                    return Err(self.error(format!("address out of range: {:X}", end)));
+                }
+                for offset in lp.exits.iter() {
+                    self.rom[*offset] |= ((end >> 8) & 0xF) as u8;
+                    self.rom[*offset + 1] = end as u8;
+                }
+            },
+            "while" => {
+                let (_, skip_if_true) = try!(self.condition());
+                if self.loops.is_empty() {
+                    return Err(self.error("while outside of a loop".to_string()));
+                }
+                self.emit(skip_if_true);
+                let exit = self.rom.len();
+                self.loops.last_mut().unwrap().exits.push(exit);
+                self.emit(0x1000);
+            },
+            "if" => {
+                let (skip_unless_true, _) = try!(self.condition());
+                try!(self.expect("then"));
+                self.emit(skip_unless_true);
+                let body = try!(self.next());
+                try!(self.statement(&body));
+            },
+            _ => {
+                if self.macros.contains_key(&tok.text) {
+                    try!(self.expand_macro(tok));
+                } else if self.register(tok).is_ok() {
+                    let x = try!(self.register(tok));
+                    try!(self.register_statement(x));
+                } else if Assembler::number(tok.text.as_slice()).is_some() {
+                    let value = try!(self.byte(tok));
+                    self.rom.push(value);
+                } else {
+                    // a bare name calls a subroutine
+                    try!(self.emit_addr(0x2000, tok));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // i := addr, i := long addr, i := hex vx, i := bighex vx and i += vx
+    fn i_statement(&mut self) -> Result<(), AsmError> {
+        let op = try!(self.next());
+        match op.text.as_slice() {
+            ":=" => {
+                let src = try!(self.next());
+                match src.text.as_slice() {
+                    "hex" | "bighex" => {
+                        let x = try!(self.next());
+                        let x = try!(self.register(&x));
+                        self.emit(if src.text.as_slice() == "hex" { 0xF029 } else { 0xF030 } | (x << 8) as u16);
+                    },
+                    "long" => {
+                        self.emit(0xF000);
+                        let target = try!(self.next());
+                        try!(self.emit_long_addr(&target));
+                    },
+                    _ => try!(self.emit_addr(0xA000, &src))
+                }
+            },
+            "+=" => {
+                let x = try!(self.next());
+                let x = try!(self.register(&x));
+                self.emit(0xF01E | (x << 8) as u16);
+            },
+            _ => return Err(self.error(format!("invalid operator for i: {}", op.text)))
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: uint) -> Result<(), AsmError> {
+        let op = try!(self.next());
+        let src = try!(self.next());
+        let vx = (x << 8) as u16;
+
+        // register to register operations are 8XYN
+        let alu = match op.text.as_slice() {
+            ":=" => Some(0x0), "|=" => Some(0x1), "&=" => Some(0x2), "^=" => Some(0x3),
+            "+=" => Some(0x4), "-=" => Some(0x5), ">>=" => Some(0x6), "=-" => Some(0x7),
+            "<<=" => Some(0xE),
+            _ => return Err(self.error(format!("invalid operator: {}", op.text)))
+        };
+        match self.register(&src) {
+            Ok(y) => {
+                self.emit(0x8000 | vx | (y << 4) as u16 | alu.unwrap());
+                return Ok(());
+            },
+            Err(_) => {}
+        }
+
+        match (op.text.as_slice(), src.text.as_slice()) {
+            (":=", "random") => {
+                let mask = try!(self.next());
+                let mask = try!(self.byte(&mask));
+                self.emit(0xC000 | vx | mask as u16);
+            },
+            (":=", "delay") => self.emit(0xF007 | vx),
+            (":=", "key") => self.emit(0xF00A | vx),
+            (":=", _) => {
+                let value = try!(self.byte(&src));
+                self.emit(0x6000 | vx | value as u16);
+            },
+            ("+=", _) => {
+                let value = try!(self.byte(&src));
+                self.emit(0x7000 | vx | value as u16);
+            },
+            ("-=", _) => {
+                let value = try!(self.byte(&src));
+                self.emit(0x7000 | vx | (-(value as i16) as u8) as u16);
+            },
+            _ => return Err(self.error(format!("{} needs a register operand", op.text)))
+        }
+        Ok(())
+    }
+
+    // Parse "vx == n", "vx != vy", "vx key" or "vx -key", returning the
+    // opcodes that skip the next instruction unless, or if, it holds
+    fn condition(&mut self) -> Result<(u16, u16), AsmError> {
+        let x = try!(self.next());
+        let vx = (try!(self.register(&x)) << 8) as u16;
+        let op = try!(self.next());
+        match op.text.as_slice() {
+            "key"  => Ok((0xE0A1 | vx, 0xE09E | vx)),
+            "-key" => Ok((0xE09E | vx, 0xE0A1 | vx)),
+            "==" | "!=" => {
+                let rhs = try!(self.next());
+                let (skip_if_eq, skip_if_neq) = match self.register(&rhs) {
+                    Ok(y) => (0x5000 | vx | (y << 4) as u16, 0x9000 | vx | (y << 4) as u16),
+                    Err(_) => {
+                        let n = try!(self.byte(&rhs)) as u16;
+                        (0x3000 | vx | n, 0x4000 | vx | n)
+                    }
+                };
+                Ok(if op.text.as_slice() == "==" { (skip_if_neq, skip_if_eq) } else { (skip_if_eq, skip_if_neq) })
+            },
+            _ => Err(self.error(format!("invalid condition: {}", op.text)))
+        }
+    }
+
+    // :macro name params { body }
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = try!(self.next());
+        let mut params = Vec::new();
+        loop {
+            let tok = try!(self.next());
+            if tok.text.as_slice() == "{" {
+                break;
+            }
+            params.push(tok.text);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1u;
+        loop {
+            let tok = try!(self.next());
+            match tok.text.as_slice() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                _ => {}
+            }
+            body.push(tok);
+        }
+        self.macros.insert(name.text, Macro { params: params, body: body });
+        Ok(())
+    }
+
+    // Replace a macro invocation with its body, substituting the arguments
+    fn expand_macro(&mut self, tok: &Token) -> Result<(), AsmError> {
+        if tok.depth >= MAX_MACRO_DEPTH {
+            return Err(self.error("macro recursion too deep".to_string()));
+        }
+        let (params, body) = {
+            let m = self.macros.get(&tok.text).unwrap();
+            (m.params.clone(), m.body.clone())
+        };
+        let mut args = Vec::new();
+        for _ in range(0u, params.len()) {
+            args.push(try!(self.next()).text);
+        }
+
+        for btok in body.iter().rev() {
+            let text = match params.iter().position(|p| *p == btok.text) {
+                Some(idx) => args[idx].clone(),
+                None => btok.text.clone()
+            };
+            self.tokens.push(Token { text: text, line: tok.line, depth: tok.depth + 1 });
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Token, AsmError> {
+        match self.tokens.pop() {
+            Some(tok) => {
+                self.line = tok.line;
+                Ok(tok)
+            },
+            None => Err(self.error("unexpected end of file".to_string()))
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|tok| tok.text.as_slice())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AsmError> {
+        let tok = try!(self.next());
+        if tok.text.as_slice() != text {
+            return Err(self.error(format!("expected {}, found {}", text, tok.text)));
+        }
+        Ok(())
+    }
+
+    fn error(&self, message: String) -> AsmError {
+        AsmError { line: self.line, message: message }
+    }
+
+    // The address the next byte goes at, which is past the end of memory
+    // once the program fills it
+    fn addr(&self) -> Result<u16, AsmError> {
+        let addr = LOAD_ADDRESS as uint + self.rom.len();
+        if addr > 0xFFFF {
+            return Err(self.error("program does not fit in memory".to_string()));
+        }
+        Ok(addr as u16)
+    }
+
+    fn emit(&mut self, opcode: u16) {
+        self.rom.push((opcode >> 8) as u8);
+        self.rom.push(opcode as u8);
+    }
+
+    // Emit an NNN instruction addressing a label, constant or number
+    fn emit_addr(&mut self, opcode: u16, target: &Token) -> Result<(), AsmError> {
+        match self.address(target) {
+            Some(addr) => {
+                if addr < 0 || addr > 0xFFF {
+                    return Err(self.error(format!("address out of range: {}", target.text)));
+                }
+                self.emit(opcode | addr as u16);
+            },
+            None => {
+                let offset = self.rom.len();
+                self.fixups.push(Fixup { offset: offset, label: target.text.clone(),
+                                         line: target.line, long: false });
+                self.emit(opcode);
+            }
+        }
+        Ok(())
+    }
+
+    // Emit the 16-bit operand of i := long
+    fn emit_long_addr(&mut self, target: &Token) -> Result<(), AsmError> {
+        match self.address(target) {
+            Some(addr) => {
+                if addr < 0 || addr > 0xFFFF {
+                    return Err(self.error(format!("address out of range: {}", target.text)));
+                }
+                self.emit(addr as u16);
+            },
+            None => {
+                let offset = self.rom.len();
+                self.fixups.push(Fixup { offset: offset, label: target.text.clone(),
+                                         line: target.line, long: true });
+                self.emit(0);
+            }
+        }
+        Ok(())
+    }
+
+    // A known address, or None for a label that must be fixed up later. It's
+    // left to the caller to check the address fits.
+    fn address(&self, tok: &Token) -> Option<int> {
+        match self.labels.get(&tok.text) {
+            Some(addr) => return Some(*addr as int),
+            None => {}
+        }
+        match self.value(tok) {
+            Ok(value) => Some(value),
+            Err(_) => None
+        }
+    }
+
+    fn register(&self, tok: &Token) -> Result<uint, AsmError> {
+        match self.aliases.get(&tok.text) {
+            Some(reg) => return Ok(*reg),
+            None => {}
+        }
+        let name: String = tok.text.chars().map(|c| c.to_lowercase()).collect();
+        if name.len() == 2 && name.starts_with("v") {
+            match num::from_str_radix::<uint>(name.slice_from(1), 16) {
+                Some(reg) => return Ok(reg),
+                None => {}
+            }
+        }
+        Err(self.error(format!("expected a register, found {}", tok.text)))
+    }
+
+    // A number or constant
+    fn value(&self, tok: &Token) -> Result<int, AsmError> {
+        match self.consts.get(&tok.text) {
+            Some(value) => return Ok(*value),
+            None => {}
+        }
+        match Assembler::number(tok.text.as_slice()) {
+            Some(value) => Ok(value),
+            None => Err(self.error(format!("expected a number, found {}", tok.text)))
+        }
+    }
+
+    fn byte(&self, tok: &Token) -> Result<u8, AsmError> {
+        let value = try!(self.value(tok));
+        if value < -128 || value > 255 {
+            return Err(self.error(format!("value out of range for a byte: {}", tok.text)));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&self, tok: &Token) -> Result<u16, AsmError> {
+        let value = try!(self.value(tok));
+        if value < 0 || value > 15 {
+            return Err(self.error(format!("value out of range for a nibble: {}", tok.text)));
+        }
+        Ok(value as u16)
+    }
+
+    // Decimal, 0x hex or 0b binary, optionally negative
+    fn number(s: &str) -> Option<int> {
+        let (negative, digits) = if s.starts_with("-") { (true, s.slice_from(1)) } else { (false, s) };
+        let value = if digits.starts_with("0x") {
+            num::from_str_radix::<int>(digits.slice_from(2), 16)
+        } else if digits.starts_with("0b") {
+            num::from_str_radix::<int>(digits.slice_from(2), 2)
+        } else {
+            from_str::<int>(digits)
+        };
+        value.map(|v| if negative { -v } else { v })
+    }
+}
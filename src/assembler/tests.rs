@@ -0,0 +1,127 @@
+use std::io::MemWriter;
+use super::{assemble, SourceMap};
+
+fn assemble_ok(source: &str) -> Vec<u8> {
+    match assemble(source) {
+        Ok(program) => program.bytes,
+        Err(err) => panic!("{}", err)
+    }
+}
+
+#[test]
+fn test_entry_point() {
+    // execution starts with a jump to main
+    assert_eq!(assemble_ok(": main clear"), vec![0x12, 0x02, 0x00, 0xE0]);
+    assert!(assemble("clear").is_err());
+}
+
+#[test]
+fn test_registers() {
+    let bytes = assemble_ok(": main v0 := 5  v1 += 0x10  v2 := v3  v4 ^= v5  v6 >>= v6  vA := random 0xFF  vb -= 1");
+    assert_eq!(bytes.slice_from(2), [0x60, 0x05, 0x71, 0x10, 0x82, 0x30, 0x84, 0x53,
+                                     0x86, 0x66, 0xCA, 0xFF, 0x7B, 0xFF].as_slice());
+}
+
+#[test]
+fn test_labels_and_i() {
+    // forward and backward references, i := label and sprite data
+    let bytes = assemble_ok(": main i := face  sprite v0 v1 2  jump main  : face 0xFF 0b10000001");
+    assert_eq!(bytes, vec![0x12, 0x02, 0xA2, 0x08, 0xD0, 0x12, 0x12, 0x02, 0xFF, 0x81]);
+
+    let bytes = assemble_ok(": main i := long data  i := hex v3  : data");
+    assert_eq!(bytes, vec![0x12, 0x02, 0xF0, 0x00, 0x02, 0x08, 0xF3, 0x29]);
+
+    assert_eq!(assemble(": main jump nowhere").err().unwrap().message.as_slice(), "undefined label: nowhere");
+}
+
+#[test]
+fn test_const_and_alias() {
+    let bytes = assemble_ok(":const SPEED 3\n:alias px v5\n: main px := SPEED  px += px");
+    assert_eq!(bytes.slice_from(2), [0x65, 0x03, 0x85, 0x54].as_slice());
+}
+
+#[test]
+fn test_control_flow() {
+    // if ... then skips unless the condition holds
+    let bytes = assemble_ok(": main if v1 == 4 then v2 := 0  if v1 != v3 then return  if v0 key then clear");
+    assert_eq!(bytes.slice_from(2), [0x41, 0x04, 0x62, 0x00, 0x51, 0x30, 0x00, 0xEE,
+                                     0xE0, 0xA1, 0x00, 0xE0].as_slice());
+
+    // loop ... again with a while jumping out past the end
+    let bytes = assemble_ok(": main loop v0 += 1 while v0 != 10 again exit");
+    assert_eq!(bytes.slice_from(2), [0x70, 0x01, 0x40, 0x0A, 0x12, 0x0A, 0x12, 0x02, 0x00, 0xFD].as_slice());
+
+    assert!(assemble(": main loop clear").is_err());
+    assert!(assemble(": main again").is_err());
+}
+
+#[test]
+fn test_addresses_out_of_range() {
+    // jumps can only reach the first 4K, even to labels defined later
+    let err = assemble(": main\n  jump far\n:org 0x1000\n: far clear").err().unwrap();
+    assert_eq!(err.message.as_slice(), "address out of range: far");
+    assert_eq!(err.line, 2);
+    assert!(assemble(": main i := long far\n:org 0x1000\n: far clear").is_ok());
+
+    // numbers are checked before they're cut down to size
+    let err = assemble(": main\n  jump 0x10200").err().unwrap();
+    assert_eq!(err.message.as_slice(), "address out of range: 0x10200");
+    assert_eq!(err.line, 2);
+    let err = assemble(": main\n  i := long 0x12345").err().unwrap();
+    assert_eq!(err.message.as_slice(), "address out of range: 0x12345");
+    assert_eq!(err.line, 2);
+    assert!(assemble(": main jump -2").is_err());
+    assert_eq!(assemble_ok(": main i := long 0xFFFF").slice_from(2), [0xF0, 0x00, 0xFF, 0xFF].as_slice());
+
+    // a program can fill memory, but a label can't go past the end of it
+    assert_eq!(assemble_ok(": main\n:org 0xFFFE\n  clear").len(), 0xFE00);
+    let err = assemble(": main\n:org 0xFFFE\n  clear\n: past").err().unwrap();
+    assert_eq!(err.message.as_slice(), "program does not fit in memory");
+    assert_eq!(err.line, 4);
+    assert!(assemble(": main\n:org 0xFFFF\n  clear").is_err());
+
+    let err = assemble(": main\n:org 0x1000\n  loop clear again").err().unwrap();
+    assert_eq!(err.message.as_slice(), "address out of range: 1000");
+    let err = assemble(": main\n:org 0xFFA\n  loop while v0 != 0 again").err().unwrap();
+    assert_eq!(err.message.as_slice(), "address out of range: 1000");
+    assert_eq!(err.line, 3);
+}
+
+#[test]
+fn test_macros() {
+    let source = ":macro move REG AMOUNT { REG += AMOUNT }\n: main\n  move v1 2\n  move v2 3";
+    let program = assemble(source).unwrap();
+    assert_eq!(program.bytes.slice_from(2), [0x71, 0x02, 0x72, 0x03].as_slice());
+    assert_eq!(program.source_map.lookup(0x204).unwrap().line, 4);
+
+    // macros can use other macros, but not forever
+    let source = ":macro inc REG { REG += 1 }\n:macro twice REG { inc REG inc REG }\n: main twice v3";
+    assert_eq!(assemble_ok(source).slice_from(2), [0x73, 0x01, 0x73, 0x01].as_slice());
+    let err = assemble(":macro forever { clear forever }\n: main\n  forever").err().unwrap();
+    assert_eq!(err.message.as_slice(), "macro recursion too deep");
+    assert_eq!(err.line, 3);
+}
+
+#[test]
+fn test_errors_report_line() {
+    let err = assemble(": main\n  v0 := 5\n  v1 := 300").err().unwrap();
+    assert_eq!(err.line, 3);
+}
+
+#[test]
+fn test_source_map() {
+    let source = ": main\n  v0 := 5 # five\n  sub\n: sub\n  return";
+    let program = assemble(source).unwrap();
+
+    let line = program.source_map.lookup(0x204).unwrap();
+    assert_eq!(line.line, 3);
+    assert_eq!(line.text.as_slice(), "sub");
+    assert_eq!(program.source_map.lookup(0x202).unwrap().text.as_slice(), "v0 := 5 # five");
+    assert!(program.source_map.lookup(0x200).is_none());
+
+    // maps survive a round trip through their text form
+    let mut w = MemWriter::new();
+    program.source_map.write(&mut w).unwrap();
+    let text = String::from_utf8(w.into_inner()).unwrap();
+    assert_eq!(SourceMap::parse(text.as_slice()), Ok(program.source_map));
+}
@@ -0,0 +1,481 @@
+use std::iter::range_step;
+use std::num;
+use assembler::SourceMap;
+use frontend::Frontend;
+use super::{Chip8, RunExit, REGISTER_COUNT, STACK_SIZE};
+use super::{AccessKind, Comparison, Location, Watchpoint, WatchKind};
+
+static HELP : &'static str = "\
+step [n]          execute n instructions (default 1)
+continue          run until a breakpoint or watchpoint, or the pause key is pressed
+break <addr>      set a breakpoint
+delete [addr]     delete a breakpoint, or all breakpoints and watchpoints
+watch <loc>       stop after <loc> is written, where <loc> is an address,
+                  v0-vF, i, dt or st
+watch <loc> <op> <val>
+                  stop once a write leaves the condition true, <op> is one
+                  of == != < <= > >=
+rwatch <loc>      stop after <loc> is read
+awatch <loc>      stop after <loc> is read or written
+unwatch <loc>     delete the watchpoints on <loc>
+watch             list watchpoints
+reverse-step [n]  go back n instructions (default 1)
+reverse-continue [loc]
+                  go back to the last breakpoint or watchpoint hit, or to
+                  the last instruction that wrote <loc>
+regs              show pc, i, sp, v0-vF and the timers
+stack             show the call stack
+mem <addr> <len>  dump memory
+set <reg> <val>   set v0-vF, i, pc, sp, dt or st
+quit              exit the emulator
+Numbers are decimal, or hex with a 0x prefix.";
+
+// Registers that can be modified with the set command
+#[derive(Copy, Show, PartialEq)]
+pub enum Register {
+    V(uint),
+    I,
+    Pc,
+    Sp,
+    DelayTimer,
+    SoundTimer
+}
+
+#[derive(Show, PartialEq)]
+pub enum Command {
+    Step(uint),
+    Continue,
+    ReverseStep(uint),
+    ReverseContinue(Option<Location>),
+    Break(u16),
+    Delete(Option<u16>),
+    Watch(Watchpoint),
+    Unwatch(Location),
+    Watches,
+    Regs,
+    Stack,
+    Mem(u16, uint),
+    Set(Register, u16),
+    Help,
+    Quit
+}
+
+// Whether the debugger should prompt for another command or exit
+#[derive(Show, PartialEq)]
+pub enum Flow {
+    Prompt,
+    Quit
+}
+
+// An interactive command line debugger driving a Chip8 through its frontend
+pub struct Debugger {
+    breakpoints : Vec<u16>,
+    watchpoints : Vec<Watchpoint>,
+    source_map  : Option<SourceMap>
+}
+
+impl Debugger {
+
+    pub fn new() -> Debugger {
+        Debugger { breakpoints: Vec::new(), watchpoints: Vec::new(), source_map: None }
+    }
+
+    // Read and execute commands from the frontend until quit or end of input
+    pub fn run(&mut self, emu: &mut Chip8, frontend: &mut Frontend) {
+        self.print_location(emu, frontend);
+
+        loop {
+            frontend.show_registers(Debugger::format_panel(emu));
+            let line = match frontend.read_line("(chip8) ") {
+                Some(line) => line,
+                None => return
+            };
+            if line.as_slice().trim().is_empty() {
+                continue;
+            }
+
+            match Debugger::parse_command(line.as_slice()) {
+                Ok(command) => match self.execute(emu, frontend, command) {
+                    Flow::Quit => return,
+                    Flow::Prompt => {}
+                },
+                Err(msg) => frontend.write_output(msg.as_slice())
+            }
+        }
+    }
+
+    // Emulation errors stop at the prompt, so the state that caused them can
+    // be inspected
+    pub fn execute(&mut self, emu: &mut Chip8, frontend: &mut Frontend, command: Command) -> Flow {
+        match command {
+            Command::Step(count) => {
+                emu.tracing = !self.watchpoints.is_empty();
+                for _ in range(0u, count) {
+                    match emu.clocked_step() {
+                        Ok(_) => {},
+                        Err(err) => {
+                            frontend.write_output(format!("emulation halted: {}", err).as_slice());
+                            break;
+                        }
+                    }
+                    match emu.triggered_watchpoint(self.watchpoints.as_slice()) {
+                        Some(wp) => {
+                            frontend.write_output(Debugger::format_watch_hit(emu, &wp).as_slice());
+                            break;
+                        },
+                        None => {}
+                    }
+                }
+                emu.present(frontend);
+                self.print_location(emu, frontend);
+            },
+            Command::Continue => {
+                // get off the breakpoint we're stopped at before running
+                emu.tracing = !self.watchpoints.is_empty();
+                let exit = match emu.clocked_step() {
+                    Ok(_) => match emu.triggered_watchpoint(self.watchpoints.as_slice()) {
+                        Some(wp) => Ok(RunExit::Watchpoint(wp)),
+                        None => emu.run_realtime(frontend, self.breakpoints.as_slice(),
+                                                 self.watchpoints.as_slice(), true)
+                    },
+                    Err(err) => Err(err)
+                };
+                match exit {
+                    Ok(RunExit::Quit) => return Flow::Quit,
+                    Ok(RunExit::Exited) => {
+                        frontend.write_output("program exited");
+                        return Flow::Quit;
+                    },
+                    Ok(RunExit::Breakpoint) =>
+                        frontend.write_output(format!("breakpoint at {:04X}", emu.pc).as_slice()),
+                    Ok(RunExit::Watchpoint(wp)) =>
+                        frontend.write_output(Debugger::format_watch_hit(emu, &wp).as_slice()),
+                    Ok(RunExit::Interrupted) => frontend.write_output("interrupted"),
+                    Err(err) => {
+                        emu.present(frontend);
+                        frontend.write_output(format!("emulation halted: {}", err).as_slice())
+                    }
+                }
+                self.print_location(emu, frontend);
+            },
+            // replaying would record frames twice, or skip ahead of a movie
+            // being played back
+            Command::ReverseStep(_) | Command::ReverseContinue(_) if emu.movie_active() => {
+                frontend.write_output("can't reverse while a movie is recording or playing");
+            },
+            Command::ReverseStep(count) => {
+                if !emu.reverse_step(count as u64) {
+                    frontend.write_output("not enough rewind history");
+                }
+                emu.present(frontend);
+                self.print_location(emu, frontend);
+            },
+            Command::ReverseContinue(location) => {
+                if !self.reverse_continue(emu, location) {
+                    frontend.write_output("no earlier match in the rewind history");
+                }
+                emu.present(frontend);
+                self.print_location(emu, frontend);
+            },
+            Command::Break(addr) => {
+                if !self.breakpoints.contains(&addr) {
+                    self.breakpoints.push(addr);
+                }
+                frontend.write_output(format!("breakpoint set at {:04X}", addr).as_slice());
+            },
+            Command::Delete(Some(addr)) => {
+                self.breakpoints.retain(|bp| *bp != addr);
+            },
+            Command::Delete(None) => {
+                self.breakpoints.clear();
+                self.watchpoints.clear();
+            },
+            Command::Watch(wp) => {
+                if !self.watchpoints.contains(&wp) {
+                    self.watchpoints.push(wp);
+                }
+                frontend.write_output(format!("watchpoint set: {}", Debugger::format_watchpoint(&wp)).as_slice());
+            },
+            Command::Unwatch(location) => {
+                self.watchpoints.retain(|wp| wp.location != location);
+            },
+            Command::Watches => {
+                if self.watchpoints.is_empty() {
+                    frontend.write_output("no watchpoints");
+                }
+                for (idx, wp) in self.watchpoints.iter().enumerate() {
+                    frontend.write_output(format!("#{}: {}", idx, Debugger::format_watchpoint(wp)).as_slice());
+                }
+            },
+            Command::Regs => frontend.write_output(Debugger::format_regs(emu).as_slice()),
+            Command::Stack => frontend.write_output(Debugger::format_stack(emu).as_slice()),
+            Command::Mem(addr, len) => frontend.write_output(Debugger::format_mem(emu, addr, len).as_slice()),
+            Command::Set(reg, val) => Debugger::set_register(emu, reg, val),
+            Command::Help => frontend.write_output(HELP),
+            Command::Quit => return Flow::Quit
+        }
+        Flow::Prompt
+    }
+
+    // Run backwards to just before the last instruction that wrote location,
+    // or without one, the last breakpoint reached or watchpoint triggered
+    fn reverse_continue(&self, emu: &mut Chip8, location: Option<Location>) -> bool {
+        let tracing = emu.tracing;
+        emu.tracing = true;
+        let found = emu.reverse_until(|&mut: emu: &Chip8, pc: Option<u16>| {
+            match (pc, location) {
+                (Some(pc), None) => self.breakpoints.contains(&pc),
+                (None, None) => emu.triggered_watchpoint(self.watchpoints.as_slice()).is_some(),
+                (None, Some(location)) => emu.accesses.iter().any(|access| {
+                    access.location == location && access.kind == AccessKind::Write
+                }),
+                (Some(_), Some(_)) => false
+            }
+        });
+        emu.tracing = tracing;
+        found
+    }
+
+    // Show source lines from an assembled program alongside its instructions
+    pub fn set_source_map(&mut self, map: SourceMap) {
+        self.source_map = Some(map);
+    }
+
+    fn print_location(&self, emu: &Chip8, frontend: &mut Frontend) {
+        frontend.write_output(Debugger::format_location(emu).as_slice());
+        match self.format_source(emu.pc) {
+            Some(source) => frontend.write_output(format!("    {}", source).as_slice()),
+            None => {}
+        }
+    }
+
+    // The source line the instruction at addr was assembled from
+    pub fn format_source(&self, addr: u16) -> Option<String> {
+        match self.source_map {
+            Some(ref map) => map.lookup(addr).map(|l| format!("line {}: {}", l.line, l.text)),
+            None => None
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[u16] {
+        self.breakpoints.as_slice()
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        self.watchpoints.as_slice()
+    }
+
+    pub fn parse_command(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.words().collect();
+        if words.is_empty() {
+            return Err("no command given".to_string());
+        }
+        let args = words.slice_from(1);
+
+        match (words[0], args.len()) {
+            ("step", 0) | ("s", 0) => Ok(Command::Step(1)),
+            ("step", 1) | ("s", 1) => Ok(Command::Step(try!(Debugger::parse_number(args[0])) as uint)),
+            ("continue", 0) | ("c", 0) => Ok(Command::Continue),
+            ("reverse-step", 0) | ("rs", 0) => Ok(Command::ReverseStep(1)),
+            ("reverse-step", 1) | ("rs", 1) => Ok(Command::ReverseStep(try!(Debugger::parse_number(args[0])) as uint)),
+            ("reverse-continue", 0) | ("rc", 0) => Ok(Command::ReverseContinue(None)),
+            ("reverse-continue", 1) | ("rc", 1) =>
+                Ok(Command::ReverseContinue(Some(try!(Debugger::parse_location(args[0]))))),
+            ("break", 1) | ("b", 1) => Ok(Command::Break(try!(Debugger::parse_number(args[0])))),
+            ("delete", 0) | ("d", 0) => Ok(Command::Delete(None)),
+            ("delete", 1) | ("d", 1) => Ok(Command::Delete(Some(try!(Debugger::parse_number(args[0]))))),
+            ("watch", 0) | ("w", 0) => Ok(Command::Watches),
+            ("watch", 1) | ("w", 1) => Debugger::parse_watch(args[0], WatchKind::Write),
+            ("watch", 3) | ("w", 3) => Ok(Command::Watch(Watchpoint {
+                location  : try!(Debugger::parse_location(args[0])),
+                kind      : WatchKind::Write,
+                condition : Some((try!(Debugger::parse_comparison(args[1])),
+                                  try!(Debugger::parse_number(args[2]))))
+            })),
+            ("rwatch", 1) => Debugger::parse_watch(args[0], WatchKind::Read),
+            ("awatch", 1) => Debugger::parse_watch(args[0], WatchKind::ReadWrite),
+            ("unwatch", 1) => Ok(Command::Unwatch(try!(Debugger::parse_location(args[0])))),
+            ("regs", 0) | ("r", 0) => Ok(Command::Regs),
+            ("stack", 0) => Ok(Command::Stack),
+            ("mem", 2) | ("m", 2) => Ok(Command::Mem(try!(Debugger::parse_number(args[0])),
+                                                     try!(Debugger::parse_number(args[1])) as uint)),
+            ("set", 2) => {
+                let reg = try!(Debugger::parse_register(args[0]));
+                let val = try!(Debugger::parse_number(args[1]));
+                // the stack pointer can't be left pointing past the stack
+                if reg == Register::Sp && val as uint > STACK_SIZE {
+                    return Err("stack pointer out of range".to_string());
+                }
+                Ok(Command::Set(reg, val))
+            },
+            ("help", 0) | ("h", 0) => Ok(Command::Help),
+            ("quit", 0) | ("q", 0) => Ok(Command::Quit),
+            _ => Err(format!("invalid command: {}, try help", line.trim()))
+        }
+    }
+
+    fn parse_number(s: &str) -> Result<u16, String> {
+        let parsed = if s.starts_with("0x") {
+            num::from_str_radix::<u16>(s.slice_from(2), 16)
+        } else {
+            from_str::<u16>(s)
+        };
+        match parsed {
+            Some(n) => Ok(n),
+            None => Err(format!("invalid number: {}", s))
+        }
+    }
+
+    fn parse_register(s: &str) -> Result<Register, String> {
+        let name: String = s.chars().map(|c| c.to_lowercase()).collect();
+        match name.as_slice() {
+            "i"  => Ok(Register::I),
+            "pc" => Ok(Register::Pc),
+            "sp" => Ok(Register::Sp),
+            "dt" => Ok(Register::DelayTimer),
+            "st" => Ok(Register::SoundTimer),
+            _ if name.len() == 2 && name.starts_with("v") => {
+                match num::from_str_radix::<uint>(name.slice_from(1), 16) {
+                    Some(vi) if vi < REGISTER_COUNT => Ok(Register::V(vi)),
+                    _ => Err(format!("invalid register: {}", s))
+                }
+            },
+            _ => Err(format!("invalid register: {}", s))
+        }
+    }
+
+    fn parse_watch(s: &str, kind: WatchKind) -> Result<Command, String> {
+        Ok(Command::Watch(Watchpoint {
+            location  : try!(Debugger::parse_location(s)),
+            kind      : kind,
+            condition : None
+        }))
+    }
+
+    // A register name, or else a memory address
+    fn parse_location(s: &str) -> Result<Location, String> {
+        match Debugger::parse_register(s) {
+            Ok(Register::V(vi))       => Ok(Location::V(vi)),
+            Ok(Register::I)           => Ok(Location::I),
+            Ok(Register::DelayTimer)  => Ok(Location::DelayTimer),
+            Ok(Register::SoundTimer)  => Ok(Location::SoundTimer),
+            Ok(_) => Err(format!("can't watch {}", s)),
+            Err(_) => match Debugger::parse_number(s) {
+                Ok(addr) => Ok(Location::Mem(addr)),
+                Err(_) => Err(format!("invalid address or register: {}", s))
+            }
+        }
+    }
+
+    fn parse_comparison(s: &str) -> Result<Comparison, String> {
+        match s {
+            "==" => Ok(Comparison::Eq),
+            "!=" => Ok(Comparison::Ne),
+            "<"  => Ok(Comparison::Lt),
+            "<=" => Ok(Comparison::Le),
+            ">"  => Ok(Comparison::Gt),
+            ">=" => Ok(Comparison::Ge),
+            _ => Err(format!("invalid comparison: {}", s))
+        }
+    }
+
+    fn set_register(emu: &mut Chip8, reg: Register, val: u16) {
+        match reg {
+            Register::V(vi)       => emu.v[vi] = val as u8,
+            Register::I           => emu.i = val,
+            Register::Pc          => emu.pc = val,
+            Register::Sp          => emu.sp = val as u8,
+            Register::DelayTimer  => emu.delay_timer = val as u8,
+            Register::SoundTimer  => emu.sound_timer = val as u8
+        }
+    }
+
+    pub fn format_location(emu: &Chip8) -> String {
+        match emu.fetch_opcode() {
+            Ok(opcode) => format!("{:04X}: {:04X}", emu.pc, opcode),
+            Err(_) => format!("{:04X}: ????", emu.pc)
+        }
+    }
+
+    pub fn format_watchpoint(wp: &Watchpoint) -> String {
+        let location = Debugger::format_watch_location(wp.location);
+        match (wp.kind, wp.condition) {
+            (_, Some((cmp, val))) => format!("{} {} 0x{:X}", location, match cmp {
+                Comparison::Eq => "==", Comparison::Ne => "!=", Comparison::Lt => "<",
+                Comparison::Le => "<=", Comparison::Gt => ">", Comparison::Ge => ">="
+            }, val),
+            (WatchKind::Read, None) => format!("read {}", location),
+            (WatchKind::Write, None) => format!("write {}", location),
+            (WatchKind::ReadWrite, None) => format!("access {}", location)
+        }
+    }
+
+    fn format_watch_location(location: Location) -> String {
+        match location {
+            Location::Mem(addr)  => format!("[{:04X}]", addr),
+            Location::V(vi)      => format!("V{:X}", vi),
+            Location::I          => "I".to_string(),
+            Location::DelayTimer => "DT".to_string(),
+            Location::SoundTimer => "ST".to_string()
+        }
+    }
+
+    // Report a triggered watchpoint along with the location's new value
+    pub fn format_watch_hit(emu: &Chip8, wp: &Watchpoint) -> String {
+        format!("watchpoint {}: {} = 0x{:X}", Debugger::format_watchpoint(wp),
+                Debugger::format_watch_location(wp.location), emu.location_value(wp.location))
+    }
+
+    pub fn format_regs(emu: &Chip8) -> String {
+        let mut out = format!("PC: {:04X}  I: {:04X}  SP: {:X}  DT: {:02X}  ST: {:02X}",
+                              emu.pc, emu.i, emu.sp, emu.delay_timer, emu.sound_timer);
+        for vi in range(0u, REGISTER_COUNT) {
+            out.push_str(format!("{}V{:X}: {:02X}", if vi % 8 == 0 { "\n" } else { "  " },
+                                 vi, emu.v[vi]).as_slice());
+        }
+        out
+    }
+
+    // The registers as narrow lines, for frontends that show them to the
+    // side of the display
+    pub fn format_panel(emu: &Chip8) -> Vec<String> {
+        let mut lines = vec![format!("PC {:04X}  I {:04X}", emu.pc, emu.i),
+                             format!("SP {:X}  DT {:02X}  ST {:02X}", emu.sp, emu.delay_timer, emu.sound_timer)];
+        for vi in range_step(0u, REGISTER_COUNT, 4) {
+            lines.push(format!("V{:X} {:02X}  V{:X} {:02X}  V{:X} {:02X}  V{:X} {:02X}",
+                               vi, emu.v[vi], vi + 1, emu.v[vi + 1], vi + 2, emu.v[vi + 2], vi + 3, emu.v[vi + 3]));
+        }
+        lines
+    }
+
+    pub fn format_stack(emu: &Chip8) -> String {
+        if emu.sp == 0 {
+            return "stack is empty".to_string();
+        }
+        let mut out = String::new();
+        for si in range(0u, emu.sp as uint).rev() {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            out.push_str(format!("#{}: {:04X}", si, emu.stack[si]).as_slice());
+        }
+        out
+    }
+
+    // Hex dump of memory, 16 bytes to a line
+    pub fn format_mem(emu: &Chip8, addr: u16, len: uint) -> String {
+        let start = addr as uint;
+        let end = if start + len > emu.memory_size() { emu.memory_size() } else { start + len };
+        let mut out = String::new();
+        for lineaddr in range_step(start, end, 16) {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            out.push_str(format!("{:04X}:", lineaddr).as_slice());
+            for a in range(lineaddr, if lineaddr + 16 < end { lineaddr + 16 } else { end }) {
+                out.push_str(format!(" {:02X}", emu.mem[a]).as_slice());
+            }
+        }
+        out
+    }
+}
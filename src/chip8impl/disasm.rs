@@ -0,0 +1,147 @@
+use std::io::IoResult;
+use super::{Instruction, InstructionSet};
+
+// ROMs are disassembled at the address they're loaded at
+const LOAD_ADDRESS: u16 = 0x200;
+
+// Data bytes printed per line, the same width as an opcode
+const DATA_LINE_SIZE: uint = 2;
+
+// Locations found while tracing the paths through a ROM
+struct CodeMap {
+    // length of the instruction starting at each offset, 0 for data
+    lengths : Vec<uint>,
+    // offsets that are the target of a jump or call
+    labels  : Vec<bool>
+}
+
+// Write a listing of a ROM with the address, raw opcode and mnemonic of each
+// instruction. Code is found by following jumps, calls and skips from the
+// entry point, anything they never reach is listed as data.
+pub fn disassemble(rom: &[u8], set: InstructionSet, w: &mut Writer) -> IoResult<()> {
+    let map = trace(rom, set);
+
+    let mut offset = 0u;
+    while offset < rom.len() {
+        let addr = LOAD_ADDRESS as uint + offset;
+        if map.labels[offset] {
+            try!(writeln!(w, "L{:03X}:", addr));
+        }
+
+        if map.lengths[offset] > 0 {
+            let opcode = word(rom, offset);
+            let instr = Instruction::decode(opcode, set).unwrap();
+            if instr.len() == 4 {
+                let operand = word(rom, offset + 2);
+                try!(writeln!(w, "{:04X}: {:04X} {:04X}  {} 0x{:04X}", addr, opcode, operand, instr, operand));
+            } else {
+                try!(writeln!(w, "{:04X}: {:04X}       {}", addr, opcode, instr));
+            }
+            offset += map.lengths[offset];
+        } else {
+            // run of data up to the next instruction or label
+            let mut end = offset + 1;
+            while end < rom.len() && end - offset < DATA_LINE_SIZE && map.lengths[end] == 0 && !map.labels[end] {
+                end += 1;
+            }
+            let raw: Vec<String> = rom.slice(offset, end).iter().map(|b| format!("{:02X}", *b)).collect();
+            let bytes: Vec<String> = rom.slice(offset, end).iter().map(|b| format!("0x{:02X}", *b)).collect();
+            try!(writeln!(w, "{:04X}: {:<10} DB {}", addr, raw.concat(), bytes.connect(", ")));
+            offset = end;
+        }
+    }
+    Ok(())
+}
+
+// Follow every path of execution from the entry point
+fn trace(rom: &[u8], set: InstructionSet) -> CodeMap {
+    let mut map = CodeMap {
+        lengths : Vec::from_elem(rom.len(), 0u),
+        labels  : Vec::from_elem(rom.len(), false)
+    };
+    let mut pending = vec![LOAD_ADDRESS];
+
+    loop {
+        match pending.pop() {
+            Some(addr) => trace_from(rom, set, addr, &mut map, &mut pending),
+            None => return map
+        }
+    }
+}
+
+// Trace straight-line code from addr until it leaves the ROM, reaches code
+// that's already been traced, or can't continue past a jump or return
+fn trace_from(rom: &[u8], set: InstructionSet, start: u16, map: &mut CodeMap, pending: &mut Vec<u16>) {
+    let mut addr = start;
+    loop {
+        let offset = match rom_offset(rom, addr, 2) {
+            Some(offset) => offset,
+            None => return
+        };
+        if map.lengths[offset] > 0 {
+            return;
+        }
+        let instr = match Instruction::decode(word(rom, offset), set) {
+            Some(instr) => instr,
+            None => return
+        };
+        if rom_offset(rom, addr, instr.len() as uint).is_none() {
+            return;
+        }
+        map.lengths[offset] = instr.len() as uint;
+        let next = addr + instr.len();
+
+        match instr {
+            Instruction::Jump(dst) => {
+                add_label(rom, map, dst);
+                pending.push(dst);
+                return;
+            },
+            Instruction::Call(dst) => {
+                add_label(rom, map, dst);
+                pending.push(dst);
+            },
+            // the jump table itself is worth a label, but where it goes isn't known
+            Instruction::JumpV0(dst) => {
+                add_label(rom, map, dst);
+                return;
+            },
+            Instruction::Return | Instruction::Exit => return,
+            Instruction::SkipIfEq(..) | Instruction::SkipIfNeq(..) |
+            Instruction::SkipIfEqRegister(..) | Instruction::SkipIfNeqRegister(..) |
+            Instruction::SkipIfKeypress(..) | Instruction::SkipIfNKeypress(..) => {
+                pending.push(next + skipped_length(rom, set, next));
+            },
+            _ => {}
+        }
+        addr = next;
+    }
+}
+
+// Size of the instruction at addr that a skip jumps over
+fn skipped_length(rom: &[u8], set: InstructionSet, addr: u16) -> u16 {
+    match rom_offset(rom, addr, 2) {
+        Some(offset) if set == InstructionSet::XoChip && word(rom, offset) == 0xF000 => 4,
+        _ => 2
+    }
+}
+
+fn add_label(rom: &[u8], map: &mut CodeMap, addr: u16) {
+    match rom_offset(rom, addr, 1) {
+        Some(offset) => map.labels[offset] = true,
+        None => {}
+    }
+}
+
+// Offset into the ROM of len bytes at addr, if they're all inside it
+fn rom_offset(rom: &[u8], addr: u16, len: uint) -> Option<uint> {
+    if addr < LOAD_ADDRESS || (addr - LOAD_ADDRESS) as uint + len > rom.len() {
+        None
+    } else {
+        Some((addr - LOAD_ADDRESS) as uint)
+    }
+}
+
+fn word(rom: &[u8], offset: uint) -> u16 {
+    ((rom[offset] as u16) << 8) | rom[offset + 1] as u16
+}
@@ -0,0 +1,225 @@
+use std::fmt;
+
+// The instruction sets understood by the decoder, each a superset of the last
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum InstructionSet {
+    Chip8,
+    SuperChip,
+    XoChip
+}
+
+impl InstructionSet {
+
+    // Accepts the quirk profile names, so one name selects both
+    pub fn from_name(name: &str) -> Option<InstructionSet> {
+        match name {
+            "vip" | "chip48" => Some(InstructionSet::Chip8),
+            "schip"          => Some(InstructionSet::SuperChip),
+            "xochip"         => Some(InstructionSet::XoChip),
+            _ => None
+        }
+    }
+}
+
+// A decoded instruction, register operands are indices into V
+#[derive(Copy, Clone, PartialEq)]
+pub enum Instruction {
+    ClearScreen,
+    Return,
+    ScrollDown(uint),
+    ScrollUp(uint),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    SetHires(bool),
+    Jump(u16),
+    Call(u16),
+    SkipIfEq(uint, u8),
+    SkipIfNeq(uint, u8),
+    SkipIfEqRegister(uint, uint),
+    StoreRange(uint, uint),
+    LoadRange(uint, uint),
+    SetRegisterConst(uint, u8),
+    AddRegister(uint, u8),
+    SetRegisterReg(uint, uint),
+    BitOr(uint, uint),
+    BitAnd(uint, uint),
+    BitXor(uint, uint),
+    Add(uint, uint),
+    Sub(uint, uint),
+    Shr(uint, uint),
+    SubInverse(uint, uint),
+    Shl(uint, uint),
+    SkipIfNeqRegister(uint, uint),
+    SetI(u16),
+    JumpV0(u16),
+    SetRandAnd(uint, u8),
+    Draw(uint, uint, u8),
+    SkipIfKeypress(uint),
+    SkipIfNKeypress(uint),
+    SetILong,
+    SelectPlanes(u8),
+    LoadPattern,
+    LoadDTimer(uint),
+    WaitKey(uint),
+    SetDTimer(uint),
+    SetSTimer(uint),
+    AddI(uint),
+    SetIFont(uint),
+    SetIBigFont(uint),
+    SetPitch(uint),
+    StoreBcd(uint),
+    StoreRegs(uint),
+    LoadRegs(uint),
+    StoreFlags(uint),
+    LoadFlags(uint)
+}
+
+impl Instruction {
+
+    // Decode an opcode, returning None if it isn't part of the instruction set
+    pub fn decode(opcode: u16, set: InstructionSet) -> Option<Instruction> {
+        let short = opcode & 0x0FFF;
+        let vx = ((opcode & 0x0F00) >> 8) as uint;
+        let vy = ((opcode & 0x00F0) >> 4) as uint;
+        let byte = (opcode & 0xFF) as u8;
+        let nibble = (opcode & 0xF) as u8;
+        let schip = set != InstructionSet::Chip8;
+        let xochip = set == InstructionSet::XoChip;
+
+        let instr = match opcode {
+            0x00E0 => Instruction::ClearScreen,
+            0x00EE => Instruction::Return,
+            0x00C0...0x00CF if schip => Instruction::ScrollDown(nibble as uint),
+            0x00D0...0x00DF if xochip => Instruction::ScrollUp(nibble as uint),
+            0x00FB if schip => Instruction::ScrollRight,
+            0x00FC if schip => Instruction::ScrollLeft,
+            0x00FD if schip => Instruction::Exit,
+            0x00FE if schip => Instruction::SetHires(false),
+            0x00FF if schip => Instruction::SetHires(true),
+            0xF000 if xochip => Instruction::SetILong,
+            _ => match (opcode & 0xF000) >> 12 {
+                0x1 => Instruction::Jump(short),
+                0x2 => Instruction::Call(short),
+                0x3 => Instruction::SkipIfEq(vx, byte),
+                0x4 => Instruction::SkipIfNeq(vx, byte),
+                0x5 => match opcode & 0xF {
+                    0x0 => Instruction::SkipIfEqRegister(vx, vy),
+                    0x2 if xochip => Instruction::StoreRange(vx, vy),
+                    0x3 if xochip => Instruction::LoadRange(vx, vy),
+                      _ => return None
+                },
+                0x6 => Instruction::SetRegisterConst(vx, byte),
+                0x7 => Instruction::AddRegister(vx, byte),
+                0x8 => match opcode & 0xF {
+                    0x0 => Instruction::SetRegisterReg(vx, vy),
+                    0x1 => Instruction::BitOr(vx, vy),
+                    0x2 => Instruction::BitAnd(vx, vy),
+                    0x3 => Instruction::BitXor(vx, vy),
+                    0x4 => Instruction::Add(vx, vy),
+                    0x5 => Instruction::Sub(vx, vy),
+                    0x6 => Instruction::Shr(vx, vy),
+                    0x7 => Instruction::SubInverse(vx, vy),
+                    0xE => Instruction::Shl(vx, vy),
+                      _ => return None
+                },
+                0x9 => Instruction::SkipIfNeqRegister(vx, vy),
+                0xA => Instruction::SetI(short),
+                0xB => Instruction::JumpV0(short),
+                0xC => Instruction::SetRandAnd(vx, byte),
+                0xD => Instruction::Draw(vx, vy, nibble),
+                0xE => match opcode & 0xFF {
+                    0x9E => Instruction::SkipIfKeypress(vx),
+                    0xA1 => Instruction::SkipIfNKeypress(vx),
+                       _ => return None
+                },
+                0xF => match opcode & 0xFF {
+                    0x01 if xochip => Instruction::SelectPlanes(vx as u8),
+                    0x02 if xochip => Instruction::LoadPattern,
+                    0x07 => Instruction::LoadDTimer(vx),
+                    0x0A => Instruction::WaitKey(vx),
+                    0x15 => Instruction::SetDTimer(vx),
+                    0x18 => Instruction::SetSTimer(vx),
+                    0x1E => Instruction::AddI(vx),
+                    0x29 => Instruction::SetIFont(vx),
+                    0x30 if schip => Instruction::SetIBigFont(vx),
+                    0x3A if xochip => Instruction::SetPitch(vx),
+                    0x33 => Instruction::StoreBcd(vx),
+                    0x55 => Instruction::StoreRegs(vx),
+                    0x65 => Instruction::LoadRegs(vx),
+                    0x75 if schip => Instruction::StoreFlags(vx),
+                    0x85 if schip => Instruction::LoadFlags(vx),
+                       _ => return None
+                },
+                _ => return None
+            }
+        };
+        Some(instr)
+    }
+
+    // Size in bytes, F000 NNNN is followed by its 16-bit operand
+    pub fn len(&self) -> u16 {
+        match *self {
+            Instruction::SetILong => 4,
+            _ => 2
+        }
+    }
+}
+
+// Mnemonics follow Cowgod's reference, with the usual names for the extensions
+impl fmt::Show for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::ClearScreen             => write!(f, "CLS"),
+            Instruction::Return                  => write!(f, "RET"),
+            Instruction::ScrollDown(n)           => write!(f, "SCD {}", n),
+            Instruction::ScrollUp(n)             => write!(f, "SCU {}", n),
+            Instruction::ScrollRight             => write!(f, "SCR"),
+            Instruction::ScrollLeft              => write!(f, "SCL"),
+            Instruction::Exit                    => write!(f, "EXIT"),
+            Instruction::SetHires(false)         => write!(f, "LOW"),
+            Instruction::SetHires(true)          => write!(f, "HIGH"),
+            Instruction::Jump(addr)              => write!(f, "JP 0x{:03X}", addr),
+            Instruction::Call(addr)              => write!(f, "CALL 0x{:03X}", addr),
+            Instruction::SkipIfEq(vx, val)       => write!(f, "SE V{:X}, 0x{:02X}", vx, val),
+            Instruction::SkipIfNeq(vx, val)      => write!(f, "SNE V{:X}, 0x{:02X}", vx, val),
+            Instruction::SkipIfEqRegister(vx, vy) => write!(f, "SE V{:X}, V{:X}", vx, vy),
+            Instruction::StoreRange(vx, vy)      => write!(f, "SAVE V{:X} - V{:X}", vx, vy),
+            Instruction::LoadRange(vx, vy)       => write!(f, "LOAD V{:X} - V{:X}", vx, vy),
+            Instruction::SetRegisterConst(vx, val) => write!(f, "LD V{:X}, 0x{:02X}", vx, val),
+            Instruction::AddRegister(vx, val)    => write!(f, "ADD V{:X}, 0x{:02X}", vx, val),
+            Instruction::SetRegisterReg(vx, vy)  => write!(f, "LD V{:X}, V{:X}", vx, vy),
+            Instruction::BitOr(vx, vy)           => write!(f, "OR V{:X}, V{:X}", vx, vy),
+            Instruction::BitAnd(vx, vy)          => write!(f, "AND V{:X}, V{:X}", vx, vy),
+            Instruction::BitXor(vx, vy)          => write!(f, "XOR V{:X}, V{:X}", vx, vy),
+            Instruction::Add(vx, vy)             => write!(f, "ADD V{:X}, V{:X}", vx, vy),
+            Instruction::Sub(vx, vy)             => write!(f, "SUB V{:X}, V{:X}", vx, vy),
+            Instruction::Shr(vx, vy)             => write!(f, "SHR V{:X}, V{:X}", vx, vy),
+            Instruction::SubInverse(vx, vy)      => write!(f, "SUBN V{:X}, V{:X}", vx, vy),
+            Instruction::Shl(vx, vy)             => write!(f, "SHL V{:X}, V{:X}", vx, vy),
+            Instruction::SkipIfNeqRegister(vx, vy) => write!(f, "SNE V{:X}, V{:X}", vx, vy),
+            Instruction::SetI(addr)              => write!(f, "LD I, 0x{:03X}", addr),
+            Instruction::JumpV0(addr)            => write!(f, "JP V0, 0x{:03X}", addr),
+            Instruction::SetRandAnd(vx, val)     => write!(f, "RND V{:X}, 0x{:02X}", vx, val),
+            Instruction::Draw(vx, vy, rows)      => write!(f, "DRW V{:X}, V{:X}, {}", vx, vy, rows),
+            Instruction::SkipIfKeypress(vx)      => write!(f, "SKP V{:X}", vx),
+            Instruction::SkipIfNKeypress(vx)     => write!(f, "SKNP V{:X}", vx),
+            Instruction::SetILong                => write!(f, "LD I, long"),
+            Instruction::SelectPlanes(planes)    => write!(f, "PLANE {}", planes),
+            Instruction::LoadPattern             => write!(f, "AUDIO"),
+            Instruction::LoadDTimer(vx)          => write!(f, "LD V{:X}, DT", vx),
+            Instruction::WaitKey(vx)             => write!(f, "LD V{:X}, K", vx),
+            Instruction::SetDTimer(vx)           => write!(f, "LD DT, V{:X}", vx),
+            Instruction::SetSTimer(vx)           => write!(f, "LD ST, V{:X}", vx),
+            Instruction::AddI(vx)                => write!(f, "ADD I, V{:X}", vx),
+            Instruction::SetIFont(vx)            => write!(f, "LD F, V{:X}", vx),
+            Instruction::SetIBigFont(vx)         => write!(f, "LD HF, V{:X}", vx),
+            Instruction::SetPitch(vx)            => write!(f, "PITCH V{:X}", vx),
+            Instruction::StoreBcd(vx)            => write!(f, "LD B, V{:X}", vx),
+            Instruction::StoreRegs(vx)           => write!(f, "LD [I], V{:X}", vx),
+            Instruction::LoadRegs(vx)            => write!(f, "LD V{:X}, [I]", vx),
+            Instruction::StoreFlags(vx)          => write!(f, "LD R, V{:X}", vx),
+            Instruction::LoadFlags(vx)           => write!(f, "LD V{:X}, R", vx)
+        }
+    }
+}
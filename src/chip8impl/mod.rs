@@ -1,28 +1,59 @@
 extern crate std;
-extern crate time;
-extern crate sdl;
 
 use std::default::Default;
+use std::fmt;
+use std::num::Float;
 use std::slice::bytes;
-use std::io::File;
-use std::rand;
+use std::io::{File, IoResult};
 use std::io::fs::PathExtensions;
-use sdl::video::Surface;
-use std::io::Timer;
-use std::time::Duration;
+use frontend::{Frontend, Hotkey, InputEvent};
+use self::movie::MovieMode;
+use self::random::Random;
+use self::rewind::RewindBuffer;
+
+pub use self::debugger::Debugger;
+pub use self::disasm::disassemble;
+pub use self::instruction::{Instruction, InstructionSet};
+pub use self::movie::{Movie, MovieFrame};
+pub use self::quirks::Quirks;
+pub use self::watch::{Access, AccessKind, Comparison, Location, Watchpoint, WatchKind};
+
+mod debugger;
+mod disasm;
+mod instruction;
+mod movie;
+mod quirks;
+mod random;
+mod rewind;
+mod state;
+mod watch;
 
 #[cfg(test)]
 mod tests;
 
 const STACK_SIZE:      uint = 16;
-const MEMORY_SIZE:     uint = 4096;
+const MEMORY_SIZE:     uint = 65536;
+// Memory below XO-CHIP, see Quirks::large_memory
+const SMALL_MEMORY_SIZE: uint = 4096;
 const REGISTER_COUNT:  uint = 16;
 const SCREEN_WIDTH:    uint = 64;
 const SCREEN_HEIGHT:   uint = 32;
+const HIRES_WIDTH:     uint = 128;
+const HIRES_HEIGHT:    uint = 64;
 const KEY_COUNT:       uint = 16;
 const FONTSET_SIZE:    uint = 80;
 const FONT_DIGIT_SIZE: u16  = 5;
-const PIXEL_COUNT:     uint = SCREEN_WIDTH * SCREEN_HEIGHT;
+const BIGFONT_SIZE:    uint = 100;
+const BIGFONT_DIGIT_SIZE: u16 = 10;
+const RPL_FLAG_COUNT:  uint = 16;
+const PATTERN_SIZE:    uint = 16;
+const PIXEL_COUNT:     uint = HIRES_WIDTH * HIRES_HEIGHT;
+
+// the delay and sound timers always count down at 60Hz, instructions are
+// scheduled in batches of cycles_per_frame per 60Hz frame
+pub const FRAME_RATE:    u64  = 60;
+const FRAME_DURATION_NS: u64  = 1000000000 / FRAME_RATE;
+const DEFAULT_CYCLES_PER_FRAME: uint = 10;
 
 static FONTSET : [u8; FONTSET_SIZE] =
     [0xF0, 0x90, 0x90, 0x90, 0xF0,  // 0
@@ -42,6 +73,75 @@ static FONTSET : [u8; FONTSET_SIZE] =
      0xF0, 0x80, 0xF0, 0x80, 0xF0,  // E
      0xF0, 0x80, 0xF0, 0x80, 0x80]; // F
 
+// SUPER-CHIP 8x10 digits, stored in memory straight after FONTSET
+static BIGFONT : [u8; BIGFONT_SIZE] =
+    [0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C,  // 0
+     0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C,  // 1
+     0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF,  // 2
+     0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C,  // 3
+     0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06,  // 4
+     0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C,  // 5
+     0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C,  // 6
+     0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60,  // 7
+     0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C,  // 8
+     0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C]; // 9
+
+// Errors raised while loading or executing a program, it's up to the host
+// whether these halt the machine or are just logged
+#[derive(PartialEq)]
+pub enum Chip8Error {
+    RomTooLarge,
+    RomNotFound,
+    UnknownOpcode { pc: u16, opcode: u16 },
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds { addr: uint },
+    InvalidKey
+}
+
+impl fmt::Show for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Chip8Error::RomTooLarge => write!(f, "ROM does not fit in memory"),
+            Chip8Error::RomNotFound => write!(f, "ROM file not found or unreadable"),
+            Chip8Error::UnknownOpcode { pc, opcode } =>
+                write!(f, "unknown opcode {:04X} at {:04X}", opcode, pc),
+            Chip8Error::StackOverflow => write!(f, "stack overflow"),
+            Chip8Error::StackUnderflow => write!(f, "stack underflow"),
+            Chip8Error::MemoryOutOfBounds { addr } =>
+                write!(f, "memory access out of bounds at {:X}", addr),
+            Chip8Error::InvalidKey => write!(f, "invalid key or hex digit value")
+        }
+    }
+}
+
+// Conditions that end a bounded run, checked before each instruction
+pub struct RunLimits {
+    pub cycles : uint,
+    pub pc     : Option<u16>,
+    pub opcode : Option<u16>
+}
+
+// Why the real-time run loop handed control back
+#[derive(Show, PartialEq)]
+pub enum RunExit {
+    Quit,
+    Exited,
+    Breakpoint,
+    Watchpoint(Watchpoint),
+    Interrupted
+}
+
+// Why a bounded run came to an end
+#[derive(Show, PartialEq)]
+pub enum StopReason {
+    CycleLimit,
+    ReachedPc,
+    ReachedOpcode,
+    Exited,
+    MovieEnded
+}
+
 pub struct Chip8 {
     pc          : u16,
     i           : u16,
@@ -53,7 +153,30 @@ pub struct Chip8 {
     gfx         : [u8; PIXEL_COUNT],
     stack       : [u16; STACK_SIZE],
     key         : [u8; KEY_COUNT],
-    gfx_update  : bool
+    gfx_update  : bool,
+    waitkey_reg : Option<uint>,
+    waitkey_held : Option<u8>,
+    hires       : bool,
+    halted      : bool,
+    rpl         : [u8; RPL_FLAG_COUNT],
+    planes      : u8,
+    pattern     : [u8; PATTERN_SIZE],
+    pitch       : u8,
+    pattern_update : bool,
+    cycles_per_frame : uint,
+    frame_cycles     : uint,
+    quirks           : Quirks,
+    tracing          : bool,
+    accesses         : Vec<Access>,
+    key_write        : Option<uint>,
+    state_path       : Option<Path>,
+    state_slot       : uint,
+    rewind           : RewindBuffer,
+    cycle_count      : u64,
+    rng              : Random,
+    movie            : MovieMode,
+    movie_finished   : bool,
+    latched_keys     : Vec<(u8, bool)>
 }
 
 impl Default for Chip8 {
@@ -69,7 +192,30 @@ impl Default for Chip8 {
             gfx         : [0; PIXEL_COUNT],
             stack       : [0; STACK_SIZE],
             key         : [0; KEY_COUNT],
-            gfx_update  : false
+            gfx_update  : false,
+            waitkey_reg : None,
+            waitkey_held : None,
+            hires       : false,
+            halted      : false,
+            rpl         : [0; RPL_FLAG_COUNT],
+            planes      : 1,
+            pattern     : [0; PATTERN_SIZE],
+            pitch       : 64,
+            pattern_update : false,
+            cycles_per_frame : DEFAULT_CYCLES_PER_FRAME,
+            frame_cycles     : 0,
+            quirks           : Default::default(),
+            tracing          : false,
+            accesses         : Vec::new(),
+            key_write        : None,
+            state_path       : None,
+            state_slot       : 0,
+            rewind           : RewindBuffer::new(0),
+            cycle_count      : 0,
+            rng              : Random::new(0),
+            movie            : MovieMode::Off,
+            movie_finished   : false,
+            latched_keys     : Vec::new()
         }
     }
 }
@@ -80,159 +226,467 @@ impl Chip8 {
     pub fn new() -> Chip8 {
         let mut rv = Chip8 { ..Default::default() };
         rv.mem.clone_from_slice(&FONTSET);
+        rv.mem.slice_mut(FONTSET_SIZE, FONTSET_SIZE + BIGFONT_SIZE).clone_from_slice(&BIGFONT);
         return rv;
     }
 
-    pub fn load_program(& mut self, filename: &String) -> bool {
+    pub fn load_program(& mut self, filename: &String) -> Result<(), Chip8Error> {
         let path = Path::new(filename.as_slice());
         if !path.exists() {
-            println!("program file {} does not exist", filename);
-            return false;
+            return Err(Chip8Error::RomNotFound);
         }
 
         let mut file = File::open(&path);
         return match file.read_to_end() {
             Ok(data) => {
-                if data.len() > (0xFFF - 0x200) {
-                    return false;
+                if data.len() > (self.memory_size() - 0x200) {
+                    return Err(Chip8Error::RomTooLarge);
                 }
                 let dst = self.mem.slice_mut(0x200, 0x200 + data.len());
                 bytes::copy_memory(dst, data.as_slice());
-                true
+                Ok(())
             },
-            _ => { false }
+            _ => { Err(Chip8Error::RomNotFound) }
         };
     }
 
-    pub fn run(& mut self, screen: &mut sdl::video::Surface ) {
-        let mut timer = Timer::new().unwrap();
-        let target_cycle_duration_ms = (1000.0f64 / 120.0f64) as u64;
-
-        'mainloop : loop {
-            let cycle_start = time::precise_time_ns();
+    // Run until the frontend asks to quit, the program exits or faults
+    pub fn run(& mut self, frontend: &mut Frontend) -> Result<(), Chip8Error> {
+        try!(self.run_realtime(frontend, &[], &[], false));
+        Ok(())
+    }
 
-            let opcode = self.fetch_opcode();
-            self.decode_and_execute(opcode);
+    // Run as fast as possible until one of the limits is hit, returning the
+    // number of cycles executed and what stopped the run. Input isn't polled,
+    // but completed frames are still handed to the frontend.
+    pub fn run_until(& mut self, limits: &RunLimits, frontend: &mut Frontend)
+                     -> Result<(uint, StopReason), Chip8Error> {
+        let mut cycles = 0u;
+        loop {
+            if cycles >= limits.cycles {
+                return Ok((cycles, StopReason::CycleLimit));
+            }
+            if self.halted {
+                return Ok((cycles, StopReason::Exited));
+            }
+            if self.frame_cycles == 0 && self.movie_ended() {
+                return Ok((cycles, StopReason::MovieEnded));
+            }
+            if limits.pc == Some(self.pc) {
+                return Ok((cycles, StopReason::ReachedPc));
+            }
+            if limits.opcode.is_some() && limits.opcode == self.fetch_opcode().ok() {
+                return Ok((cycles, StopReason::ReachedOpcode));
+            }
 
-            if self.delay_timer > 0 {
-                self.delay_timer -= 1;
+            if try!(self.clocked_step()) {
+                self.end_frame(frontend);
             }
-            if self.sound_timer > 0 {
-                if self.sound_timer == 1 {
-                    // TODO emit beep
-                }
-                self.sound_timer -= 1;
+            cycles += 1;
+        }
+    }
+
+    // Write the register file and a text rendering of the graphics buffer
+    pub fn dump_state(&self, w: &mut Writer) -> IoResult<()> {
+        try!(writeln!(w, "PC: 0x{:04X}  I: 0x{:04X}  SP: 0x{:X}  DT: 0x{:02X}  ST: 0x{:02X}",
+                      self.pc, self.i, self.sp, self.delay_timer, self.sound_timer));
+
+        for vi in range(0u, REGISTER_COUNT) {
+            try!(write!(w, "V{:X}: 0x{:02X}{}", vi, self.v[vi],
+                        if vi % 8 == 7 { "\n" } else { "  " }));
+        }
+
+        try!(write!(w, "Stack:"));
+        for si in range(0u, self.sp as uint) {
+            try!(write!(w, " 0x{:04X}", self.stack[si]));
+        }
+        try!(writeln!(w, ""));
+
+        let (width, height) = self.screen_size();
+        for row in range(0u, height) {
+            for col in range(0u, width) {
+                try!(write!(w, "{}", match self.gfx[col + row * width] { 0 => '.', 1 => '#', 2 => '+', _ => '@' }));
             }
+            try!(writeln!(w, ""));
+        }
+        Ok(())
+    }
+
+    // Fetch, decode and execute a single instruction, timers are left alone
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        // a key completing FX0A writes Vx between instructions, so that's
+        // reported along with the next step
+        self.accesses.clear();
+        match self.key_write.take() {
+            Some(vx) => self.watch(Location::V(vx), AccessKind::Write),
+            None => {}
+        }
+
+        // while blocked on FX0A the CPU stalls until a key arrives, and
+        // once the program has exited with 00FD nothing more is executed
+        if self.waitkey_reg.is_none() && !self.halted {
+            let opcode = try!(self.fetch_opcode());
+            try!(self.decode_and_execute(opcode));
+        }
+        Ok(())
+    }
+
+    // Execute count instructions, stopping early on a fault
+    pub fn run_cycles(&mut self, count: uint) -> Result<(), Chip8Error> {
+        for _ in range(0u, count) {
+            try!(self.step());
+        }
+        Ok(())
+    }
+
+    // Execute one 60Hz frame worth of instructions, then tick the timers
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        try!(self.run_frame_until(&[], &[]));
+        Ok(())
+    }
 
-            if self.gfx_update {
-                self.draw_screen(screen);
-                screen.flip();
-                self.gfx_update = false;
+    // Set how many instructions are executed per 60Hz frame
+    pub fn set_cycles_per_frame(&mut self, count: uint) {
+        self.cycles_per_frame = if count > 0 { count } else { 1 };
+    }
+
+    // Set the CPU speed in instructions per second, rounded to whole frames
+    pub fn set_instructions_per_second(&mut self, ips: uint) {
+        let count = (ips + FRAME_RATE as uint / 2) / FRAME_RATE as uint;
+        self.set_cycles_per_frame(count);
+    }
+
+    pub fn instructions_per_second(&self) -> uint {
+        self.cycles_per_frame * FRAME_RATE as uint
+    }
+
+    // Seed the random numbers returned by CXNN, the same seed always gives
+    // the same sequence
+    pub fn set_seed(&mut self, seed: u32) {
+        self.rng = Random::new(seed);
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    // Decrement the delay and sound timers, call this at 60Hz
+    pub fn tick_timers(&mut self) {
+        if self.delay_timer > 0 {
+            self.watch(Location::DelayTimer, AccessKind::Write);
+            self.delay_timer -= 1;
+        }
+        if self.sound_timer > 0 {
+            self.watch(Location::SoundTimer, AccessKind::Write);
+            self.sound_timer -= 1;
+        }
+    }
+
+    // Update the state of a keypad key (0x0 - 0xF)
+    pub fn handle_keypress(&mut self, key: u8, pressed: bool) {
+        // a movie only sees input between frames, so that's when it arrives
+        if self.movie_active() && self.frame_cycles != 0 {
+            self.latched_keys.push((key, pressed));
+            return;
+        }
+        self.key[key as uint] = if pressed { 1u8 } else { 0u8 };
+
+        // complete a pending FX0A, either as soon as a key goes down or once
+        // the first key pressed while waiting comes back up
+        if self.waitkey_reg.is_none() {
+            return;
+        }
+        let completed = if !self.quirks.waitkey_on_release {
+            pressed
+        } else if pressed {
+            if self.waitkey_held.is_none() {
+                self.waitkey_held = Some(key);
             }
+            false
+        } else {
+            self.waitkey_held == Some(key)
+        };
+        if completed {
+            let vx = self.waitkey_reg.take().unwrap();
+            self.waitkey_held = None;
+            self.key_write = Some(vx);
+            self.v[vx] = key;
+            self.record_waitkey(key);
+        }
+    }
+
+    // The graphics buffer at the current resolution, one byte per pixel. Each
+    // pixel holds a bit for each of the two XO-CHIP planes, so 0 - 3.
+    pub fn display(&self) -> &[u8] {
+        let (width, height) = self.screen_size();
+        self.gfx.slice_to(width * height)
+    }
 
+    // Width and height of the display, 64x32 or 128x64 in SUPER-CHIP hires mode
+    pub fn screen_size(&self) -> (uint, uint) {
+        if self.hires { (HIRES_WIDTH, HIRES_HEIGHT) } else { (SCREEN_WIDTH, SCREEN_HEIGHT) }
+    }
+
+    // True once the program has exited with 00FD
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // True while the sound timer is running and a tone should be played
+    pub fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+}
+
+// Chip8 internals
+impl Chip8 {
+
+    // The real-time run loop, executing frames at 60Hz while handling input
+    // and hotkeys. When debugging, execution stops before reaching any of the
+    // breakpoint addresses or after triggering a watchpoint, and the pause key
+    // drops back to the debugger.
+    fn run_realtime(& mut self, frontend: &mut Frontend, breakpoints: &[u16],
+                    watchpoints: &[Watchpoint], debugging: bool) -> Result<RunExit, Chip8Error> {
+        let mut next_frame = frontend.time_ns();
+        let mut paused = false;
+        let mut fast_forward = false;
+        let mut rewinding = false;
+
+        loop {
             'eventloop : loop {
-                match sdl::event::poll_event() {
-                    sdl::event::Event::Quit => break 'mainloop,
-                    sdl::event::Event::None => break 'eventloop,
-                    sdl::event::Event::Key(k, pressed, _, _) =>
-                        match k {
-                            sdl::event::Key::Escape => break 'mainloop,
-                            _ => self.handle_keypress(k, pressed),
+                match frontend.poll_input() {
+                    InputEvent::Quit => return Ok(RunExit::Quit),
+                    InputEvent::None => break 'eventloop,
+                    // a movie being played supplies all the input
+                    InputEvent::Key(k, pressed) => if !self.movie_playing() {
+                        self.handle_keypress(k, pressed)
+                    },
+                    // and anything that would leave it out of step with the
+                    // frames is locked out
+                    InputEvent::Hotkey(Hotkey::SpeedUp) | InputEvent::Hotkey(Hotkey::SpeedDown) |
+                    InputEvent::Hotkey(Hotkey::Rewind(_)) | InputEvent::Hotkey(Hotkey::LoadState)
+                        if self.movie_active() => {},
+                    InputEvent::Hotkey(hotkey) => match hotkey {
+                        Hotkey::SpeedUp => self.adjust_speed(true, frontend),
+                        Hotkey::SpeedDown => self.adjust_speed(false, frontend),
+                        Hotkey::Pause if debugging => {
+                            frontend.set_beep(false);
+                            return Ok(RunExit::Interrupted);
                         },
-                    _ => {}
+                        Hotkey::Pause => {
+                            paused = !paused;
+                            frontend.show_status(if paused { "paused" } else { "resumed" });
+                        },
+                        Hotkey::FastForward(held) => fast_forward = held,
+                        Hotkey::Rewind(held) => rewinding = held,
+                        Hotkey::SaveState => self.save_slot(frontend),
+                        Hotkey::LoadState => self.load_slot(frontend),
+                        Hotkey::NextSlot => self.select_slot(true, frontend),
+                        Hotkey::PrevSlot => self.select_slot(false, frontend)
+                    }
+                }
+            }
+
+            if paused {
+                frontend.set_beep(false);
+            } else if rewinding {
+                frontend.set_beep(false);
+                self.rewind_frame();
+                self.present(frontend);
+            } else {
+                match try!(self.run_frame_until(breakpoints, watchpoints)) {
+                    Some(exit) => {
+                        frontend.set_beep(false);
+                        self.present(frontend);
+                        return Ok(exit);
+                    },
+                    None => self.end_frame(frontend)
                 }
             }
 
-            let cycle_duration_ms = (time::precise_time_ns() - cycle_start) / 1000000;
-            if cycle_duration_ms < target_cycle_duration_ms {
-                timer.sleep(Duration::milliseconds((target_cycle_duration_ms - cycle_duration_ms) as i64));
+            if self.halted {
+                return Ok(RunExit::Exited);
+            }
+
+            // schedule against absolute frame deadlines so sleep rounding
+            // doesn't accumulate, but don't try to catch up after a stall.
+            // Fast forward runs frames back to back with no limiter at all.
+            next_frame += FRAME_DURATION_NS;
+            let now = frontend.time_ns();
+            if fast_forward {
+                next_frame = now;
+            } else if now < next_frame {
+                frontend.sleep_ms((next_frame - now) / 1000000);
+            } else if now - next_frame > FRAME_DURATION_NS {
+                next_frame = now;
             }
         }
     }
-}
 
-// Chip8 internals
-impl Chip8 {
+    // Execute the rest of the current frame, returning why it was cut short
+    // if a breakpoint was reached or a watchpoint triggered
+    fn run_frame_until(&mut self, breakpoints: &[u16], watchpoints: &[Watchpoint])
+                       -> Result<Option<RunExit>, Chip8Error> {
+        self.tracing = !watchpoints.is_empty();
+        loop {
+            if self.waitkey_reg.is_none() && breakpoints.contains(&self.pc) {
+                return Ok(Some(RunExit::Breakpoint));
+            }
+            let frame_done = try!(self.clocked_step());
+            match self.triggered_watchpoint(watchpoints) {
+                Some(wp) => return Ok(Some(RunExit::Watchpoint(wp))),
+                None => {}
+            }
+            if frame_done {
+                return Ok(None);
+            }
+        }
+    }
 
-    fn map_key(key: sdl::event::Key) -> Option<u8> {
-        /*
-        Keypad                   Keyboard
-        +-+-+-+-+                +-+-+-+-+
-        |1|2|3|C|                |1|2|3|4|
-        +-+-+-+-+                +-+-+-+-+
-        |4|5|6|D|                |Q|W|E|R|
-        +-+-+-+-+       =>       +-+-+-+-+
-        |7|8|9|E|                |A|S|D|F|
-        +-+-+-+-+                +-+-+-+-+
-        |A|0|B|F|                |Z|X|C|V|
-        +-+-+-+-+                +-+-+-+-+
-        */
-
-        return match key {
-            sdl::event::Key::Num1 => Some(0x1),
-            sdl::event::Key::Num2 => Some(0x2),
-            sdl::event::Key::Num3 => Some(0x3),
-            sdl::event::Key::Num4 => Some(0xC),
-            sdl::event::Key::Q    => Some(0x4),
-            sdl::event::Key::W    => Some(0x5),
-            sdl::event::Key::E    => Some(0x6),
-            sdl::event::Key::R    => Some(0xD),
-            sdl::event::Key::A    => Some(0x7),
-            sdl::event::Key::S    => Some(0x8),
-            sdl::event::Key::D    => Some(0x9),
-            sdl::event::Key::F    => Some(0xE),
-            sdl::event::Key::Z    => Some(0xA),
-            sdl::event::Key::X    => Some(0x0),
-            sdl::event::Key::C    => Some(0xB),
-            sdl::event::Key::V    => Some(0xF),
-            _ => None
-        }
-    }
-
-    fn handle_keypress(&mut self, key: sdl::event::Key, pressed: bool) {
-        match Chip8::map_key(key) {
-            Some(k) => self.key[k as uint] = if pressed { 1u8 } else { 0u8 },
-            None => {}
+    // The first watchpoint triggered by the instruction last executed
+    fn triggered_watchpoint(&self, watchpoints: &[Watchpoint]) -> Option<Watchpoint> {
+        watchpoints.iter().find(|wp| wp.triggered(self)).map(|wp| *wp)
+    }
+
+    // Hook through which instructions report the state they touch, recorded
+    // only while watchpoints are active
+    fn watch(& mut self, location: Location, kind: AccessKind) {
+        if self.tracing {
+            self.accesses.push(Access { location: location, kind: kind });
+        }
+    }
+
+    fn watch_mem(& mut self, addr: uint, len: uint, kind: AccessKind) {
+        for a in range(addr, addr + len) {
+            self.watch(Location::Mem(a as u16), kind);
         }
     }
 
-    fn draw_screen(&mut self, screen: &mut sdl::video::Surface) {
-        let pixelsize = 8 as u16;
-        let white = sdl::video::RGB(0xFF, 0xFF, 0xFF);
-        let black = sdl::video::RGB(0, 0, 0);
+    // Report an access to each of V0 through Vx
+    fn watch_regs(& mut self, vx: uint, kind: AccessKind) {
+        for vi in range(0u, vx + 1) {
+            self.watch(Location::V(vi), kind);
+        }
+    }
 
-        for row in range(0u, SCREEN_HEIGHT) {
-            for col in range(0u, SCREEN_WIDTH) {
-                let color = if self.gfx[col + row * SCREEN_WIDTH] == 1 { white } else { black };
-                screen.fill_rect(Some(sdl::Rect {
-                    x: (col as i16) * (pixelsize as i16),
-                    y: (row as i16) * (pixelsize as i16),
-                    w: pixelsize,
-                    h: pixelsize
-                }), color);
-            }
+    // Current value of a watchable location
+    fn location_value(&self, location: Location) -> u16 {
+        match location {
+            Location::Mem(addr)  => self.mem[addr as uint] as u16,
+            Location::V(vi)      => self.v[vi] as u16,
+            Location::I          => self.i,
+            Location::DelayTimer => self.delay_timer as u16,
+            Location::SoundTimer => self.sound_timer as u16
+        }
+    }
+
+    // Hand the sound state and any display changes of a frame to the frontend
+    fn end_frame(&mut self, frontend: &mut Frontend) {
+        if self.pattern_update {
+            frontend.set_pattern(&self.pattern, self.pattern_rate());
+            self.pattern_update = false;
+        }
+        frontend.set_beep(self.sound_active());
+        self.present(frontend);
+        frontend.end_frame();
+    }
+
+    fn present(&mut self, frontend: &mut Frontend) {
+        if self.gfx_update {
+            let (width, height) = self.screen_size();
+            frontend.present(self.display(), width, height);
+            self.gfx_update = false;
+        }
+        // movies run out in the middle of stepping, where there's no frontend
+        // to say so
+        if self.movie_finished {
+            frontend.show_status("movie finished");
+            self.movie_finished = false;
         }
     }
 
-    fn fetch_opcode(&self) -> u16 {
-        ( ( self.mem[ self.pc as uint ] as u16 ) << 8 ) |
-        self.mem[ self.pc as uint + 1 ] as u16
+    // Step the CPU speed up or down by roughly 25%
+    fn adjust_speed(&mut self, faster: bool, frontend: &mut Frontend) {
+        let cpf = self.cycles_per_frame;
+        let delta = std::cmp::max(1, cpf / 4);
+        self.set_cycles_per_frame(if faster { cpf + delta } else if cpf > delta { cpf - delta } else { 1 });
+        frontend.show_status(format!("speed: {} instructions/sec", self.instructions_per_second()).as_slice());
+    }
+
+    // Execute one instruction as part of the current frame, ticking the timers
+    // at the end of the frame. Returns true if this step completed a frame.
+    fn clocked_step(&mut self) -> Result<bool, Chip8Error> {
+        if self.frame_cycles == 0 {
+            self.apply_latched_keys();
+            self.movie_frame();
+            self.take_snapshot();
+        }
+        try!(self.step());
+        self.cycle_count += 1;
+
+        self.frame_cycles += 1;
+        if self.frame_cycles >= self.cycles_per_frame {
+            self.frame_cycles = 0;
+            self.tick_timers();
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn fetch_opcode(&self) -> Result<u16, Chip8Error> {
+        try!(self.check_mem_range(self.pc as uint, 2));
+        Ok(( ( self.mem[ self.pc as uint ] as u16 ) << 8 ) |
+           self.mem[ self.pc as uint + 1 ] as u16)
+    }
+
+    // How much memory the quirks give programs
+    fn memory_size(&self) -> uint {
+        if self.quirks.large_memory { MEMORY_SIZE } else { SMALL_MEMORY_SIZE }
+    }
+
+    // Validate an access of len bytes of memory starting at addr
+    fn check_mem_range(&self, addr: uint, len: uint) -> Result<(), Chip8Error> {
+        if addr + len > self.memory_size() {
+            return Err(Chip8Error::MemoryOutOfBounds { addr: addr + len - 1 });
+        }
+        Ok(())
     }
 
     fn advance_pc(& mut self, instruction_count: u16) {
         self.pc += instruction_count * 2;
     }
 
-    fn stack_push(& mut self, val: u16) {
-        assert!(self.sp as uint <= REGISTER_COUNT);
+    // Number of instructions to advance to skip the next one, XO-CHIP's
+    // F000 NNNN takes up the space of two
+    fn skip_length(&self) -> u16 {
+        let next = self.pc as uint + 2;
+        if next + 1 < self.memory_size() && self.mem[next] == 0xF0 && self.mem[next + 1] == 0x00 { 3 } else { 2 }
+    }
+
+    // XO-CHIP audio pattern playback rate in bits per second
+    fn pattern_rate(&self) -> f32 {
+        4000.0 * 2.0f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    fn stack_push(& mut self, val: u16) -> Result<(), Chip8Error> {
+        if self.sp as uint >= STACK_SIZE {
+            return Err(Chip8Error::StackOverflow);
+        }
         self.stack[self.sp as uint] = val;
         self.sp += 1;
+        Ok(())
     }
 
-    fn stack_pop(& mut self) -> u16 {
-        assert!(self.sp > 0);
+    fn stack_pop(& mut self) -> Result<u16, Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow);
+        }
         self.sp -= 1;
-        return self.stack[self.sp as uint];
+        return Ok(self.stack[self.sp as uint]);
     }
 
     // Instruction: Jump to location
@@ -241,164 +695,367 @@ impl Chip8 {
     }
 
     // Instruction: Call subroutine
-    fn execute_call(& mut self, dst: u16) {
+    fn execute_call(& mut self, dst: u16) -> Result<(), Chip8Error> {
         // TODO: a temporary should not be required here
         //  this appears to be related to https://github.com/rust-lang/rust/issues/6268
         let currpc = self.pc;
-        self.stack_push(currpc);
+        try!(self.stack_push(currpc));
         self.pc = dst;
+        Ok(())
     }
 
     // Instruction: Skip next instruction if Vx == val
     fn execute_skipifeq(& mut self, vx: uint, val: u8) {
-        let numinstr = if self.v[vx] == val { 2 } else { 1 };
+        self.watch(Location::V(vx), AccessKind::Read);
+        let numinstr = if self.v[vx] == val { self.skip_length() } else { 1 };
         self.advance_pc(numinstr);
     }
 
     // Instruction: Skip next instruction if Vx != val
     fn execute_skipifneq(& mut self, vx: uint, val: u8) {
-        let numinstr = if self.v[vx] != val { 2 } else { 1 };
+        self.watch(Location::V(vx), AccessKind::Read);
+        let numinstr = if self.v[vx] != val { self.skip_length() } else { 1 };
         self.advance_pc(numinstr);
     }
 
     // Instruction: Vx = val
     fn execute_setregister_const(& mut self, vx: uint, val: u8) {
+        self.watch(Location::V(vx), AccessKind::Write);
         self.v[vx] = val;
         self.advance_pc(1);
     }
 
     // Instruction: Vx = Vx + val
     fn execute_addregister(& mut self, vx: uint, val: u8) {
+        self.watch(Location::V(vx), AccessKind::Read);
+        self.watch(Location::V(vx), AccessKind::Write);
         self.v[vx] += val;
         self.advance_pc(1);
     }
 
     // Instruction: Skip next instruction if Vx == Vy
     fn execute_skipifeq_register(& mut self, vx: uint, vy: uint) {
-        let numinstr = if self.v[vx] == self.v[vy] { 2 } else { 1 };
+        self.watch(Location::V(vx), AccessKind::Read);
+        self.watch(Location::V(vy), AccessKind::Read);
+        let numinstr = if self.v[vx] == self.v[vy] { self.skip_length() } else { 1 };
         self.advance_pc(numinstr);
     }
 
     // Instruction: Skip next instruction if Vx != Vy
     fn execute_skipifneq_register(& mut self, vx: uint, vy: uint) {
-        let numinstr = if self.v[vx] != self.v[vy] { 2 } else { 1 };
+        self.watch(Location::V(vx), AccessKind::Read);
+        self.watch(Location::V(vy), AccessKind::Read);
+        let numinstr = if self.v[vx] != self.v[vy] { self.skip_length() } else { 1 };
         self.advance_pc(numinstr);
     }
 
-    // Instruction: Clear Display
+    // Instruction: Clear Display (the selected planes only)
     fn execute_clearscreen(& mut self) {
-        self.gfx = [0; PIXEL_COUNT];
+        for pixel in self.gfx.iter_mut() {
+            *pixel &= !self.planes;
+        }
+        self.gfx_update = true;
+        self.advance_pc(1);
+    }
+
+    // Move the selected planes of the display by (dx, dy) pixels, filling in
+    // the vacated area with blank pixels
+    fn scroll(& mut self, dx: int, dy: int) {
+        let (width, height) = self.screen_size();
+        let old = self.gfx;
+        for y in range(0u, height) {
+            for x in range(0u, width) {
+                let (srcx, srcy) = (x as int - dx, y as int - dy);
+                let src = if srcx >= 0 && srcx < width as int && srcy >= 0 && srcy < height as int {
+                    old[srcx as uint + srcy as uint * width]
+                } else {
+                    0
+                };
+                let pixel = x + y * width;
+                self.gfx[pixel] = (old[pixel] & !self.planes) | (src & self.planes);
+            }
+        }
+        self.gfx_update = true;
+        self.advance_pc(1);
+    }
+
+    // Instruction: Scroll the display down n rows
+    fn execute_scrolldown(& mut self, rows: uint) {
+        self.scroll(0, rows as int);
+    }
+
+    // Instruction: Scroll the display up n rows
+    fn execute_scrollup(& mut self, rows: uint) {
+        self.scroll(0, -(rows as int));
+    }
+
+    // Instruction: Scroll the display right 4 pixels
+    fn execute_scrollright(& mut self) {
+        self.scroll(4, 0);
+    }
+
+    // Instruction: Scroll the display left 4 pixels
+    fn execute_scrollleft(& mut self) {
+        self.scroll(-4, 0);
+    }
+
+    // Instruction: Exit the interpreter
+    fn execute_exit(& mut self) {
+        self.halted = true;
+    }
+
+    // Instruction: Switch between 64x32 and 128x64 display modes, clearing
+    // every plane of the screen, as the old pixels don't fit the new rows
+    fn execute_sethires(& mut self, hires: bool) {
+        self.hires = hires;
+        for pixel in self.gfx.iter_mut() {
+            *pixel = 0;
+        }
         self.gfx_update = true;
         self.advance_pc(1);
     }
 
     // Instruction: Return from current call
-    fn execute_return(& mut self) {
-        self.pc = self.stack_pop();
+    fn execute_return(& mut self) -> Result<(), Chip8Error> {
+        self.pc = try!(self.stack_pop());
         self.advance_pc(1);
+        Ok(())
     }
 
     // Instruction: I = val
     fn execute_seti(& mut self, val: u16) {
+        self.watch(Location::I, AccessKind::Write);
         self.i = val;
         self.advance_pc(1);
     }
     
     // Instruction: Jump to v0 + val
     fn execute_jumpv0(& mut self, val: u16) {
-        self.pc = self.v[0] as u16 + val;
+        let vi = if self.quirks.jump_uses_vx { ((val & 0xF00) >> 8) as uint } else { 0 };
+        self.watch(Location::V(vi), AccessKind::Read);
+        self.pc = self.v[vi] as u16 + val;
     }
 
     // Instruction: Vx = rand byte & val
     fn execute_setrandand(& mut self, vx: uint, val: u8) {
-        self.v[vx] = val & rand::random::<u8>();
+        self.watch(Location::V(vx), AccessKind::Write);
+        self.v[vx] = val & self.rng.next_u8();
         self.advance_pc(1);
     }
 
     // Instruction: Display n-row sprite starting at memory location I at (Vx, Vy), set VF = collision.
-    fn execute_draw(& mut self, vx: uint, vy: uint, rows: u8) {
-        let xcoord = self.v[vx] as uint;
-        let ycoord = self.v[vy] as uint;
+    // With n = 0 a 16x16 SUPER-CHIP sprite is drawn, stored as two bytes per row. When both
+    // XO-CHIP planes are selected the sprite data for the second plane follows that of the first.
+    fn execute_draw(& mut self, vx: uint, vy: uint, rows: u8) -> Result<(), Chip8Error> {
+        let (width, height) = self.screen_size();
+        let (spritewidth, spriteheight) = if rows == 0 { (16u, 16u) } else { (8u, rows as uint) };
+        let rowbytes = spritewidth / 8;
+        let spritesize = spriteheight * rowbytes;
+        let planecount = (self.planes & 1) as uint + ((self.planes >> 1) & 1) as uint;
+        let xcoord = self.v[vx] as uint % width;
+        let ycoord = self.v[vy] as uint % height;
+        try!(self.check_mem_range(self.i as uint, spritesize * planecount));
+        self.watch(Location::V(vx), AccessKind::Read);
+        self.watch(Location::V(vy), AccessKind::Read);
+        self.watch(Location::I, AccessKind::Read);
+        let mut spriteaddr = self.i as uint;
+        self.watch_mem(spriteaddr, spritesize * planecount, AccessKind::Read);
+        self.watch(Location::V(0xF), AccessKind::Write);
 
         self.v[0xF] = 0;
-        for rowidx in range(0u, rows as uint) {
-            let spriterow = self.mem[self.i as uint + rowidx];
+        for plane in [1u8, 2u8].iter() {
+            if self.planes & *plane == 0 {
+                continue;
+            }
+            self.draw_plane(*plane, spriteaddr, xcoord, ycoord, spritewidth, spriteheight);
+            spriteaddr += spritesize;
+        }
+
+        self.gfx_update = true;
+        self.advance_pc(1);
+        Ok(())
+    }
+
+    // XOR a sprite onto one plane of the display, setting VF on collision
+    fn draw_plane(& mut self, plane: u8, addr: uint, xcoord: uint, ycoord: uint,
+                  spritewidth: uint, spriteheight: uint) {
+        let (width, height) = self.screen_size();
+        let rowbytes = spritewidth / 8;
+
+        for rowidx in range(0u, spriteheight) {
+            let rowaddr = addr + rowidx * rowbytes;
+            let spriterow = if rowbytes == 2 {
+                ((self.mem[rowaddr] as u16) << 8) | self.mem[rowaddr + 1] as u16
+            } else {
+                (self.mem[rowaddr] as u16) << 8
+            };
 
             // our representation of pixels is as bytes, but the source
             // pixels are bitwise in memory
-            for colidx in range(0u, 8) {
-                if spriterow & (0x80 >> colidx) > 0 {
+            for colidx in range(0u, spritewidth) {
+                if spriterow & (0x8000 >> colidx) > 0 {
+                    // sprites either wrap around or are clipped at the edges
+                    let (x, y) = (colidx + xcoord, rowidx + ycoord);
+                    if self.quirks.clip_sprites && (x >= width || y >= height) {
+                        continue;
+                    }
+                    let pixel = x % width + (y % height) * width;
+
                     // check for collision and set VF if needed
-                    if self.gfx[colidx + xcoord + (rowidx + ycoord) * SCREEN_WIDTH] == 1 {
+                    if self.gfx[pixel] & plane != 0 {
                         self.v[0xF] = 1;
                     }
 
-                    self.gfx[colidx + xcoord + (rowidx + ycoord) * SCREEN_WIDTH] ^= 1;
+                    self.gfx[pixel] ^= plane;
                 }
             }
         }
-
-        self.gfx_update = true;
-        self.advance_pc(1);
     }
 
     // Instruction: Vx = Delay Timer
     fn execute_loaddtimer(& mut self, vx: uint) {
+        self.watch(Location::DelayTimer, AccessKind::Read);
+        self.watch(Location::V(vx), AccessKind::Write);
         self.v[vx] = self.delay_timer;
         self.advance_pc(1);
     }
 
     // Instruction: Wait for key press, store key in Vx
     fn execute_waitkey(& mut self, vx: uint) {
-        'waitloop : loop {
-            match sdl::event::poll_event() {
-                sdl::event::Event::None => {},
-                sdl::event::Event::Key(k, pressed, _, _) => {
-                    self.handle_keypress(k, pressed);
-                    if pressed {
-                        match Chip8::map_key(k) {
-                            Some(mkey) => {
-                                self.v[vx] = mkey;
-                            },
-                            None => {}
-                        }
-                        break 'waitloop;
-                    }
-                },
-                _ => ()
-            }
-        }
-
+        // execution is suspended until handle_keypress sees the next key go
+        // down, or down and back up again, while the timers and display carry
+        // on as normal
+        self.waitkey_reg = Some(vx);
+        self.waitkey_held = None;
         self.advance_pc(1);
     }
 
     // Instruction: Delay Timer = Vx
     fn execute_setdtimer(& mut self, vx: uint) {
+        self.watch(Location::V(vx), AccessKind::Read);
+        self.watch(Location::DelayTimer, AccessKind::Write);
         self.delay_timer = self.v[vx];
         self.advance_pc(1);
     }
 
     // Instruction: Sound Timer = Vx
     fn execute_setstimer(& mut self, vx: uint) {
+        self.watch(Location::V(vx), AccessKind::Read);
+        self.watch(Location::SoundTimer, AccessKind::Write);
         self.sound_timer = self.v[vx];
         self.advance_pc(1);
     }
 
     // Instruction: I = I + Vx
     fn execute_addi(& mut self, vx: uint) {
+        self.watch(Location::V(vx), AccessKind::Read);
+        self.watch(Location::I, AccessKind::Read);
+        self.watch(Location::I, AccessKind::Write);
         self.i += self.v[vx] as u16;
         self.advance_pc(1);
     }
 
     // Instruction: I = location of sprite for digit Vx
-    fn execute_setifont(& mut self, vx: uint) {
-        assert!(self.v[vx] <= 0xF);
+    fn execute_setifont(& mut self, vx: uint) -> Result<(), Chip8Error> {
+        self.watch(Location::V(vx), AccessKind::Read);
+        if self.v[vx] > 0xF {
+            return Err(Chip8Error::InvalidKey);
+        }
+        self.watch(Location::I, AccessKind::Write);
         self.i = self.v[vx] as u16 * FONT_DIGIT_SIZE;
         self.advance_pc(1);
+        Ok(())
+    }
+
+    // Instruction: I = the 16-bit address following this instruction
+    fn execute_setilong(& mut self) -> Result<(), Chip8Error> {
+        let addr = self.pc as uint + 2;
+        try!(self.check_mem_range(addr, 2));
+        self.watch(Location::I, AccessKind::Write);
+        self.i = ((self.mem[addr] as u16) << 8) | self.mem[addr + 1] as u16;
+        self.advance_pc(2);
+        Ok(())
+    }
+
+    // Instruction: Select the XO-CHIP bitplanes drawn to by subsequent instructions
+    fn execute_selectplanes(& mut self, planes: u8) {
+        self.planes = planes & 0x3;
+        self.advance_pc(1);
+    }
+
+    // Instruction: Load the 16 byte audio pattern buffer from memory starting at location I
+    fn execute_loadpattern(& mut self) -> Result<(), Chip8Error> {
+        try!(self.check_mem_range(self.i as uint, PATTERN_SIZE));
+        self.watch(Location::I, AccessKind::Read);
+        let addr = self.i as uint;
+        self.watch_mem(addr, PATTERN_SIZE, AccessKind::Read);
+        for idx in range(0u, PATTERN_SIZE) {
+            self.pattern[idx] = self.mem[self.i as uint + idx];
+        }
+        self.pattern_update = true;
+        self.advance_pc(1);
+        Ok(())
+    }
+
+    // Instruction: Set the audio pattern playback pitch to Vx
+    fn execute_setpitch(& mut self, vx: uint) {
+        self.watch(Location::V(vx), AccessKind::Read);
+        self.pitch = self.v[vx];
+        self.pattern_update = true;
+        self.advance_pc(1);
+    }
+
+    // Instruction: Store Vx through Vy in memory starting at location I, I is unchanged
+    fn execute_storerange(& mut self, vx: uint, vy: uint) -> Result<(), Chip8Error> {
+        let count = if vx > vy { vx - vy } else { vy - vx } + 1;
+        try!(self.check_mem_range(self.i as uint, count));
+        self.watch(Location::I, AccessKind::Read);
+        for offset in range(0u, count) {
+            let vi = if vx > vy { vx - offset } else { vx + offset };
+            let addr = self.i as uint + offset;
+            self.watch(Location::V(vi), AccessKind::Read);
+            self.watch(Location::Mem(addr as u16), AccessKind::Write);
+            self.mem[self.i as uint + offset] = self.v[vi];
+        }
+        self.advance_pc(1);
+        Ok(())
+    }
+
+    // Instruction: Load Vx through Vy from memory starting at location I, I is unchanged
+    fn execute_loadrange(& mut self, vx: uint, vy: uint) -> Result<(), Chip8Error> {
+        let count = if vx > vy { vx - vy } else { vy - vx } + 1;
+        try!(self.check_mem_range(self.i as uint, count));
+        self.watch(Location::I, AccessKind::Read);
+        for offset in range(0u, count) {
+            let vi = if vx > vy { vx - offset } else { vx + offset };
+            let addr = self.i as uint + offset;
+            self.watch(Location::Mem(addr as u16), AccessKind::Read);
+            self.watch(Location::V(vi), AccessKind::Write);
+            self.v[vi] = self.mem[self.i as uint + offset];
+        }
+        self.advance_pc(1);
+        Ok(())
+    }
+
+    // Instruction: I = location of SUPER-CHIP large sprite for digit Vx
+    fn execute_setibigfont(& mut self, vx: uint) -> Result<(), Chip8Error> {
+        self.watch(Location::V(vx), AccessKind::Read);
+        if self.v[vx] > 9 {
+            return Err(Chip8Error::InvalidKey);
+        }
+        self.watch(Location::I, AccessKind::Write);
+        self.i = FONTSET_SIZE as u16 + self.v[vx] as u16 * BIGFONT_DIGIT_SIZE;
+        self.advance_pc(1);
+        Ok(())
     }
 
     // Instruction: Store BCD representation of Vx in memory locations I, I+1, and I+2.
-    fn execute_storebcd(& mut self, vx: uint) {
+    fn execute_storebcd(& mut self, vx: uint) -> Result<(), Chip8Error> {
+        try!(self.check_mem_range(self.i as uint, 3));
+        self.watch(Location::V(vx), AccessKind::Read);
+        self.watch(Location::I, AccessKind::Read);
+        let addr = self.i as uint;
+        self.watch_mem(addr, 3, AccessKind::Write);
         let hundreds = self.v[vx] / 100;
         let tens = (self.v[vx] - hundreds * 100) / 10;
         let ones = self.v[vx] - hundreds * 100 - tens * 10;
@@ -406,64 +1063,137 @@ impl Chip8 {
         self.mem[self.i as uint + 1] = tens;
         self.mem[self.i as uint+ 2] = ones;
         self.advance_pc(1);
+        Ok(())
     }
 
     // Instruction: Read V0 through Vx from memory starting at location I
-    fn execute_storeregs(& mut self, vx: uint) {
+    fn execute_storeregs(& mut self, vx: uint) -> Result<(), Chip8Error> {
+        try!(self.check_mem_range(self.i as uint, vx + 1));
+        self.watch(Location::I, AccessKind::Read);
+        self.watch_regs(vx, AccessKind::Read);
+        let addr = self.i as uint;
+        self.watch_mem(addr, vx + 1, AccessKind::Write);
         for vi in range(0u, vx + 1) {
             self.mem[self.i as uint + vi] = self.v[vi];
         }
-        self.advance_pc(1)
+        if self.quirks.load_store_increments_i {
+            self.watch(Location::I, AccessKind::Write);
+            self.i += vx as u16 + 1;
+        }
+        self.advance_pc(1);
+        Ok(())
     }
 
     // Instruction: Store V0 through Vx in memory starting at location I
-    fn execute_loadregs(& mut self, vx: uint) {
+    fn execute_loadregs(& mut self, vx: uint) -> Result<(), Chip8Error> {
+        try!(self.check_mem_range(self.i as uint, vx + 1));
+        self.watch(Location::I, AccessKind::Read);
+        let addr = self.i as uint;
+        self.watch_mem(addr, vx + 1, AccessKind::Read);
+        self.watch_regs(vx, AccessKind::Write);
         for vi in range(0u, vx + 1) {
             self.v[vi] = self.mem[self.i as uint + vi];
         }
-        self.advance_pc(1)
+        if self.quirks.load_store_increments_i {
+            self.watch(Location::I, AccessKind::Write);
+            self.i += vx as u16 + 1;
+        }
+        self.advance_pc(1);
+        Ok(())
+    }
+
+    // Instruction: Store V0 through Vx in the RPL user flags
+    fn execute_storeflags(& mut self, vx: uint) {
+        self.watch_regs(vx, AccessKind::Read);
+        for vi in range(0u, vx + 1) {
+            self.rpl[vi] = self.v[vi];
+        }
+        self.advance_pc(1);
+    }
+
+    // Instruction: Read V0 through Vx from the RPL user flags
+    fn execute_loadflags(& mut self, vx: uint) {
+        self.watch_regs(vx, AccessKind::Write);
+        for vi in range(0u, vx + 1) {
+            self.v[vi] = self.rpl[vi];
+        }
+        self.advance_pc(1);
     }
 
     // Instruction: Skip next instruction if key in Vx is pressed
-    fn execute_skipifkeypress(& mut self, vx: uint) {
-        assert!(self.v[vx] < KEY_COUNT as u8);
-        let instrcount = if self.key[self.v[vx] as uint] == 1 { 2 } else { 1 };
+    fn execute_skipifkeypress(& mut self, vx: uint) -> Result<(), Chip8Error> {
+        self.watch(Location::V(vx), AccessKind::Read);
+        if self.v[vx] as uint >= KEY_COUNT {
+            return Err(Chip8Error::InvalidKey);
+        }
+        let instrcount = if self.key[self.v[vx] as uint] == 1 { self.skip_length() } else { 1 };
         self.advance_pc(instrcount);
+        Ok(())
     }
 
     // Instruction: Skip next instruction if key in Vx is not pressed
-    fn execute_skipifnkeypress(& mut self, vx: uint) {
-        assert!(self.v[vx] < KEY_COUNT as u8);
-        let instrcount = if self.key[self.v[vx] as uint] == 0 { 2 } else { 1 };
+    fn execute_skipifnkeypress(& mut self, vx: uint) -> Result<(), Chip8Error> {
+        self.watch(Location::V(vx), AccessKind::Read);
+        if self.v[vx] as uint >= KEY_COUNT {
+            return Err(Chip8Error::InvalidKey);
+        }
+        let instrcount = if self.key[self.v[vx] as uint] == 0 { self.skip_length() } else { 1 };
         self.advance_pc(instrcount);
+        Ok(())
     }
 
     // Instruction: Vx = Vy
     fn execute_setregister_reg(& mut self, vx: uint, vy: uint) {
+        self.watch(Location::V(vy), AccessKind::Read);
+        self.watch(Location::V(vx), AccessKind::Write);
         self.v[vx] = self.v[vy];
         self.advance_pc(1);
     }
 
     // Instruction: Vx = Vx | Vy
     fn execute_bitor(& mut self, vx: uint, vy: uint) {
+        self.watch_alu(vx, vy, false);
         self.v[vx] = self.v[vx] | self.v[vy];
+        self.reset_vf_after_logic();
         self.advance_pc(1);
     }
 
     // Instruction: Vx = Vx & Vy
     fn execute_bitand(& mut self, vx: uint, vy: uint) {
+        self.watch_alu(vx, vy, false);
         self.v[vx] = self.v[vx] & self.v[vy];
+        self.reset_vf_after_logic();
         self.advance_pc(1);
     }
 
     // Instruction: Vx = Vx ^ Vy
     fn execute_bitxor(& mut self, vx: uint, vy: uint) {
+        self.watch_alu(vx, vy, false);
         self.v[vx] = self.v[vx] ^ self.v[vy];
+        self.reset_vf_after_logic();
         self.advance_pc(1);
     }
 
+    fn reset_vf_after_logic(& mut self) {
+        if self.quirks.logic_resets_vf {
+            self.watch(Location::V(0xF), AccessKind::Write);
+            self.v[0xF] = 0;
+        }
+    }
+
+    // Report the accesses of an 8XYN arithmetic or logic instruction
+    fn watch_alu(& mut self, vx: uint, vy: uint, sets_vf: bool) {
+        self.watch(Location::V(vx), AccessKind::Read);
+        self.watch(Location::V(vy), AccessKind::Read);
+        self.watch(Location::V(vx), AccessKind::Write);
+        if sets_vf {
+            self.watch(Location::V(0xF), AccessKind::Write);
+        }
+    }
+
     // Instruction: Vx = Vx + Vy, VF = carry
     fn execute_add(& mut self, vx: uint, vy: uint) {
+        self.watch_alu(vx, vy, true);
         let result = self.v[vx] as u16 + self.v[vy] as u16;
         self.v[vx] = result as u8;
         self.v[0xF] = if result > std::u8::MAX as u16 { 1 } else { 0 };
@@ -472,88 +1202,102 @@ impl Chip8 {
 
     // Instruction: Vx = Vx - Vy, VF = ~borrow
     fn execute_sub(& mut self, vx: uint, vy: uint) {
+        self.watch_alu(vx, vy, true);
         self.v[0xF] = if self.v[vy] > self.v[vx] { 0 } else { 1 };
         self.v[vx] = self.v[vx] - self.v[vy];
         self.advance_pc(1);
     }
 
-    // Instruction: Vx = Vx >> 1, VF = LSB of Vx before shifting
-    fn execute_shr(& mut self, vx: uint) {
-        self.v[0xF] = self.v[vx] & 1;
-        self.v[vx] = self.v[vx] >> 1;
+    // Instruction: Vx = Vx >> 1 (or Vy >> 1), VF = LSB before shifting
+    fn execute_shr(& mut self, vx: uint, vy: uint) {
+        let src = if self.quirks.shift_uses_vy { self.v[vy] } else { self.v[vx] };
+        self.watch(Location::V(if self.quirks.shift_uses_vy { vy } else { vx }), AccessKind::Read);
+        self.watch(Location::V(vx), AccessKind::Write);
+        self.watch(Location::V(0xF), AccessKind::Write);
+        self.v[0xF] = src & 1;
+        self.v[vx] = src >> 1;
         self.advance_pc(1);
     }
 
-    // Instruction: Vx = Vx << 1, VF = MSB of Vx before shifting
-    fn execute_shl(& mut self, vx: uint) {
-        self.v[0xF] = self.v[vx] >> 7;
-        self.v[vx] = self.v[vx] << 1;
+    // Instruction: Vx = Vx << 1 (or Vy << 1), VF = MSB before shifting
+    fn execute_shl(& mut self, vx: uint, vy: uint) {
+        let src = if self.quirks.shift_uses_vy { self.v[vy] } else { self.v[vx] };
+        self.watch(Location::V(if self.quirks.shift_uses_vy { vy } else { vx }), AccessKind::Read);
+        self.watch(Location::V(vx), AccessKind::Write);
+        self.watch(Location::V(0xF), AccessKind::Write);
+        self.v[0xF] = src >> 7;
+        self.v[vx] = src << 1;
         self.advance_pc(1);
     }
 
     // Instruction: Vx = Vy - Vx, VF = 0 if there is a borrow, 1 otherwise
     fn execute_sub_inverse(& mut self, vx: uint, vy: uint) {
+        self.watch_alu(vx, vy, true);
         self.v[0xF] = if self.v[vx] > self.v[vy] { 0 } else { 1 };
         self.v[vx] = self.v[vy] - self.v[vx];
         self.advance_pc(1);
     }
 
-    fn decode_and_execute(& mut self, opcode: u16) {
-        let short = opcode & 0x0FFF;
-        let vx = ((opcode & 0x0F00) >> 8) as uint;
-        let vy = ((opcode & 0x00F0) >> 4) as uint;
-        let byte = (opcode & 0xFF) as u8;
-        let nibble = (opcode & 0xF) as u8;
-
-        match opcode {
-            0x00E0 => self.execute_clearscreen(),
-            0x00EE => self.execute_return(),
-            _ => match (opcode & 0xF000) >> 12 {
-                0x1 => self.execute_jump(short),
-                0x2 => self.execute_call(short),
-                0x3 => self.execute_skipifeq(vx, byte),
-                0x4 => self.execute_skipifneq(vx, byte),
-                0x5 => self.execute_skipifeq_register(vx, vy),
-                0x6 => self.execute_setregister_const(vx, byte),
-                0x7 => self.execute_addregister(vx, byte),
-                0x8 => match opcode & 0xF {
-                    0x0 => self.execute_setregister_reg(vx, vy),
-                    0x1 => self.execute_bitor(vx, vy),
-                    0x2 => self.execute_bitand(vx, vy),
-                    0x3 => self.execute_bitxor(vx, vy),
-                    0x4 => self.execute_add(vx, vy),
-                    0x5 => self.execute_sub(vx, vy),
-                    0x6 => self.execute_shr(vx),
-                    0x7 => self.execute_sub_inverse(vx, vy),
-                    0xE => self.execute_shl(vx),
-                      _ => println!("invalid instruction")
-                },
-                0x9 => self.execute_skipifneq_register(vx, vy), 
-                0xA => self.execute_seti(short),
-                0xB => self.execute_jumpv0(short),
-                0xC => self.execute_setrandand(vx, byte),
-                0xD => self.execute_draw(vx, vy, nibble),
-                0xE => match opcode & 0xFF {
-                    0x9E => self.execute_skipifkeypress(vx),
-                    0xA1 => self.execute_skipifnkeypress(vx),
-                       _ => println!("invalid instruction")
-                },
-                0xF => match opcode & 0xFF {
-                    0x07 => self.execute_loaddtimer(vx),
-                    0x0A => self.execute_waitkey(vx),
-                    0x15 => self.execute_setdtimer(vx),
-                    0x18 => self.execute_setstimer(vx),
-                    0x1E => self.execute_addi(vx),
-                    0x29 => self.execute_setifont(vx),
-                    0x33 => self.execute_storebcd(vx),
-                    0x55 => self.execute_storeregs(vx),
-                    0x65 => self.execute_loadregs(vx),
-                       _ => println!("invalid instruction")
-                },
-                _ => println!("invalid instruction1") 
-            }
+    fn decode_and_execute(& mut self, opcode: u16) -> Result<(), Chip8Error> {
+        // the machine accepts every extension, quirks cover where they differ
+        let instr = match Instruction::decode(opcode, InstructionSet::XoChip) {
+            Some(instr) => instr,
+            None => return Err(Chip8Error::UnknownOpcode { pc: self.pc, opcode: opcode })
+        };
+
+        match instr {
+            Instruction::ClearScreen             => self.execute_clearscreen(),
+            Instruction::Return                  => try!(self.execute_return()),
+            Instruction::ScrollDown(rows)        => self.execute_scrolldown(rows),
+            Instruction::ScrollUp(rows)          => self.execute_scrollup(rows),
+            Instruction::ScrollRight             => self.execute_scrollright(),
+            Instruction::ScrollLeft              => self.execute_scrollleft(),
+            Instruction::Exit                    => self.execute_exit(),
+            Instruction::SetHires(hires)         => self.execute_sethires(hires),
+            Instruction::Jump(addr)              => self.execute_jump(addr),
+            Instruction::Call(addr)              => try!(self.execute_call(addr)),
+            Instruction::SkipIfEq(vx, val)       => self.execute_skipifeq(vx, val),
+            Instruction::SkipIfNeq(vx, val)      => self.execute_skipifneq(vx, val),
+            Instruction::SkipIfEqRegister(vx, vy) => self.execute_skipifeq_register(vx, vy),
+            Instruction::StoreRange(vx, vy)      => try!(self.execute_storerange(vx, vy)),
+            Instruction::LoadRange(vx, vy)       => try!(self.execute_loadrange(vx, vy)),
+            Instruction::SetRegisterConst(vx, val) => self.execute_setregister_const(vx, val),
+            Instruction::AddRegister(vx, val)    => self.execute_addregister(vx, val),
+            Instruction::SetRegisterReg(vx, vy)  => self.execute_setregister_reg(vx, vy),
+            Instruction::BitOr(vx, vy)           => self.execute_bitor(vx, vy),
+            Instruction::BitAnd(vx, vy)          => self.execute_bitand(vx, vy),
+            Instruction::BitXor(vx, vy)          => self.execute_bitxor(vx, vy),
+            Instruction::Add(vx, vy)             => self.execute_add(vx, vy),
+            Instruction::Sub(vx, vy)             => self.execute_sub(vx, vy),
+            Instruction::Shr(vx, vy)             => self.execute_shr(vx, vy),
+            Instruction::SubInverse(vx, vy)      => self.execute_sub_inverse(vx, vy),
+            Instruction::Shl(vx, vy)             => self.execute_shl(vx, vy),
+            Instruction::SkipIfNeqRegister(vx, vy) => self.execute_skipifneq_register(vx, vy),
+            Instruction::SetI(addr)              => self.execute_seti(addr),
+            Instruction::JumpV0(addr)            => self.execute_jumpv0(addr),
+            Instruction::SetRandAnd(vx, val)     => self.execute_setrandand(vx, val),
+            Instruction::Draw(vx, vy, rows)      => try!(self.execute_draw(vx, vy, rows)),
+            Instruction::SkipIfKeypress(vx)      => try!(self.execute_skipifkeypress(vx)),
+            Instruction::SkipIfNKeypress(vx)     => try!(self.execute_skipifnkeypress(vx)),
+            Instruction::SetILong                => try!(self.execute_setilong()),
+            Instruction::SelectPlanes(planes)    => self.execute_selectplanes(planes),
+            Instruction::LoadPattern             => try!(self.execute_loadpattern()),
+            Instruction::LoadDTimer(vx)          => self.execute_loaddtimer(vx),
+            Instruction::WaitKey(vx)             => self.execute_waitkey(vx),
+            Instruction::SetDTimer(vx)           => self.execute_setdtimer(vx),
+            Instruction::SetSTimer(vx)           => self.execute_setstimer(vx),
+            Instruction::AddI(vx)                => self.execute_addi(vx),
+            Instruction::SetIFont(vx)            => try!(self.execute_setifont(vx)),
+            Instruction::SetIBigFont(vx)         => try!(self.execute_setibigfont(vx)),
+            Instruction::SetPitch(vx)            => self.execute_setpitch(vx),
+            Instruction::StoreBcd(vx)            => try!(self.execute_storebcd(vx)),
+            Instruction::StoreRegs(vx)           => try!(self.execute_storeregs(vx)),
+            Instruction::LoadRegs(vx)            => try!(self.execute_loadregs(vx)),
+            Instruction::StoreFlags(vx)          => self.execute_storeflags(vx),
+            Instruction::LoadFlags(vx)           => self.execute_loadflags(vx)
         }
 
         // println!("{:X}, PC: {:X}, I: {:X}", opcode, self.pc, self.i);
+        Ok(())
     }
 }
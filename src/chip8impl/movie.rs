@@ -0,0 +1,210 @@
+use std::io::{IoError, IoResult, InvalidInput};
+use std::mem;
+use super::random::Random;
+use super::{Chip8, Quirks, KEY_COUNT};
+
+// Movies start with a magic number ("CH8M") and a format version
+static MOVIE_MAGIC : [u8; 4] = [0x43, 0x48, 0x38, 0x4D];
+const MOVIE_VERSION: u8 = 1;
+
+// Marks a frame that didn't complete an FX0A
+const NO_WAITKEY: u8 = 0xFF;
+
+fn invalid_movie(desc: &'static str) -> IoError {
+    IoError { kind: InvalidInput, desc: desc, detail: None }
+}
+
+// The input for one frame: a bit for each key held down during it, and the
+// key that completed an FX0A just before it started. Several keys can go down
+// between two frames, so the held keys alone don't say which one FX0A saw.
+#[derive(Show, PartialEq, Copy, Clone)]
+pub struct MovieFrame {
+    pub keys    : u16,
+    pub waitkey : Option<u8>
+}
+
+// A recording of every frame of input from power on, along with everything
+// else that decides how the run plays out
+#[derive(Show, PartialEq, Clone)]
+pub struct Movie {
+    pub seed             : u32,
+    pub quirks           : Quirks,
+    pub cycles_per_frame : uint,
+    pub frames           : Vec<MovieFrame>
+}
+
+impl Movie {
+
+    pub fn write(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write(&MOVIE_MAGIC));
+        try!(w.write_u8(MOVIE_VERSION));
+        try!(w.write_be_u32(self.seed));
+        try!(w.write_u8(self.quirks.to_bits()));
+        try!(w.write_be_u32(self.cycles_per_frame as u32));
+        try!(w.write_be_u32(self.frames.len() as u32));
+        for frame in self.frames.iter() {
+            try!(w.write_be_u16(frame.keys));
+            try!(w.write_u8(frame.waitkey.unwrap_or(NO_WAITKEY)));
+        }
+        Ok(())
+    }
+
+    pub fn read(r: &mut Reader) -> IoResult<Movie> {
+        if try!(r.read_exact(MOVIE_MAGIC.len())).as_slice() != MOVIE_MAGIC.as_slice() {
+            return Err(invalid_movie("not a movie"));
+        }
+        if try!(r.read_u8()) != MOVIE_VERSION {
+            return Err(invalid_movie("unsupported movie version"));
+        }
+
+        let seed = try!(r.read_be_u32());
+        let quirks = Quirks::from_bits(try!(r.read_u8()));
+        let cycles_per_frame = try!(r.read_be_u32()) as uint;
+        if cycles_per_frame == 0 {
+            return Err(invalid_movie("invalid speed"));
+        }
+        let count = try!(r.read_be_u32()) as uint;
+        let mut frames = Vec::new();
+        for _ in range(0u, count) {
+            let keys = try!(r.read_be_u16());
+            let waitkey = match try!(r.read_u8()) {
+                NO_WAITKEY => None,
+                key if (key as uint) < KEY_COUNT => Some(key),
+                _ => return Err(invalid_movie("invalid key"))
+            };
+            frames.push(MovieFrame { keys: keys, waitkey: waitkey });
+        }
+        Ok(Movie { seed: seed, quirks: quirks, cycles_per_frame: cycles_per_frame, frames: frames })
+    }
+
+    // Number of instructions it takes to play the whole movie
+    pub fn cycles(&self) -> uint {
+        self.frames.len() * self.cycles_per_frame
+    }
+}
+
+// Whether input is being recorded to or played back from a movie. While
+// recording, the key that completed an FX0A since the last frame is kept until
+// it can be stored with the next one.
+pub enum MovieMode {
+    Off,
+    Recording(Movie, Option<u8>),
+    Playing(Movie, uint)
+}
+
+// input recording and playback
+impl Chip8 {
+
+    // Record the input of every frame from here on. This should be called
+    // before the first instruction is executed, as playback starts at power on.
+    pub fn start_recording(&mut self) {
+        let movie = Movie {
+            seed             : self.rng.state(),
+            quirks           : self.quirks,
+            cycles_per_frame : self.cycles_per_frame,
+            frames           : Vec::new()
+        };
+        self.movie = MovieMode::Recording(movie, None);
+    }
+
+    // Stop recording, returning the movie recorded so far
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        match mem::replace(&mut self.movie, MovieMode::Off) {
+            MovieMode::Recording(movie, _) => Some(movie),
+            other => {
+                self.movie = other;
+                None
+            }
+        }
+    }
+
+    // Replay a movie's input in place of the frontend's, from its seed, quirks
+    // and speed. Like recording, this should start at power on.
+    pub fn play_movie(&mut self, movie: Movie) {
+        self.rng = Random::from_state(movie.seed);
+        self.quirks = movie.quirks;
+        self.set_cycles_per_frame(movie.cycles_per_frame);
+        self.movie = MovieMode::Playing(movie, 0);
+    }
+
+    // True while recording or playing a movie, when anything that would throw
+    // the input out of step with the frames is locked out
+    pub fn movie_active(&self) -> bool {
+        match self.movie {
+            MovieMode::Off => false,
+            _ => true
+        }
+    }
+
+    pub fn movie_playing(&self) -> bool {
+        match self.movie {
+            MovieMode::Playing(..) => true,
+            _ => false
+        }
+    }
+
+    // True once every frame of the movie being played has started
+    fn movie_ended(&self) -> bool {
+        match self.movie {
+            MovieMode::Playing(ref movie, frame) => frame >= movie.frames.len(),
+            _ => false
+        }
+    }
+
+    // Let the recording know which key completed an FX0A
+    fn record_waitkey(&mut self, key: u8) {
+        match self.movie {
+            MovieMode::Recording(_, ref mut waitkey) => *waitkey = Some(key),
+            _ => {}
+        }
+    }
+
+    // Deliver the key changes held back until the start of a frame
+    fn apply_latched_keys(&mut self) {
+        for (key, pressed) in mem::replace(&mut self.latched_keys, Vec::new()).into_iter() {
+            self.handle_keypress(key, pressed);
+        }
+    }
+
+    // Record or play back the input of the frame that's about to start
+    fn movie_frame(&mut self) {
+        let keys = self.key_mask();
+        let played = match self.movie {
+            MovieMode::Off => return,
+            MovieMode::Recording(ref mut movie, ref mut waitkey) => {
+                movie.frames.push(MovieFrame { keys: keys, waitkey: waitkey.take() });
+                return;
+            },
+            MovieMode::Playing(ref movie, ref mut frame) => {
+                *frame += 1;
+                movie.frames.get(*frame - 1).map(|f| *f)
+            }
+        };
+
+        match played {
+            Some(frame) => {
+                for k in range(0u, KEY_COUNT) {
+                    self.key[k] = ((frame.keys >> k) & 1) as u8;
+                }
+                match (frame.waitkey, self.waitkey_reg.take()) {
+                    (Some(key), Some(vx)) => self.v[vx] = key,
+                    (_, waiting) => self.waitkey_reg = waiting
+                }
+            },
+            None => {
+                self.movie = MovieMode::Off;
+                self.movie_finished = true;
+            }
+        }
+    }
+
+    fn key_mask(&self) -> u16 {
+        let mut keys = 0u16;
+        for k in range(0u, KEY_COUNT) {
+            if self.key[k] != 0 {
+                keys |= 1 << k;
+            }
+        }
+        keys
+    }
+}
@@ -0,0 +1,117 @@
+use std::default::Default;
+
+// Behaviors of ambiguous opcodes that differ between interpreters. ROMs are
+// usually written against one particular interpreter and rely on its quirks.
+#[derive(Copy, Clone, PartialEq, Show)]
+pub struct Quirks {
+    // 8XY6/8XYE shift Vy into Vx, rather than shifting Vx in place
+    pub shift_uses_vy           : bool,
+    // FX55/FX65 leave I pointing past the last register stored or loaded
+    pub load_store_increments_i : bool,
+    // BXNN jumps to XNN + Vx rather than BNNN jumping to NNN + V0
+    pub jump_uses_vx            : bool,
+    // 8XY1/8XY2/8XY3 reset VF to zero
+    pub logic_resets_vf         : bool,
+    // sprites are clipped at the screen edges rather than wrapping around
+    pub clip_sprites            : bool,
+    // FX0A completes once the key is released again, not as soon as it's
+    // pressed
+    pub waitkey_on_release      : bool,
+    // the whole 64K XO-CHIP address space is usable, not just the first 4K
+    pub large_memory            : bool
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift_uses_vy           : false,
+            load_store_increments_i : false,
+            jump_uses_vx            : false,
+            logic_resets_vf         : false,
+            clip_sprites            : false,
+            waitkey_on_release      : true,
+            large_memory            : true
+        }
+    }
+}
+
+impl Quirks {
+
+    // The original CHIP-8 interpreter on the COSMAC VIP
+    pub fn vip() -> Quirks {
+        Quirks {
+            shift_uses_vy           : true,
+            load_store_increments_i : true,
+            jump_uses_vx            : false,
+            logic_resets_vf         : true,
+            clip_sprites            : true,
+            waitkey_on_release      : true,
+            large_memory            : false
+        }
+    }
+
+    // CHIP-48 on the HP48 calculators
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy           : false,
+            load_store_increments_i : false,
+            jump_uses_vx            : true,
+            logic_resets_vf         : false,
+            clip_sprites            : true,
+            waitkey_on_release      : true,
+            large_memory            : false
+        }
+    }
+
+    // SUPER-CHIP 1.1, the successor of CHIP-48
+    pub fn schip() -> Quirks {
+        Quirks { ..Quirks::chip48() }
+    }
+
+    // XO-CHIP as implemented by Octo
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift_uses_vy           : true,
+            load_store_increments_i : true,
+            jump_uses_vx            : false,
+            logic_resets_vf         : false,
+            clip_sprites            : false,
+            waitkey_on_release      : true,
+            large_memory            : true
+        }
+    }
+
+    // Pack the quirks into a byte, one bit each, for saving to files. The
+    // memory bit is set for 4K, so files from before it keep all 64K.
+    pub fn to_bits(&self) -> u8 {
+        (self.shift_uses_vy as u8) |
+        (self.load_store_increments_i as u8) << 1 |
+        (self.jump_uses_vx as u8) << 2 |
+        (self.logic_resets_vf as u8) << 3 |
+        (self.clip_sprites as u8) << 4 |
+        (self.waitkey_on_release as u8) << 5 |
+        (!self.large_memory as u8) << 6
+    }
+
+    pub fn from_bits(bits: u8) -> Quirks {
+        Quirks {
+            shift_uses_vy           : bits & 0x01 != 0,
+            load_store_increments_i : bits & 0x02 != 0,
+            jump_uses_vx            : bits & 0x04 != 0,
+            logic_resets_vf         : bits & 0x08 != 0,
+            clip_sprites            : bits & 0x10 != 0,
+            waitkey_on_release      : bits & 0x20 != 0,
+            large_memory            : bits & 0x40 == 0
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "vip"    => Some(Quirks::vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip"  => Some(Quirks::schip()),
+            "xochip" => Some(Quirks::xochip()),
+            _ => None
+        }
+    }
+}
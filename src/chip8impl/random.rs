@@ -0,0 +1,31 @@
+// A xorshift generator for CXNN. Its whole state is one word, so it's easy to
+// seed from the command line and to keep in save states.
+#[derive(Copy, Clone, PartialEq, Show)]
+pub struct Random {
+    state : u32
+}
+
+// xorshift gets stuck at zero, so a zero seed is swapped for this
+const ZERO_SEED_STATE: u32 = 0x9E3779B9;
+
+impl Random {
+
+    pub fn new(seed: u32) -> Random {
+        Random::from_state(seed)
+    }
+
+    pub fn from_state(state: u32) -> Random {
+        Random { state: if state == 0 { ZERO_SEED_STATE } else { state } }
+    }
+
+    pub fn state(&self) -> u32 {
+        self.state
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 24) as u8
+    }
+}
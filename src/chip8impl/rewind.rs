@@ -0,0 +1,189 @@
+use std::collections::RingBuf;
+use std::io::{MemReader, MemWriter};
+use super::Chip8;
+
+// A save state taken at the start of a frame. Input is only delivered between
+// frames, so the key state in it covers everything up to the next snapshot.
+// States are mostly unused memory and blank pixels, so they're kept with runs
+// of zeros compressed.
+struct Snapshot {
+    cycle : u64,
+    state : Vec<u8>
+}
+
+// Snapshots of the last few seconds of emulation, oldest first
+pub struct RewindBuffer {
+    snapshots : RingBuf<Snapshot>,
+    capacity  : uint
+}
+
+impl RewindBuffer {
+
+    pub fn new(capacity: uint) -> RewindBuffer {
+        RewindBuffer { snapshots: RingBuf::new(), capacity: capacity }
+    }
+}
+
+// rewinding and reverse execution
+impl Chip8 {
+
+    // Keep a snapshot of each of the last frames frames, 0 turns rewinding off
+    pub fn set_rewind_frames(&mut self, frames: uint) {
+        self.rewind = RewindBuffer::new(frames);
+    }
+
+    // Total number of instructions executed through the frame scheduler
+    pub fn cycle_count(&self) -> u64 {
+        self.cycle_count
+    }
+
+    // Step back to the start of the previous frame, returning false once
+    // there's nothing left to rewind
+    pub fn rewind_frame(&mut self) -> bool {
+        // the newest snapshot is the start of the current frame
+        if self.rewind.snapshots.back().map_or(false, |s| s.cycle == self.cycle_count) {
+            self.rewind.snapshots.pop_back();
+        }
+        match self.rewind.snapshots.pop_back() {
+            Some(snapshot) => {
+                self.restore_snapshot(&snapshot);
+                true
+            },
+            None => false
+        }
+    }
+
+    // Record a snapshot at the start of a frame, unless it's already been
+    // taken while replaying
+    fn take_snapshot(&mut self) {
+        if self.rewind.capacity == 0 || self.rewind.snapshots.back().map_or(false, |s| s.cycle >= self.cycle_count) {
+            return;
+        }
+        let mut w = MemWriter::new();
+        self.save_state(&mut w).unwrap();
+        if self.rewind.snapshots.len() >= self.rewind.capacity {
+            self.rewind.snapshots.pop_front();
+        }
+        let state = compress(w.get_ref());
+        self.rewind.snapshots.push_back(Snapshot { cycle: self.cycle_count, state: state });
+    }
+
+    fn restore_snapshot(&mut self, snapshot: &Snapshot) {
+        self.load_state(&mut MemReader::new(decompress(snapshot.state.as_slice()))).unwrap();
+        self.cycle_count = snapshot.cycle;
+    }
+
+    // Go back to just before the instruction at the given cycle was executed,
+    // by restoring the last snapshot before it and replaying from there
+    fn replay_to(&mut self, cycle: u64) -> bool {
+        let snapshot = match self.rewind.snapshots.iter().rev().position(|s| s.cycle <= cycle) {
+            Some(pos) => self.rewind.snapshots.len() - 1 - pos,
+            None => return false
+        };
+        let snapshot = self.rewind.snapshots.remove(snapshot).unwrap();
+        self.restore_snapshot(&snapshot);
+        self.forget_snapshots_after(snapshot.cycle);
+        self.rewind.snapshots.push_back(snapshot);
+
+        while self.cycle_count < cycle {
+            if self.clocked_step().is_err() {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Drop snapshots of a future that's been rewound away
+    fn forget_snapshots_after(&mut self, cycle: u64) {
+        while self.rewind.snapshots.back().map_or(false, |s| s.cycle > cycle) {
+            self.rewind.snapshots.pop_back();
+        }
+    }
+
+    // Reverse execution by count instructions
+    fn reverse_step(&mut self, count: u64) -> bool {
+        if count > self.cycle_count {
+            return false;
+        }
+        let target = self.cycle_count - count;
+        self.replay_to(target)
+    }
+
+    // Reverse execution to just before the most recent instruction for which
+    // hit returns true. hit is called before each instruction is replayed
+    // with its pc, and again afterwards with None.
+    fn reverse_until<F: FnMut(&Chip8, Option<u16>) -> bool>(&mut self, mut hit: F) -> bool {
+        let end = self.cycle_count;
+        let mut segment_end = end;
+
+        // search back through one snapshot's worth of instructions at a time
+        for idx in range(0u, self.rewind.snapshots.len()).rev() {
+            let start = self.rewind.snapshots[idx].cycle;
+            if start >= segment_end {
+                continue;
+            }
+            let state = decompress(self.rewind.snapshots[idx].state.as_slice());
+            self.load_state(&mut MemReader::new(state)).unwrap();
+            self.cycle_count = start;
+
+            let mut found = None;
+            while self.cycle_count < segment_end {
+                let before = self.cycle_count;
+                if hit(&*self, Some(self.pc)) {
+                    found = Some(before);
+                }
+                if self.clocked_step().is_err() {
+                    break;
+                }
+                if hit(&*self, None) {
+                    found = Some(before);
+                }
+            }
+
+            match found {
+                Some(cycle) => return self.replay_to(cycle),
+                None => segment_end = start
+            }
+        }
+
+        // nothing found, so go back to where we started
+        self.replay_to(end);
+        false
+    }
+}
+
+// Replace each run of up to 255 zeros with a zero followed by the run length
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0u;
+    while pos < data.len() {
+        if data[pos] != 0 {
+            out.push(data[pos]);
+            pos += 1;
+            continue;
+        }
+        let mut run = 1u;
+        while run < 255 && pos + run < data.len() && data[pos + run] == 0 {
+            run += 1;
+        }
+        out.push(0);
+        out.push(run as u8);
+        pos += run;
+    }
+    out
+}
+
+pub fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0u;
+    while pos < data.len() {
+        if data[pos] != 0 {
+            out.push(data[pos]);
+            pos += 1;
+        } else {
+            out.grow(data[pos + 1] as uint, 0);
+            pos += 2;
+        }
+    }
+    out
+}
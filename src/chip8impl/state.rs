@@ -0,0 +1,176 @@
+use std::io::{File, IoError, IoResult, InvalidInput};
+use std::slice::bytes;
+use frontend::Frontend;
+use super::random::Random;
+use super::{Chip8, Quirks, KEY_COUNT, MEMORY_SIZE, PATTERN_SIZE, PIXEL_COUNT, REGISTER_COUNT,
+            RPL_FLAG_COUNT, STACK_SIZE};
+
+// Save states start with a magic number ("CH8S") and a format version, bump
+// the version whenever the layout below changes
+static STATE_MAGIC : [u8; 4] = [0x43, 0x48, 0x38, 0x53];
+const STATE_VERSION: u8   = 4;
+const STATE_SLOTS:   uint = 10;
+
+// Marks that no FX0A is pending, or that no key has gone down during it
+const NO_WAITKEY: u8 = 0xFF;
+
+fn invalid_state(desc: &'static str) -> IoError {
+    IoError { kind: InvalidInput, desc: desc, detail: None }
+}
+
+// save states and slots
+impl Chip8 {
+
+    // Write a snapshot of the entire machine, including the quirks and speed
+    // it's configured with and the state of the random number generator
+    pub fn save_state(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write(&STATE_MAGIC));
+        try!(w.write_u8(STATE_VERSION));
+
+        try!(w.write_be_u16(self.pc));
+        try!(w.write_be_u16(self.i));
+        try!(w.write_u8(self.sp));
+        for si in range(0u, STACK_SIZE) {
+            try!(w.write_be_u16(self.stack[si]));
+        }
+        try!(w.write(&self.v));
+        try!(w.write_u8(self.delay_timer));
+        try!(w.write_u8(self.sound_timer));
+        // only the memory the quirks allow is kept
+        let memory_size = self.memory_size();
+        try!(w.write_be_u32(memory_size as u32));
+        try!(w.write(self.mem.slice_to(memory_size)));
+        try!(w.write(&self.gfx));
+        try!(w.write(&self.key));
+        try!(w.write_u8(match self.waitkey_reg { Some(vx) => vx as u8, None => NO_WAITKEY }));
+        try!(w.write_u8(self.waitkey_held.unwrap_or(NO_WAITKEY)));
+        try!(w.write_u8(self.hires as u8));
+        try!(w.write_u8(self.halted as u8));
+        try!(w.write(&self.rpl));
+        try!(w.write_u8(self.planes));
+        try!(w.write(&self.pattern));
+        try!(w.write_u8(self.pitch));
+        try!(w.write_be_u32(self.cycles_per_frame as u32));
+        try!(w.write_be_u32(self.frame_cycles as u32));
+        try!(w.write_u8(self.quirks.to_bits()));
+        try!(w.write_be_u32(self.rng.state()));
+        Ok(())
+    }
+
+    // Restore a snapshot written by save_state. The machine is left untouched
+    // if the snapshot can't be read.
+    pub fn load_state(&mut self, r: &mut Reader) -> IoResult<()> {
+        if try!(r.read_exact(STATE_MAGIC.len())).as_slice() != STATE_MAGIC.as_slice() {
+            return Err(invalid_state("not a save state"));
+        }
+        if try!(r.read_u8()) != STATE_VERSION {
+            return Err(invalid_state("unsupported save state version"));
+        }
+
+        let mut state = Chip8::new();
+        state.pc = try!(r.read_be_u16());
+        state.i = try!(r.read_be_u16());
+        state.sp = try!(r.read_u8());
+        if state.sp as uint > STACK_SIZE {
+            return Err(invalid_state("stack pointer out of range"));
+        }
+        for si in range(0u, STACK_SIZE) {
+            state.stack[si] = try!(r.read_be_u16());
+        }
+        bytes::copy_memory(&mut state.v, try!(r.read_exact(REGISTER_COUNT)).as_slice());
+        state.delay_timer = try!(r.read_u8());
+        state.sound_timer = try!(r.read_u8());
+        let memory_size = try!(r.read_be_u32()) as uint;
+        if memory_size > MEMORY_SIZE {
+            return Err(invalid_state("memory size out of range"));
+        }
+        bytes::copy_memory(&mut state.mem, try!(r.read_exact(memory_size)).as_slice());
+        bytes::copy_memory(&mut state.gfx, try!(r.read_exact(PIXEL_COUNT)).as_slice());
+        bytes::copy_memory(&mut state.key, try!(r.read_exact(KEY_COUNT)).as_slice());
+        state.waitkey_reg = match try!(r.read_u8()) {
+            NO_WAITKEY => None,
+            vx if (vx as uint) < REGISTER_COUNT => Some(vx as uint),
+            _ => return Err(invalid_state("invalid key wait register"))
+        };
+        state.waitkey_held = match try!(r.read_u8()) {
+            NO_WAITKEY => None,
+            key if (key as uint) < KEY_COUNT => Some(key),
+            _ => return Err(invalid_state("invalid held key"))
+        };
+        state.hires = try!(r.read_u8()) != 0;
+        state.halted = try!(r.read_u8()) != 0;
+        bytes::copy_memory(&mut state.rpl, try!(r.read_exact(RPL_FLAG_COUNT)).as_slice());
+        state.planes = try!(r.read_u8());
+        bytes::copy_memory(&mut state.pattern, try!(r.read_exact(PATTERN_SIZE)).as_slice());
+        state.pitch = try!(r.read_u8());
+        state.cycles_per_frame = try!(r.read_be_u32()) as uint;
+        state.frame_cycles = try!(r.read_be_u32()) as uint;
+        state.quirks = Quirks::from_bits(try!(r.read_u8()));
+        state.rng = Random::from_state(try!(r.read_be_u32()));
+
+        self.pc = state.pc;
+        self.i = state.i;
+        self.sp = state.sp;
+        self.stack = state.stack;
+        self.v = state.v;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.mem = state.mem;
+        self.gfx = state.gfx;
+        self.key = state.key;
+        self.waitkey_reg = state.waitkey_reg;
+        self.waitkey_held = state.waitkey_held;
+        self.hires = state.hires;
+        self.halted = state.halted;
+        self.rpl = state.rpl;
+        self.planes = state.planes;
+        self.pattern = state.pattern;
+        self.pitch = state.pitch;
+        self.set_cycles_per_frame(state.cycles_per_frame);
+        self.frame_cycles = state.frame_cycles;
+        self.quirks = state.quirks;
+        self.rng = state.rng;
+
+        // have the frontend pick up the restored display and sound
+        self.gfx_update = true;
+        self.pattern_update = true;
+        Ok(())
+    }
+
+    // Keep numbered save state slots in files named after path, e.g.
+    // PONG.state0 for a path of PONG
+    pub fn set_state_path(&mut self, path: Path) {
+        self.state_path = Some(path);
+    }
+
+    fn slot_path(&self) -> Option<Path> {
+        self.state_path.as_ref().map(|path| path.with_extension(format!("state{}", self.state_slot)))
+    }
+
+    fn save_slot(&mut self, frontend: &mut Frontend) {
+        let path = match self.slot_path() {
+            Some(path) => path,
+            None => return
+        };
+        frontend.show_status(match File::create(&path).and_then(|mut file| self.save_state(&mut file)) {
+            Ok(()) => format!("saved state to slot {}", self.state_slot),
+            Err(err) => format!("failed to save state: {}", err)
+        }.as_slice());
+    }
+
+    fn load_slot(&mut self, frontend: &mut Frontend) {
+        let path = match self.slot_path() {
+            Some(path) => path,
+            None => return
+        };
+        frontend.show_status(match File::open(&path).and_then(|mut file| self.load_state(&mut file)) {
+            Ok(()) => format!("loaded state from slot {}", self.state_slot),
+            Err(err) => format!("failed to load state: {}", err)
+        }.as_slice());
+    }
+
+    fn select_slot(&mut self, next: bool, frontend: &mut Frontend) {
+        self.state_slot = (if next { self.state_slot + 1 } else { self.state_slot + STATE_SLOTS - 1 }) % STATE_SLOTS;
+        frontend.show_status(format!("save state slot {}", self.state_slot).as_slice());
+    }
+}
@@ -1,17 +1,58 @@
 extern crate std;
 
 use std::default::Default;
-use super::Chip8;
+use std::io::{File, MemReader, MemWriter, TempDir};
+use super::{Chip8, Chip8Error, Debugger, Quirks, RunExit, RunLimits, StopReason, PIXEL_COUNT};
+use super::{Access, AccessKind, Comparison, Location, Watchpoint, WatchKind};
+use super::{Instruction, InstructionSet, Movie, MovieFrame, disassemble};
+use super::debugger::{Command, Flow, Register};
+use super::rewind;
+use assembler;
+use frontend::{Frontend, HeadlessFrontend, InputEvent};
+
+// copy a program into memory at the usual load address
+fn load_bytes(emu: &mut Chip8, program: &[u8]) {
+    for idx in range(0u, program.len()) {
+        emu.mem[0x200 + idx] = program[idx];
+    }
+}
 
 #[test]
 fn test_stack() {
     let mut emu = Chip8::new();
     for index in range(0u16, 16) {
-        emu.stack_push(index);
+        emu.stack_push(index).unwrap();
     }
     for index in range(16u16, 0) {
-        assert_eq!(emu.stack_pop(), index);
+        assert_eq!(emu.stack_pop().unwrap(), index);
+    }
+}
+
+#[test]
+fn test_stack_errors() {
+    let mut emu = Chip8::new();
+    assert_eq!(emu.stack_pop(), Err(Chip8Error::StackUnderflow));
+
+    for index in range(0u16, 16) {
+        emu.stack_push(index).unwrap();
     }
+    assert_eq!(emu.stack_push(16), Err(Chip8Error::StackOverflow));
+}
+
+#[test]
+fn test_unknown_opcode() {
+    let mut emu = Chip8::new();
+    load_bytes(&mut emu, &[0xE0, 0x00]);
+    assert_eq!(emu.step(), Err(Chip8Error::UnknownOpcode { pc: 0x200, opcode: 0xE000 }));
+}
+
+#[test]
+fn test_memory_out_of_bounds() {
+    let mut emu = Chip8::new();
+    emu.i = 0xFFFE;
+    assert_eq!(emu.execute_storebcd(0), Err(Chip8Error::MemoryOutOfBounds { addr: 0x10000 }));
+    emu.execute_storeregs(1).unwrap();
+    assert_eq!(emu.execute_loadregs(2), Err(Chip8Error::MemoryOutOfBounds { addr: 0x10000 }));
 }
 
 #[test]
@@ -29,7 +70,7 @@ fn test_instr_call() {
     let dst = 0xABC as u16;
     let prev_sp = emu.sp;
 
-    emu.execute_call(dst);
+    emu.execute_call(dst).unwrap();
     assert_eq!(emu.pc, dst);
     assert_eq!(emu.sp, prev_sp + 1);
 }
@@ -115,9 +156,9 @@ fn test_instr_addregister() {
 #[test]
 fn test_instr_clearscreen() {
     let mut emu = Chip8::new();
-    emu.gfx = [1u8; 64 * 32];
+    emu.gfx = [1u8; 128 * 64];
     emu.execute_clearscreen();
-    for idx in range(0u, 64 * 32) {
+    for idx in range(0u, 128 * 64) {
         assert_eq!(emu.gfx[idx], 0);
     }
 }
@@ -127,13 +168,13 @@ fn test_instr_return() {
     let mut emu = Chip8::new();
 
     let pcstart = emu.pc;
-    emu.execute_call(0xABC);
-    emu.execute_call(0xDEF);
+    emu.execute_call(0xABC).unwrap();
+    emu.execute_call(0xDEF).unwrap();
 
-    emu.execute_return();
+    emu.execute_return().unwrap();
     assert_eq!(emu.pc, 0xABC + 2);
 
-    emu.execute_return();
+    emu.execute_return().unwrap();
     assert_eq!(emu.pc, pcstart + 2);
 }
 
@@ -155,14 +196,34 @@ fn test_instr_jumpv0() {
 #[test]
 fn test_instr_setrandand() {
     let mut emu = Chip8::new();
+    emu.set_seed(1234);
     emu.v[0] = 0xF;
     emu.execute_setrandand(0, 0);
     assert_eq!(emu.v[0], 0);
 
-    for idx in range(0u, 10000u) {
-        emu.execute_setrandand(0, 0b11);
-        assert_eq!(emu.v[0] & !(0b11 as u8), 0);
-    }
+    // a seed always gives the same bytes, masked by NN
+    emu.execute_setrandand(0, 0xFF);
+    assert_eq!(emu.v[0], 0x6E);
+    emu.execute_setrandand(0, 0xFF);
+    assert_eq!(emu.v[0], 0xEF);
+    emu.execute_setrandand(1, 0b11);
+    assert_eq!(emu.v[1], 0xC7 & 0b11);
+
+    // and the sequence carries on from a save state
+    let mut w = MemWriter::new();
+    emu.save_state(&mut w).unwrap();
+    let mut loaded = Chip8::new();
+    loaded.set_seed(99);
+    loaded.load_state(&mut MemReader::new(w.into_inner())).unwrap();
+    loaded.execute_setrandand(0, 0xFF);
+    assert_eq!(loaded.v[0], 0xDF);
+
+    // zero is still a usable seed
+    emu.set_seed(0);
+    emu.execute_setrandand(0, 0xFF);
+    assert_eq!(emu.v[0], 0x51);
+    emu.execute_setrandand(0, 0xFF);
+    assert_eq!(emu.v[0], 0xE0);
 }
 
 #[test]
@@ -178,6 +239,64 @@ fn test_instr_loaddtimer() {
     assert_eq!(emu.v[1], emu.delay_timer);
 }
 
+#[test]
+fn test_instr_waitkey() {
+    let mut emu = Chip8::new();
+    let pcstart = emu.pc;
+
+    emu.execute_waitkey(3);
+    assert_eq!(emu.waitkey_reg, Some(3));
+    assert_eq!(emu.pc, pcstart + 2);
+
+    // a key that was already down doesn't complete the wait
+    emu.handle_keypress(0xB, false);
+    assert_eq!(emu.waitkey_reg, Some(3));
+
+    // the first key pressed does once it's released
+    emu.handle_keypress(0xB, true);
+    emu.handle_keypress(0x2, true);
+    assert_eq!(emu.waitkey_reg, Some(3));
+    assert_eq!(emu.key[0xB], 1);
+    emu.handle_keypress(0x2, false);
+    assert_eq!(emu.waitkey_reg, Some(3));
+    emu.handle_keypress(0xB, false);
+    assert_eq!(emu.waitkey_reg, None);
+    assert_eq!(emu.v[3], 0xB);
+
+    // or straight away when pressed, without the quirk
+    emu.quirks.waitkey_on_release = false;
+    emu.execute_waitkey(4);
+    emu.handle_keypress(0x7, false);
+    assert_eq!(emu.waitkey_reg, Some(4));
+    emu.handle_keypress(0x7, true);
+    assert_eq!(emu.waitkey_reg, None);
+    assert_eq!(emu.v[4], 0x7);
+}
+
+#[test]
+fn test_waitkey_keeps_running() {
+    let mut emu = Chip8::new();
+    let mut frontend = HeadlessFrontend::new();
+    // LD V0, K; LD V1, V0; loop {}
+    load_bytes(&mut emu, &[0xF0, 0x0A, 0x81, 0x00, 0x12, 0x04]);
+    emu.delay_timer = 10;
+    emu.set_cycles_per_frame(5);
+
+    // the timers keep counting down while waiting, headless or not
+    let limits = RunLimits { cycles: 20, pc: None, opcode: None };
+    assert_eq!(emu.run_until(&limits, &mut frontend).unwrap(), (20, StopReason::CycleLimit));
+    assert_eq!(emu.pc, 0x202);
+    assert_eq!(emu.delay_timer, 6);
+
+    emu.handle_keypress(0xE, true);
+    emu.run_frame().unwrap();
+    assert_eq!(emu.pc, 0x202);
+    emu.handle_keypress(0xE, false);
+    emu.run_frame().unwrap();
+    assert_eq!(emu.v[1], 0xE);
+    assert_eq!(emu.pc, 0x204);
+}
+
 #[test]
 fn test_instr_setdtimer() {
     let mut emu = Chip8::new();
@@ -208,7 +327,7 @@ fn test_instr_addi() {
 fn test_instr_setifont() {
     let mut emu = Chip8::new();
     emu.v[0] = 0xA;
-    emu.execute_setifont(0);
+    emu.execute_setifont(0).unwrap();
     assert_eq!(emu.i, 0xAu16 * 5);
 }
 
@@ -218,19 +337,19 @@ fn test_instr_storebcd() {
     emu.i = 0x100;
 
     emu.v[0] = 5;
-    emu.execute_storebcd(0);
+    emu.execute_storebcd(0).unwrap();
     assert_eq!(emu.mem[emu.i as uint], 0);
     assert_eq!(emu.mem[emu.i as uint + 1], 0);
     assert_eq!(emu.mem[emu.i as uint + 2], 5);
 
     emu.v[0] = 26;
-    emu.execute_storebcd(0);
+    emu.execute_storebcd(0).unwrap();
     assert_eq!(emu.mem[emu.i as uint], 0);
     assert_eq!(emu.mem[emu.i as uint + 1], 2);
     assert_eq!(emu.mem[emu.i as uint + 2], 6);
 
     emu.v[0] = 137;
-    emu.execute_storebcd(0);
+    emu.execute_storebcd(0).unwrap();
     assert_eq!(emu.mem[emu.i as uint], 1);
     assert_eq!(emu.mem[emu.i as uint + 1], 3);
     assert_eq!(emu.mem[emu.i as uint + 2], 7);
@@ -245,7 +364,7 @@ fn test_instr_storeregs() {
     }
 
     emu.i = 0x100;
-    emu.execute_storeregs(5);
+    emu.execute_storeregs(5).unwrap();
 
     for idx in range(0u, 6u) {
         assert_eq!(emu.mem[0x100 + idx], idx as u8);
@@ -264,7 +383,7 @@ fn test_instr_loadregs() {
     }
 
     emu.i = 0x100;
-    emu.execute_loadregs(5);
+    emu.execute_loadregs(5).unwrap();
 
     for idx in range(0u, 6u) {
         assert_eq!(emu.v[idx], idx as u8);
@@ -352,7 +471,7 @@ fn test_instr_shl() {
     let mut emu = Chip8::new();
 
     emu.v[0] = 0b10000000;
-    emu.execute_shl(0);
+    emu.execute_shl(0, 1);
     assert_eq!(emu.v[0], 0);
     assert_eq!(emu.v[0xF], 1);
 }
@@ -362,7 +481,7 @@ fn test_instr_shr() {
     let mut emu = Chip8::new();
 
     emu.v[0] = 0b1;
-    emu.execute_shr(0);
+    emu.execute_shr(0, 1);
     assert_eq!(emu.v[0], 0);
     assert_eq!(emu.v[0xF], 1);
 }
@@ -394,11 +513,923 @@ fn test_instr_skipifkeypress() {
     let startpc = emu.pc;
     emu.v[0] = 0xA;
     emu.key[0xA] = 1;
-    emu.execute_skipifkeypress(0);
+    emu.execute_skipifkeypress(0).unwrap();
     assert_eq!(emu.pc, startpc + 4);
+
+    // there are only 16 keys
+    emu.v[0] = 0x10;
+    assert_eq!(emu.execute_skipifkeypress(0), Err(Chip8Error::InvalidKey));
 }
 
 #[test]
 fn test_instr_skipifnkeypress() {
     let mut emu = Chip8::new();
 }
+
+#[test]
+fn test_run_until() {
+    let mut emu = Chip8::new();
+    let mut frontend = HeadlessFrontend::new();
+    // V0 = 5; loop { V0 += 1 }
+    load_bytes(&mut emu, &[0x60, 0x05, 0x70, 0x01, 0x12, 0x02]);
+
+    let limits = RunLimits { cycles: 5, pc: None, opcode: None };
+    assert_eq!(emu.run_until(&limits, &mut frontend).unwrap(), (5, StopReason::CycleLimit));
+    assert_eq!(emu.v[0], 7);
+
+    let limits = RunLimits { cycles: 100, pc: Some(0x204), opcode: None };
+    assert_eq!(emu.run_until(&limits, &mut frontend).unwrap(), (1, StopReason::ReachedPc));
+
+    let limits = RunLimits { cycles: 100, pc: None, opcode: Some(0x7001) };
+    assert_eq!(emu.run_until(&limits, &mut frontend).unwrap(), (1, StopReason::ReachedOpcode));
+    assert_eq!(emu.pc, 0x202);
+}
+
+#[test]
+fn test_dump_state() {
+    let mut emu = Chip8::new();
+    emu.v[0xA] = 0x42;
+    emu.gfx[1] = 1;
+
+    let mut w = MemWriter::new();
+    emu.dump_state(&mut w).unwrap();
+    let dump = String::from_utf8(w.into_inner()).unwrap();
+
+    assert!(dump.as_slice().contains("PC: 0x0200"));
+    assert!(dump.as_slice().contains("VA: 0x42"));
+    assert!(dump.as_slice().contains(".#.."));
+}
+
+#[test]
+fn test_run_cycles() {
+    let mut emu = Chip8::new();
+    // V0 = 5; loop { V0 += 1 }
+    load_bytes(&mut emu, &[0x60, 0x05, 0x70, 0x01, 0x12, 0x02]);
+    emu.delay_timer = 10;
+
+    emu.step().unwrap();
+    assert_eq!(emu.v[0], 5);
+
+    emu.run_cycles(4).unwrap();
+    assert_eq!(emu.v[0], 7);
+
+    // executing instructions doesn't touch the timers
+    assert_eq!(emu.delay_timer, 10);
+}
+
+#[test]
+fn test_tick_timers() {
+    let mut emu = Chip8::new();
+    emu.delay_timer = 2;
+    emu.sound_timer = 1;
+    assert!(emu.sound_active());
+
+    emu.tick_timers();
+    assert_eq!(emu.delay_timer, 1);
+    assert_eq!(emu.sound_timer, 0);
+    assert!(!emu.sound_active());
+
+    emu.tick_timers();
+    emu.tick_timers();
+    assert_eq!(emu.delay_timer, 0);
+    assert_eq!(emu.sound_timer, 0);
+}
+
+#[test]
+fn test_run_frame() {
+    let mut emu = Chip8::new();
+    // loop { V0 += 1 }
+    load_bytes(&mut emu, &[0x70, 0x01, 0x12, 0x00]);
+    emu.delay_timer = 10;
+    emu.set_cycles_per_frame(6);
+
+    // timers tick once per frame regardless of the instruction count
+    emu.run_frame().unwrap();
+    assert_eq!(emu.v[0], 3);
+    assert_eq!(emu.delay_timer, 9);
+
+    emu.set_instructions_per_second(1200);
+    assert_eq!(emu.instructions_per_second(), 1200);
+    emu.run_frame().unwrap();
+    assert_eq!(emu.v[0], 13);
+    assert_eq!(emu.delay_timer, 8);
+}
+
+#[test]
+fn test_quirks_presets() {
+    assert_eq!(Quirks::from_name("vip"), Some(Quirks::vip()));
+    assert_eq!(Quirks::from_name("schip"), Some(Quirks::schip()));
+    assert_eq!(Quirks::from_name("eti660"), None);
+    assert!(Quirks::vip().shift_uses_vy);
+    assert!(Quirks::chip48().jump_uses_vx);
+}
+
+#[test]
+fn test_memory_size() {
+    // a ROM that only fits in XO-CHIP's 64K
+    let dir = TempDir::new("rom").unwrap();
+    let path = dir.path().join("BIG.ch8");
+    File::create(&path).write(Vec::from_elem(8192, 0x12u8).as_slice()).unwrap();
+    let filename = path.as_str().unwrap().to_string();
+
+    let mut emu = Chip8::new();
+    emu.set_quirks(Quirks::vip());
+    assert!(emu.load_program(&filename) == Err(Chip8Error::RomTooLarge));
+    emu.set_quirks(Quirks::xochip());
+    assert!(emu.load_program(&filename).is_ok());
+
+    // only the first 4K is addressable, and saved
+    emu.set_quirks(Quirks::chip48());
+    emu.i = 0xFFE;
+    assert!(emu.execute_storebcd(0).is_err());
+    let mut w = MemWriter::new();
+    emu.save_state(&mut w).unwrap();
+    assert!(w.get_ref().len() < 4096 + PIXEL_COUNT + 200);
+    let mut restored = Chip8::new();
+    restored.load_state(&mut MemReader::new(w.into_inner())).unwrap();
+    assert_eq!(restored.quirks(), Quirks::chip48());
+    assert_eq!(restored.mem[0xFFF], 0x12);
+}
+
+#[test]
+fn test_quirk_shift_uses_vy() {
+    let mut emu = Chip8::new();
+    emu.set_quirks(Quirks { shift_uses_vy: true, ..Default::default() });
+
+    emu.v[0] = 0xFF;
+    emu.v[1] = 0b110;
+    emu.execute_shr(0, 1);
+    assert_eq!(emu.v[0], 0b11);
+    assert_eq!(emu.v[0xF], 0);
+
+    emu.execute_shl(0, 1);
+    assert_eq!(emu.v[0], 0b1100);
+    assert_eq!(emu.v[1], 0b110);
+}
+
+#[test]
+fn test_quirk_load_store_increments_i() {
+    let mut emu = Chip8::new();
+    emu.set_quirks(Quirks { load_store_increments_i: true, ..Default::default() });
+
+    emu.i = 0x300;
+    emu.execute_storeregs(3).unwrap();
+    assert_eq!(emu.i, 0x304);
+    emu.execute_loadregs(0).unwrap();
+    assert_eq!(emu.i, 0x305);
+}
+
+#[test]
+fn test_quirk_jump_uses_vx() {
+    let mut emu = Chip8::new();
+    emu.set_quirks(Quirks { jump_uses_vx: true, ..Default::default() });
+
+    emu.v[0] = 0x1;
+    emu.v[3] = 0x4;
+    emu.execute_jumpv0(0x310);
+    assert_eq!(emu.pc, 0x314);
+}
+
+#[test]
+fn test_quirk_logic_resets_vf() {
+    let mut emu = Chip8::new();
+    emu.v[0xF] = 1;
+    emu.execute_bitor(0, 1);
+    assert_eq!(emu.v[0xF], 1);
+
+    emu.set_quirks(Quirks { logic_resets_vf: true, ..Default::default() });
+    emu.execute_bitand(0, 1);
+    assert_eq!(emu.v[0xF], 0);
+}
+
+#[test]
+fn test_quirk_clip_sprites() {
+    // a single row of 8 pixels drawn 4 pixels from the right edge
+    let mut emu = Chip8::new();
+    emu.mem[0x300] = 0xFF;
+    emu.i = 0x300;
+    emu.v[0] = 60;
+    emu.v[1] = 0;
+
+    emu.execute_draw(0, 1, 1).unwrap();
+    assert_eq!(emu.gfx[63], 1);
+    assert_eq!(emu.gfx[0], 1);
+
+    emu.set_quirks(Quirks { clip_sprites: true, ..Default::default() });
+    emu.execute_clearscreen();
+    emu.execute_draw(0, 1, 1).unwrap();
+    assert_eq!(emu.gfx[63], 1);
+    assert_eq!(emu.gfx[0], 0);
+}
+
+#[test]
+fn test_instr_sethires() {
+    let mut emu = Chip8::new();
+    assert_eq!(emu.screen_size(), (64, 32));
+
+    emu.gfx[0] = 1;
+    emu.execute_sethires(true);
+    assert_eq!(emu.screen_size(), (128, 64));
+    assert_eq!(emu.display().len(), 128 * 64);
+    assert_eq!(emu.gfx[0], 0);
+
+    emu.execute_sethires(false);
+    assert_eq!(emu.display().len(), 64 * 32);
+
+    // every plane is cleared, whichever are selected
+    emu.gfx[5] = 3;
+    emu.execute_selectplanes(1);
+    emu.execute_sethires(true);
+    assert_eq!(emu.gfx[5], 0);
+}
+
+#[test]
+fn test_instr_draw_large_sprite() {
+    let mut emu = Chip8::new();
+    emu.execute_sethires(true);
+    for idx in range(0u, 32) {
+        emu.mem[0x300 + idx] = 0xFF;
+    }
+    emu.i = 0x300;
+    emu.v[0] = 100;
+    emu.v[1] = 40;
+
+    emu.execute_draw(0, 1, 0).unwrap();
+    assert_eq!(emu.v[0xF], 0);
+    assert_eq!(emu.gfx[100 + 40 * 128], 1);
+    assert_eq!(emu.gfx[115 + 55 * 128], 1);
+    assert_eq!(emu.gfx[116 + 55 * 128], 0);
+
+    emu.execute_draw(0, 1, 0).unwrap();
+    assert_eq!(emu.v[0xF], 1);
+    assert_eq!(emu.gfx[100 + 40 * 128], 0);
+}
+
+#[test]
+fn test_instr_scroll() {
+    let mut emu = Chip8::new();
+    emu.gfx[5 + 2 * 64] = 1;
+
+    emu.execute_scrolldown(3);
+    assert_eq!(emu.gfx[5 + 5 * 64], 1);
+    assert_eq!(emu.gfx[5 + 2 * 64], 0);
+
+    emu.execute_scrollright();
+    assert_eq!(emu.gfx[9 + 5 * 64], 1);
+    assert_eq!(emu.gfx[5 + 5 * 64], 0);
+
+    emu.execute_scrollleft();
+    emu.execute_scrollleft();
+    assert_eq!(emu.gfx[1 + 5 * 64], 1);
+    assert_eq!(emu.gfx[9 + 5 * 64], 0);
+
+    // pixels scrolled off the edge are lost
+    emu.execute_scrollleft();
+    for idx in range(0u, 64 * 32) {
+        assert_eq!(emu.gfx[idx], 0);
+    }
+}
+
+#[test]
+fn test_instr_exit() {
+    let mut emu = Chip8::new();
+    let mut frontend = HeadlessFrontend::new();
+    // V0 += 1; exit
+    load_bytes(&mut emu, &[0x70, 0x01, 0x00, 0xFD]);
+
+    let limits = RunLimits { cycles: 100, pc: None, opcode: None };
+    assert_eq!(emu.run_until(&limits, &mut frontend).unwrap(), (2, StopReason::Exited));
+    assert!(emu.is_halted());
+    assert_eq!(emu.v[0], 1);
+}
+
+#[test]
+fn test_instr_setibigfont() {
+    let mut emu = Chip8::new();
+    emu.v[0] = 8;
+    emu.execute_setibigfont(0).unwrap();
+    assert_eq!(emu.i, 80 + 8 * 10);
+    assert_eq!(emu.mem[emu.i as uint], 0x3C);
+
+    emu.v[0] = 0xA;
+    assert_eq!(emu.execute_setibigfont(0), Err(Chip8Error::InvalidKey));
+}
+
+#[test]
+fn test_instr_rplflags() {
+    let mut emu = Chip8::new();
+    for idx in range(0u, 8u) {
+        emu.v[idx] = idx as u8 + 1;
+    }
+    emu.execute_storeflags(7);
+
+    for idx in range(0u, 8u) {
+        emu.v[idx] = 0;
+    }
+    emu.execute_loadflags(3);
+    assert_eq!(emu.v[3], 4);
+    assert_eq!(emu.v[4], 0);
+}
+
+#[test]
+fn test_instr_setilong() {
+    let mut emu = Chip8::new();
+    // I = 0xABCD; V0 = 1
+    load_bytes(&mut emu, &[0xF0, 0x00, 0xAB, 0xCD, 0x60, 0x01]);
+    emu.step().unwrap();
+    assert_eq!(emu.i, 0xABCD);
+    assert_eq!(emu.pc, 0x204);
+}
+
+#[test]
+fn test_skip_over_long_instruction() {
+    let mut emu = Chip8::new();
+    // skip if V0 == 0; I = 0xABCD; V1 = 1
+    load_bytes(&mut emu, &[0x30, 0x00, 0xF0, 0x00, 0xAB, 0xCD, 0x61, 0x01]);
+    emu.step().unwrap();
+    assert_eq!(emu.pc, 0x206);
+}
+
+#[test]
+fn test_instr_draw_planes() {
+    let mut emu = Chip8::new();
+    emu.mem[0x300] = 0x80;
+    emu.mem[0x301] = 0xC0;
+    emu.i = 0x300;
+
+    // plane 2 alone uses the first byte of sprite data
+    emu.execute_selectplanes(2);
+    emu.execute_draw(0, 1, 1).unwrap();
+    assert_eq!(emu.gfx[0], 2);
+    assert_eq!(emu.gfx[1], 0);
+
+    // both planes consume one row each, plane 1 first
+    emu.execute_selectplanes(3);
+    emu.execute_draw(0, 1, 1).unwrap();
+    assert_eq!(emu.v[0xF], 1);
+    assert_eq!(emu.gfx[0], 1);
+    assert_eq!(emu.gfx[1], 2);
+
+    // clearing only affects the selected planes
+    emu.execute_selectplanes(1);
+    emu.execute_clearscreen();
+    assert_eq!(emu.gfx[0], 0);
+    assert_eq!(emu.gfx[1], 2);
+}
+
+#[test]
+fn test_instr_storerange_loadrange() {
+    let mut emu = Chip8::new();
+    for idx in range(0u, 16u) {
+        emu.v[idx] = idx as u8;
+    }
+    emu.i = 0x300;
+
+    emu.execute_storerange(2, 4).unwrap();
+    assert_eq!(emu.mem.slice(0x300, 0x303), [2u8, 3, 4].as_slice());
+
+    // a descending range is stored in reverse
+    emu.execute_storerange(6, 5).unwrap();
+    assert_eq!(emu.mem.slice(0x300, 0x302), [6u8, 5].as_slice());
+    assert_eq!(emu.i, 0x300);
+
+    emu.execute_loadrange(8, 9).unwrap();
+    assert_eq!(emu.v[8], 6);
+    assert_eq!(emu.v[9], 5);
+}
+
+#[test]
+fn test_instr_audio_pattern() {
+    let mut emu = Chip8::new();
+    for idx in range(0u, 16u) {
+        emu.mem[0x300 + idx] = 0xF0;
+    }
+    emu.i = 0x300;
+    emu.execute_loadpattern().unwrap();
+    assert_eq!(emu.pattern[15], 0xF0);
+    assert!(emu.pattern_update);
+
+    emu.v[0] = 112;
+    emu.execute_setpitch(0);
+    assert_eq!(emu.pattern_rate(), 8000.0);
+}
+
+#[test]
+fn test_debugger_parse_command() {
+    assert_eq!(Debugger::parse_command("step"), Ok(Command::Step(1)));
+    assert_eq!(Debugger::parse_command("s 10"), Ok(Command::Step(10)));
+    assert_eq!(Debugger::parse_command("continue"), Ok(Command::Continue));
+    assert_eq!(Debugger::parse_command("break 0x2A4"), Ok(Command::Break(0x2A4)));
+    assert_eq!(Debugger::parse_command("delete"), Ok(Command::Delete(None)));
+    assert_eq!(Debugger::parse_command("delete 512"), Ok(Command::Delete(Some(0x200))));
+    assert_eq!(Debugger::parse_command("mem 0x300 32"), Ok(Command::Mem(0x300, 32)));
+    assert_eq!(Debugger::parse_command("set v3 0x10"), Ok(Command::Set(Register::V(3), 0x10)));
+    assert_eq!(Debugger::parse_command("set VF 1"), Ok(Command::Set(Register::V(0xF), 1)));
+    assert_eq!(Debugger::parse_command("set i 0x300"), Ok(Command::Set(Register::I, 0x300)));
+    assert!(Debugger::parse_command("set vg 1").is_err());
+    assert_eq!(Debugger::parse_command("set sp 16"), Ok(Command::Set(Register::Sp, 16)));
+    assert_eq!(Debugger::parse_command("set sp 40"), Err("stack pointer out of range".to_string()));
+    assert!(Debugger::parse_command("break").is_err());
+    assert!(Debugger::parse_command("mem zz 1").is_err());
+    assert!(Debugger::parse_command("frobnicate").is_err());
+}
+
+#[test]
+fn test_debugger_execute() {
+    let mut emu = Chip8::new();
+    let mut frontend = HeadlessFrontend::new();
+    let mut debugger = Debugger::new();
+    // V0 = 5; loop { V0 += 1 }
+    load_bytes(&mut emu, &[0x60, 0x05, 0x70, 0x01, 0x12, 0x02]);
+
+    assert_eq!(debugger.execute(&mut emu, &mut frontend, Command::Step(2)), Flow::Prompt);
+    assert_eq!(emu.pc, 0x204);
+    assert_eq!(emu.v[0], 6);
+
+    debugger.execute(&mut emu, &mut frontend, Command::Set(Register::V(0), 0x20));
+    assert_eq!(emu.v[0], 0x20);
+
+    debugger.execute(&mut emu, &mut frontend, Command::Break(0x202));
+    debugger.execute(&mut emu, &mut frontend, Command::Break(0x204));
+    debugger.execute(&mut emu, &mut frontend, Command::Break(0x204));
+    assert_eq!(debugger.breakpoints(), [0x202u16, 0x204].as_slice());
+
+    // continue runs until the next breakpoint
+    debugger.execute(&mut emu, &mut frontend, Command::Continue);
+    assert_eq!(emu.pc, 0x202);
+    assert_eq!(emu.v[0], 0x20);
+    debugger.execute(&mut emu, &mut frontend, Command::Continue);
+    assert_eq!(emu.pc, 0x204);
+    assert_eq!(emu.v[0], 0x21);
+
+    debugger.execute(&mut emu, &mut frontend, Command::Delete(Some(0x202)));
+    assert_eq!(debugger.breakpoints(), [0x204u16].as_slice());
+    debugger.execute(&mut emu, &mut frontend, Command::Delete(None));
+    assert!(debugger.breakpoints().is_empty());
+
+    assert_eq!(debugger.execute(&mut emu, &mut frontend, Command::Quit), Flow::Quit);
+}
+
+#[test]
+fn test_debugger_stops_on_error() {
+    let mut emu = Chip8::new();
+    let mut frontend = HeadlessFrontend::new();
+    let mut debugger = Debugger::new();
+    // return with nothing on the stack
+    load_bytes(&mut emu, &[0x00, 0xEE]);
+
+    assert_eq!(debugger.execute(&mut emu, &mut frontend, Command::Step(1)), Flow::Prompt);
+    assert_eq!(emu.pc, 0x200);
+    assert_eq!(debugger.execute(&mut emu, &mut frontend, Command::Continue), Flow::Prompt);
+    assert_eq!(emu.pc, 0x200);
+}
+
+// Collects the debugger's output instead of printing it
+struct OutputFrontend {
+    headless : HeadlessFrontend,
+    output   : Vec<String>
+}
+
+impl Frontend for OutputFrontend {
+
+    fn present(&mut self, gfx: &[u8], width: uint, height: uint) {
+        self.headless.present(gfx, width, height);
+    }
+
+    fn poll_input(&mut self) -> InputEvent {
+        self.headless.poll_input()
+    }
+
+    fn set_beep(&mut self, on: bool) {
+        self.headless.set_beep(on);
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8], rate: f32) {
+        self.headless.set_pattern(pattern, rate);
+    }
+
+    fn time_ns(&self) -> u64 {
+        self.headless.time_ns()
+    }
+
+    fn sleep_ms(&mut self, ms: u64) {
+        self.headless.sleep_ms(ms);
+    }
+
+    fn write_output(&mut self, text: &str) {
+        self.output.push(text.to_string());
+    }
+}
+
+#[test]
+fn test_debugger_output() {
+    let mut emu = Chip8::new();
+    let mut frontend = OutputFrontend { headless: HeadlessFrontend::new(), output: Vec::new() };
+    let mut debugger = Debugger::new();
+    // return with nothing on the stack
+    load_bytes(&mut emu, &[0x00, 0xEE]);
+
+    // everything the debugger prints goes through the frontend
+    debugger.execute(&mut emu, &mut frontend, Command::Break(0x204));
+    assert_eq!(frontend.output, vec!["breakpoint set at 0204".to_string()]);
+    debugger.execute(&mut emu, &mut frontend, Command::Step(1));
+    assert!(frontend.output[1].as_slice().starts_with("emulation halted: "));
+    assert!(frontend.output[2].as_slice().starts_with("0200"));
+}
+
+#[test]
+fn test_debugger_format() {
+    let mut emu = Chip8::new();
+    emu.v[0xA] = 0x42;
+    emu.stack_push(0x2A4).unwrap();
+    emu.mem[0x300] = 0xAB;
+
+    assert!(Debugger::format_regs(&emu).as_slice().contains("VA: 42"));
+    assert_eq!(Debugger::format_stack(&emu).as_slice(), "#0: 02A4");
+    assert_eq!(Debugger::format_mem(&emu, 0x300, 2).as_slice(), "0300: AB 00");
+    assert_eq!(Debugger::format_mem(&emu, 0x300, 17).as_slice().lines().count(), 2);
+}
+
+#[test]
+fn test_access_tracing() {
+    let mut emu = Chip8::new();
+    // V1 = 7; [I] = BCD(V1)
+    load_bytes(&mut emu, &[0x61, 0x07, 0xF1, 0x33]);
+    emu.i = 0x300;
+
+    emu.step().unwrap();
+    assert!(emu.accesses.is_empty());
+
+    emu.tracing = true;
+    emu.step().unwrap();
+    assert!(emu.accesses.contains(&Access { location: Location::V(1), kind: AccessKind::Read }));
+    assert!(emu.accesses.contains(&Access { location: Location::I, kind: AccessKind::Read }));
+    assert!(emu.accesses.contains(&Access { location: Location::Mem(0x302), kind: AccessKind::Write }));
+    assert!(!emu.accesses.contains(&Access { location: Location::Mem(0x303), kind: AccessKind::Write }));
+}
+
+#[test]
+fn test_watchpoints() {
+    let mut emu = Chip8::new();
+    // V5 = 0x1E; loop { V5 += 1; V0 = [I] }
+    load_bytes(&mut emu, &[0x65, 0x1E, 0x75, 0x01, 0xF0, 0x65, 0x12, 0x02]);
+    emu.i = 0x300;
+    emu.tracing = true;
+
+    let write_v5 = Watchpoint { location: Location::V(5), kind: WatchKind::Write, condition: None };
+    let read_v5 = Watchpoint { location: Location::V(5), kind: WatchKind::Read, condition: None };
+    let read_mem = Watchpoint { location: Location::Mem(0x300), kind: WatchKind::ReadWrite, condition: None };
+    let v5_is_20 = Watchpoint { location: Location::V(5), kind: WatchKind::Write,
+                                condition: Some((Comparison::Eq, 0x20)) };
+
+    emu.step().unwrap();
+    assert!(write_v5.triggered(&emu));
+    assert!(!read_v5.triggered(&emu));
+    assert!(!v5_is_20.triggered(&emu));
+
+    emu.step().unwrap();
+    assert!(read_v5.triggered(&emu));
+    assert!(!v5_is_20.triggered(&emu));
+
+    emu.step().unwrap();
+    assert!(read_mem.triggered(&emu));
+    assert!(!write_v5.triggered(&emu));
+
+    // the run loop stops straight after the instruction that set V5 to 0x20
+    emu.set_cycles_per_frame(100);
+    assert_eq!(emu.run_frame_until(&[], &[v5_is_20]), Ok(Some(RunExit::Watchpoint(v5_is_20))));
+    assert_eq!(emu.v[5], 0x20);
+    assert_eq!(emu.pc, 0x204);
+}
+
+#[test]
+fn test_watchpoints_outside_instructions() {
+    let mut emu = Chip8::new();
+    // V3 = key; loop {}
+    load_bytes(&mut emu, &[0xF3, 0x0A, 0x12, 0x02]);
+    emu.set_quirks(Quirks { waitkey_on_release: false, ..Default::default() });
+    emu.tracing = true;
+
+    let dt_is_0 = Watchpoint { location: Location::DelayTimer, kind: WatchKind::Write,
+                               condition: Some((Comparison::Eq, 0)) };
+    let write_v3 = Watchpoint { location: Location::V(3), kind: WatchKind::Write, condition: None };
+
+    // the timers tick down at the end of a frame
+    emu.delay_timer = 1;
+    emu.step().unwrap();
+    assert!(!dt_is_0.triggered(&emu));
+    emu.tick_timers();
+    assert!(dt_is_0.triggered(&emu));
+
+    // a key completing FX0A is reported with the next step
+    emu.step().unwrap();
+    assert!(!write_v3.triggered(&emu));
+    emu.handle_keypress(7, true);
+    emu.step().unwrap();
+    assert!(write_v3.triggered(&emu));
+    assert_eq!(emu.v[3], 7);
+    emu.step().unwrap();
+    assert!(!write_v3.triggered(&emu));
+}
+
+#[test]
+fn test_debugger_watch() {
+    let v5_is_20 = Watchpoint { location: Location::V(5), kind: WatchKind::Write,
+                                condition: Some((Comparison::Eq, 0x20)) };
+    assert_eq!(Debugger::parse_command("watch v5 == 0x20"), Ok(Command::Watch(v5_is_20)));
+    assert_eq!(Debugger::parse_command("rwatch 0x300"),
+               Ok(Command::Watch(Watchpoint { location: Location::Mem(0x300), kind: WatchKind::Read,
+                                              condition: None })));
+    assert_eq!(Debugger::parse_command("awatch dt"),
+               Ok(Command::Watch(Watchpoint { location: Location::DelayTimer, kind: WatchKind::ReadWrite,
+                                              condition: None })));
+    assert_eq!(Debugger::parse_command("unwatch i"), Ok(Command::Unwatch(Location::I)));
+    assert_eq!(Debugger::parse_command("watch"), Ok(Command::Watches));
+    assert!(Debugger::parse_command("watch pc").is_err());
+    assert!(Debugger::parse_command("watch v5 =~ 1").is_err());
+
+    let mut emu = Chip8::new();
+    let mut frontend = HeadlessFrontend::new();
+    let mut debugger = Debugger::new();
+    // V5 = 0x1E; loop { V5 += 1 }
+    load_bytes(&mut emu, &[0x65, 0x1E, 0x75, 0x01, 0x12, 0x02]);
+
+    debugger.execute(&mut emu, &mut frontend, Command::Watch(v5_is_20));
+    assert_eq!(debugger.watchpoints(), [v5_is_20].as_slice());
+
+    // stepping stops early when a watchpoint triggers
+    debugger.execute(&mut emu, &mut frontend, Command::Step(100));
+    assert_eq!(emu.v[5], 0x20);
+    assert_eq!(emu.pc, 0x204);
+
+    debugger.execute(&mut emu, &mut frontend, Command::Unwatch(Location::V(5)));
+    assert!(debugger.watchpoints().is_empty());
+    assert_eq!(Debugger::format_watchpoint(&v5_is_20).as_slice(), "V5 == 0x20");
+}
+
+#[test]
+fn test_decode() {
+    assert_eq!(Instruction::decode(0x6310, InstructionSet::Chip8), Some(Instruction::SetRegisterConst(3, 0x10)));
+    assert_eq!(Instruction::decode(0xD015, InstructionSet::Chip8), Some(Instruction::Draw(0, 1, 5)));
+    assert_eq!(Instruction::decode(0x8126, InstructionSet::Chip8), Some(Instruction::Shr(1, 2)));
+    assert_eq!(Instruction::decode(0x8128, InstructionSet::XoChip), None);
+
+    // the extensions are only decoded when selected
+    assert_eq!(Instruction::decode(0x00FF, InstructionSet::Chip8), None);
+    assert_eq!(Instruction::decode(0x00FF, InstructionSet::SuperChip), Some(Instruction::SetHires(true)));
+    assert_eq!(Instruction::decode(0xF000, InstructionSet::SuperChip), None);
+    assert_eq!(Instruction::decode(0xF000, InstructionSet::XoChip), Some(Instruction::SetILong));
+
+    assert_eq!(format!("{}", Instruction::SetRegisterConst(3, 0x10)).as_slice(), "LD V3, 0x10");
+    assert_eq!(format!("{}", Instruction::Draw(0, 1, 5)).as_slice(), "DRW V0, V1, 5");
+    assert_eq!(format!("{}", Instruction::Call(0x2A4)).as_slice(), "CALL 0x2A4");
+}
+
+#[test]
+fn test_disassemble() {
+    // CALL 0x208; SE V0, 0; JP 0x206; JP 0x206; sub: LD I, 0x20C; RET; sprite
+    let rom = [0x22, 0x08, 0x30, 0x00, 0x12, 0x06, 0x12, 0x06, 0xA2, 0x0C, 0x00, 0xEE, 0xF0, 0x90];
+    let mut w = MemWriter::new();
+    disassemble(&rom, InstructionSet::Chip8, &mut w).unwrap();
+    let listing = String::from_utf8(w.into_inner()).unwrap();
+    let lines: Vec<&str> = listing.as_slice().lines().collect();
+
+    assert_eq!(lines, vec!["0200: 2208       CALL 0x208",
+                           "0202: 3000       SE V0, 0x00",
+                           "0204: 1206       JP 0x206",
+                           "L206:",
+                           "0206: 1206       JP 0x206",
+                           "L208:",
+                           "0208: A20C       LD I, 0x20C",
+                           "020A: 00EE       RET",
+                           "020C: F090       DB 0xF0, 0x90"]);
+}
+
+#[test]
+fn test_disassemble_extensions() {
+    // LD I, long 0x0300 is only code when XO-CHIP is selected
+    let rom = [0xF0, 0x00, 0x03, 0x00, 0x00, 0xFD];
+    let mut w = MemWriter::new();
+    disassemble(&rom, InstructionSet::XoChip, &mut w).unwrap();
+    let listing = String::from_utf8(w.into_inner()).unwrap();
+    assert!(listing.as_slice().contains("0200: F000 0300  LD I, long 0x0300"));
+    assert!(listing.as_slice().contains("0204: 00FD       EXIT"));
+
+    let mut w = MemWriter::new();
+    disassemble(&rom, InstructionSet::Chip8, &mut w).unwrap();
+    let listing = String::from_utf8(w.into_inner()).unwrap();
+    assert!(listing.as_slice().starts_with("0200: F000       DB 0xF0, 0x00"));
+}
+
+#[test]
+fn test_debugger_source_map() {
+    let program = assembler::assemble(": main\n  v0 := 5\n  loop again").unwrap();
+    let mut debugger = Debugger::new();
+    assert_eq!(debugger.format_source(0x202), None);
+
+    debugger.set_source_map(program.source_map);
+    assert_eq!(debugger.format_source(0x202), Some("line 2: v0 := 5".to_string()));
+    assert_eq!(debugger.format_source(0x204), Some("line 3: loop again".to_string()));
+}
+
+#[test]
+fn test_save_load_state() {
+    let mut emu = Chip8::new();
+    // V0 = 5; CALL 0x208; ...; sub: V0 += 1
+    load_bytes(&mut emu, &[0x60, 0x05, 0x22, 0x08, 0x00, 0x00, 0x00, 0x00, 0x70, 0x01]);
+    emu.set_quirks(Quirks::vip());
+    emu.set_cycles_per_frame(20);
+    emu.run_cycles(2).unwrap();
+    emu.delay_timer = 30;
+    emu.gfx[100] = 3;
+    emu.handle_keypress(0xA, true);
+
+    let mut w = MemWriter::new();
+    emu.save_state(&mut w).unwrap();
+    let state = w.into_inner();
+
+    // restoring into a fresh machine resumes from exactly the same point
+    let mut restored = Chip8::new();
+    restored.load_state(&mut MemReader::new(state.clone())).unwrap();
+    assert_eq!(restored.pc, 0x208);
+    assert_eq!(restored.sp, 1);
+    assert_eq!(restored.stack[0], 0x202);
+    assert_eq!(restored.v[0], 5);
+    assert_eq!(restored.delay_timer, 30);
+    assert_eq!(restored.gfx[100], 3);
+    assert_eq!(restored.key[0xA], 1);
+    assert_eq!(restored.quirks(), Quirks::vip());
+    assert_eq!(restored.instructions_per_second(), 20 * 60);
+    restored.step().unwrap();
+    assert_eq!(restored.v[0], 6);
+
+    // anything else is rejected, leaving the machine alone
+    assert!(restored.load_state(&mut MemReader::new(vec![0x00, 0x01, 0x02])).is_err());
+    let mut truncated = state.clone();
+    truncated.truncate(100);
+    assert!(restored.load_state(&mut MemReader::new(truncated)).is_err());
+    assert_eq!(restored.v[0], 6);
+}
+
+#[test]
+fn test_movie_record_and_play() {
+    // LD V0, K; loop { RND V1, 0xFF; if V0 pressed { V2 += 1 } }
+    let program = [0xF0, 0x0A, 0xC1, 0xFF, 0xE0, 0x9E, 0x12, 0x02, 0x72, 0x01, 0x12, 0x02];
+    let mut emu = Chip8::new();
+    load_bytes(&mut emu, &program);
+    emu.set_seed(7);
+    emu.set_quirks(Quirks::schip());
+    emu.set_cycles_per_frame(4);
+    emu.start_recording();
+
+    emu.run_frame().unwrap();
+    // two keys go down between frames, FX0A takes the first once it's up
+    emu.handle_keypress(0x5, true);
+    emu.handle_keypress(0x3, true);
+    emu.run_frame().unwrap();
+    emu.run_frame().unwrap();
+    emu.handle_keypress(0x5, false);
+    emu.run_frame().unwrap();
+    assert_eq!(emu.v[0], 5);
+
+    let movie = emu.stop_recording().unwrap();
+    assert!(emu.stop_recording().is_none());
+    assert_eq!(movie.frames.len(), 4);
+    assert_eq!(movie.frames[1], MovieFrame { keys: 0x0028, waitkey: None });
+    assert_eq!(movie.frames[3], MovieFrame { keys: 0x0008, waitkey: Some(5) });
+
+    // movies survive a round trip through a file
+    let mut w = MemWriter::new();
+    movie.write(&mut w).unwrap();
+    let movie = Movie::read(&mut MemReader::new(w.into_inner())).unwrap();
+    assert!(Movie::read(&mut MemReader::new(vec![0x00, 0x01])).is_err());
+
+    // playing it back reproduces the run exactly, seed and quirks included
+    let mut replay = Chip8::new();
+    let mut frontend = HeadlessFrontend::new();
+    load_bytes(&mut replay, &program);
+    replay.play_movie(movie);
+    assert!(replay.movie_playing());
+    let limits = RunLimits { cycles: 1000, pc: None, opcode: None };
+    assert_eq!(replay.run_until(&limits, &mut frontend).unwrap(), (16, StopReason::MovieEnded));
+    assert_eq!(replay.quirks(), Quirks::schip());
+    assert_eq!(replay.pc, emu.pc);
+    assert_eq!(replay.v.as_slice(), emu.v.as_slice());
+    assert_eq!(replay.key.as_slice(), emu.key.as_slice());
+}
+
+#[test]
+fn test_movie_latches_keys() {
+    // loop { if V0 pressed { V2 += 1 } }
+    let mut emu = Chip8::new();
+    load_bytes(&mut emu, &[0xE0, 0x9E, 0x12, 0x00, 0x72, 0x01, 0x12, 0x00]);
+    emu.set_cycles_per_frame(4);
+    emu.start_recording();
+
+    // a key pressed mid-frame, say while stepping in the debugger, waits
+    // for the next frame, the first one the movie can give it to
+    emu.run_frame().unwrap();
+    emu.clocked_step().unwrap();
+    emu.handle_keypress(0x0, true);
+    assert_eq!(emu.key[0], 0);
+    for _ in range(0u, 3) {
+        emu.clocked_step().unwrap();
+    }
+    assert_eq!(emu.v[2], 0);
+    emu.clocked_step().unwrap();
+    assert_eq!(emu.key[0], 1);
+
+    let movie = emu.stop_recording().unwrap();
+    assert_eq!(movie.frames[1].keys, 0);
+    assert_eq!(movie.frames[2].keys, 1);
+}
+
+#[test]
+fn test_rewind_frame() {
+    let mut emu = Chip8::new();
+    // loop { V0 += 1 }
+    load_bytes(&mut emu, &[0x70, 0x01, 0x12, 0x00]);
+    emu.set_cycles_per_frame(4);
+    emu.set_rewind_frames(2);
+    for _ in range(0u, 3) {
+        emu.run_frame().unwrap();
+    }
+    assert_eq!(emu.v[0], 6);
+
+    // only the last two frames are kept
+    assert!(emu.rewind_frame());
+    assert_eq!(emu.v[0], 4);
+    assert_eq!(emu.cycle_count(), 8);
+    assert!(emu.rewind_frame());
+    assert_eq!(emu.v[0], 2);
+    assert!(!emu.rewind_frame());
+
+    // running again carries on from the rewound point
+    emu.run_frame().unwrap();
+    assert_eq!(emu.v[0], 4);
+}
+
+#[test]
+fn test_rewind_compression() {
+    let mut emu = Chip8::new();
+    load_bytes(&mut emu, &[0x70, 0x01, 0x12, 0x00]);
+    let mut w = MemWriter::new();
+    emu.save_state(&mut w).unwrap();
+
+    // a state is mostly zeros, and comes back unchanged
+    let state = w.into_inner();
+    let packed = rewind::compress(state.as_slice());
+    assert!(packed.len() < state.len() / 20);
+    assert_eq!(rewind::decompress(packed.as_slice()), state);
+
+    let data = [0u8, 0, 5, 0, 7, 7];
+    assert_eq!(rewind::compress(&data), vec![0, 2, 5, 0, 1, 7, 7]);
+    let zeros = Vec::from_elem(600, 0u8);
+    assert_eq!(rewind::decompress(rewind::compress(zeros.as_slice()).as_slice()), zeros);
+}
+
+#[test]
+fn test_debugger_reverse() {
+    let mut emu = Chip8::new();
+    let mut frontend = HeadlessFrontend::new();
+    let mut debugger = Debugger::new();
+    // V1 = 0; loop { V0 += 1; V1 += 2; V2 = V0 }
+    load_bytes(&mut emu, &[0x61, 0x00, 0x70, 0x01, 0x71, 0x02, 0x82, 0x00, 0x12, 0x02]);
+    emu.set_cycles_per_frame(3);
+    emu.set_rewind_frames(100);
+
+    debugger.execute(&mut emu, &mut frontend, Command::Step(11));
+    assert_eq!(emu.pc, 0x206);
+    assert_eq!((emu.v[0], emu.v[1], emu.v[2]), (3, 6, 2));
+
+    assert_eq!(Debugger::parse_command("rs 2"), Ok(Command::ReverseStep(2)));
+    assert_eq!(Debugger::parse_command("reverse-continue v1"), Ok(Command::ReverseContinue(Some(Location::V(1)))));
+
+    debugger.execute(&mut emu, &mut frontend, Command::ReverseStep(2));
+    assert_eq!(emu.cycle_count(), 9);
+    assert_eq!(emu.pc, 0x202);
+    assert_eq!((emu.v[0], emu.v[1]), (2, 4));
+
+    // back to just before the last write to V2
+    debugger.execute(&mut emu, &mut frontend, Command::ReverseContinue(Some(Location::V(2))));
+    assert_eq!(emu.pc, 0x206);
+    assert_eq!((emu.v[0], emu.v[2]), (2, 1));
+
+    // and to the last time the breakpoint was reached
+    debugger.execute(&mut emu, &mut frontend, Command::Break(0x204));
+    debugger.execute(&mut emu, &mut frontend, Command::ReverseContinue(None));
+    assert_eq!(emu.pc, 0x204);
+    assert_eq!(emu.v[1], 2);
+
+    // stepping forward again replays the same execution
+    debugger.execute(&mut emu, &mut frontend, Command::Step(3));
+    assert_eq!((emu.v[0], emu.v[1], emu.v[2]), (2, 4, 2));
+
+    // replaying can't be recorded into a movie
+    emu.start_recording();
+    debugger.execute(&mut emu, &mut frontend, Command::ReverseStep(2));
+    debugger.execute(&mut emu, &mut frontend, Command::ReverseContinue(None));
+    assert_eq!(emu.cycle_count(), 9);
+    assert_eq!(emu.pc, 0x202);
+    assert_eq!(emu.stop_recording().unwrap().frames.len(), 0);
+}
@@ -0,0 +1,84 @@
+use super::Chip8;
+
+// Machine state that instructions report accesses to
+#[derive(Copy, Show, PartialEq)]
+pub enum Location {
+    Mem(u16),
+    V(uint),
+    I,
+    DelayTimer,
+    SoundTimer
+}
+
+#[derive(Copy, Show, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write
+}
+
+// A single read or write made by the instruction last executed
+#[derive(Copy, Show, PartialEq)]
+pub struct Access {
+    pub location : Location,
+    pub kind     : AccessKind
+}
+
+// Which kinds of access a watchpoint triggers on
+#[derive(Copy, Show, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite
+}
+
+#[derive(Copy, Show, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
+
+// Stops execution after an instruction accesses a location. A watchpoint
+// with a condition only stops once a write leaves the condition holding.
+#[derive(Copy, Show, PartialEq)]
+pub struct Watchpoint {
+    pub location  : Location,
+    pub kind      : WatchKind,
+    pub condition : Option<(Comparison, u16)>
+}
+
+impl Comparison {
+
+    pub fn holds(&self, lhs: u16, rhs: u16) -> bool {
+        match *self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs
+        }
+    }
+}
+
+impl Watchpoint {
+
+    pub fn triggered(&self, emu: &Chip8) -> bool {
+        let accessed = emu.accesses.iter().any(|access| {
+            access.location == self.location && match (self.kind, access.kind) {
+                (WatchKind::ReadWrite, _) => true,
+                (WatchKind::Read, AccessKind::Read) => true,
+                (WatchKind::Write, AccessKind::Write) => true,
+                _ => false
+            }
+        });
+
+        match self.condition {
+            Some((cmp, val)) => accessed && cmp.holds(emu.location_value(self.location), val),
+            None => accessed
+        }
+    }
+}
@@ -0,0 +1,241 @@
+extern crate toml;
+
+use std::default::Default;
+use std::fmt;
+use std::num;
+use frontend::{DisplayConfig, KeyMap};
+use frontend::display::{mod, Palette};
+
+#[cfg(test)]
+mod tests;
+
+// A problem with the contents of a config file
+#[derive(PartialEq)]
+pub struct ConfigError {
+    pub message : String
+}
+
+impl fmt::Show for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+fn config_error(message: String) -> ConfigError {
+    ConfigError { message: message }
+}
+
+// Settings read from a TOML config file. Everything is optional, and sections
+// under [rom.NAME] override the top level settings for the ROM whose file is
+// called NAME, ignoring any extension:
+//
+//     layout = "azerty"
+//     theme = "amber"
+//     background = "#101010"
+//     scale = "fit"
+//
+//     [keys]
+//     5 = ["z", "up"]
+//
+//     [rom.PONG.keys]
+//     1 = "up"
+//     4 = "down"
+//
+//     [rom.PONG.gamepad]
+//     c = "button0"
+pub struct Config {
+    table : toml::Table
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config { table: toml::Table::new() }
+    }
+}
+
+impl Config {
+
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut parser = toml::Parser::new(text);
+        match parser.parse() {
+            Some(table) => Ok(Config { table: table }),
+            None => {
+                let err = &parser.errors[0];
+                let (line, _) = parser.to_linecol(err.lo);
+                Err(config_error(format!("line {}: {}", line + 1, err.desc)))
+            }
+        }
+    }
+
+    // The keypad mapping for a ROM, starting from the layout picked in the
+    // config or on the command line and then applying the [keys] bindings
+    pub fn keymap(&self, rom: &str, layout: Option<&str>) -> Result<KeyMap, ConfigError> {
+        let sections = self.sections(rom);
+
+        let mut name = layout.map(|name| name.to_string());
+        if name.is_none() {
+            for section in sections.iter() {
+                match section.get("layout") {
+                    Some(value) => name = Some(try!(as_str(value, "layout")).to_string()),
+                    None => {}
+                }
+            }
+        }
+        let mut keymap = match name {
+            Some(name) => match KeyMap::layout(name.as_slice()) {
+                Some(keymap) => keymap,
+                None => return Err(config_error(format!("unknown keyboard layout: {}", name)))
+            },
+            None => Default::default()
+        };
+
+        try!(bind_keys(&mut keymap, sections.as_slice(), "keys"));
+        Ok(keymap)
+    }
+
+    // The gamepad mapping for a ROM, the built-in profile for it with the
+    // [gamepad] bindings applied
+    pub fn gamepad(&self, rom: &str) -> Result<KeyMap, ConfigError> {
+        let mut keymap = KeyMap::gamepad(rom_name(rom).as_slice());
+        try!(bind_keys(&mut keymap, self.sections(rom).as_slice(), "gamepad"));
+        Ok(keymap)
+    }
+
+    // The display settings for a ROM, starting from the theme picked in the
+    // config. The colors array sets all four palette colors, background and
+    // foreground just the first two. A theme given on the command line
+    // replaces the whole palette.
+    pub fn display(&self, rom: &str, theme: Option<&str>) -> Result<DisplayConfig, ConfigError> {
+        let sections = self.sections(rom);
+        let mut display: DisplayConfig = Default::default();
+
+        for section in sections.iter() {
+            match section.get("theme") {
+                Some(value) => display.palette = try!(as_theme(try!(as_str(value, "theme")))),
+                None => {}
+            }
+        }
+
+        for section in sections.iter() {
+            match section.get("colors") {
+                Some(value) => {
+                    let colors = try!(as_str_list(value, "colors"));
+                    if colors.len() != display.palette.len() {
+                        return Err(config_error("colors must have four colors".to_string()));
+                    }
+                    for (idx, color) in colors.iter().enumerate() {
+                        display.palette[idx] = try!(as_color(color.as_slice()));
+                    }
+                },
+                None => {}
+            }
+            match section.get("background") {
+                Some(value) => display.palette[0] = try!(as_color(try!(as_str(value, "background")))),
+                None => {}
+            }
+            match section.get("foreground") {
+                Some(value) => display.palette[1] = try!(as_color(try!(as_str(value, "foreground")))),
+                None => {}
+            }
+            match section.get("scale") {
+                Some(value) => display.scale = try!(as_scale(value)),
+                None => {}
+            }
+            match section.get("fullscreen") {
+                Some(value) => display.fullscreen = match value.as_bool() {
+                    Some(fullscreen) => fullscreen,
+                    None => return Err(config_error("fullscreen must be true or false".to_string()))
+                },
+                None => {}
+            }
+        }
+
+        match theme {
+            Some(name) => display.palette = try!(as_theme(name)),
+            None => {}
+        }
+        Ok(display)
+    }
+
+    // The top level table followed by the one for the ROM, if there is one
+    fn sections(&self, rom: &str) -> Vec<&toml::Table> {
+        let mut sections = vec![&self.table];
+        let name = rom_name(rom);
+        match self.table.get("rom").and_then(|roms| roms.as_table()).and_then(|roms| roms.get(&name)) {
+            Some(value) => match value.as_table() {
+                Some(section) => sections.push(section),
+                None => {}
+            },
+            None => {}
+        }
+        sections
+    }
+}
+
+// The name of a ROM's file without any extension
+fn rom_name(rom: &str) -> String {
+    Path::new(rom).filestem_str().unwrap_or(rom).to_string()
+}
+
+// Apply the bindings from the table called name in each of the sections, it
+// maps keypad keys to one host key or an array of them
+fn bind_keys(keymap: &mut KeyMap, sections: &[&toml::Table], name: &str) -> Result<(), ConfigError> {
+    for section in sections.iter() {
+        let keys = match section.get(name) {
+            Some(value) => match value.as_table() {
+                Some(keys) => keys,
+                None => return Err(config_error(format!("{} must be a table", name)))
+            },
+            None => continue
+        };
+        for (key, value) in keys.iter() {
+            let digit = match num::from_str_radix::<u8>(key.as_slice(), 16) {
+                Some(digit) if digit < 16 => digit,
+                _ => return Err(config_error(format!("invalid keypad key: {}", key)))
+            };
+            match keymap.bind(digit, try!(as_str_list(value, key.as_slice()))) {
+                Ok(()) => {},
+                Err(msg) => return Err(config_error(msg))
+            }
+        }
+    }
+    Ok(())
+}
+
+fn as_str<'a>(value: &'a toml::Value, name: &str) -> Result<&'a str, ConfigError> {
+    match value.as_str() {
+        Some(s) => Ok(s),
+        None => Err(config_error(format!("{} must be a string", name)))
+    }
+}
+
+fn as_theme(name: &str) -> Result<Palette, ConfigError> {
+    match display::theme(name) {
+        Some(palette) => Ok(palette),
+        None => Err(config_error(format!("unknown theme: {}", name)))
+    }
+}
+
+fn as_color(s: &str) -> Result<(u8, u8, u8), ConfigError> {
+    match display::parse_color(s) {
+        Some(color) => Ok(color),
+        None => Err(config_error(format!("invalid color: {}", s)))
+    }
+}
+
+// A whole number of pixels, or "fit" for None
+fn as_scale(value: &toml::Value) -> Result<Option<uint>, ConfigError> {
+    match (value.as_integer(), value.as_str()) {
+        (Some(scale), _) if scale > 0 => Ok(Some(scale as uint)),
+        (_, Some("fit")) => Ok(None),
+        _ => Err(config_error("scale must be a positive number or \"fit\"".to_string()))
+    }
+}
+
+// A single string or an array of them
+fn as_str_list(value: &toml::Value, name: &str) -> Result<Vec<String>, ConfigError> {
+    match value.as_slice() {
+        Some(values) => values.iter().map(|v| as_str(v, name).map(|s| s.to_string())).collect(),
+        None => as_str(value, name).map(|s| vec![s.to_string()])
+    }
+}
@@ -0,0 +1,119 @@
+use super::Config;
+use frontend::{KeyMap, DEFAULT_PALETTE};
+use frontend::display;
+
+#[test]
+fn test_default_keymap() {
+    let config = Config::parse("").unwrap();
+    let keymap = config.keymap("PONG", None).unwrap();
+    assert_eq!(keymap.lookup("x"), Some(0x0));
+    assert_eq!(keymap.lookup("v"), Some(0xF));
+
+    // a layout on the command line wins
+    let config = Config::parse("layout = \"dvorak\"").unwrap();
+    assert_eq!(config.keymap("PONG", None).unwrap(), KeyMap::layout("dvorak").unwrap());
+    assert_eq!(config.keymap("PONG", Some("numpad")).unwrap(), KeyMap::layout("numpad").unwrap());
+}
+
+#[test]
+fn test_key_bindings() {
+    let text = "layout = \"azerty\"\n\
+                [keys]\n\
+                5 = [\"z\", \"up\"]\n\
+                [rom.PONG]\n\
+                layout = \"qwerty\"\n\
+                [rom.PONG.keys]\n\
+                1 = \"Up\"\n\
+                c = [\"down\"]\n";
+    let config = Config::parse(text).unwrap();
+
+    let keymap = config.keymap("games/BRIX", None).unwrap();
+    assert_eq!(keymap.lookup("a"), Some(0x4));
+    assert_eq!(keymap.lookup("up"), Some(0x5));
+    assert_eq!(keymap.lookup("z"), Some(0x5));
+    assert_eq!(keymap.lookup("é"), Some(0x2));
+
+    // the ROM's section is applied on top, whatever its extension
+    let keymap = config.keymap("games/PONG.ch8", None).unwrap();
+    assert_eq!(keymap.lookup("q"), Some(0x4));
+    assert_eq!(keymap.lookup("up"), Some(0x1));
+    assert_eq!(keymap.lookup("1"), None);
+    assert_eq!(keymap.lookup("down"), Some(0xC));
+    assert_eq!(keymap.lookup("4"), None);
+}
+
+#[test]
+fn test_config_errors() {
+    assert!(Config::parse("layout = ").is_err());
+    let config = Config::parse("layout = \"colemak\"").unwrap();
+    assert!(config.keymap("PONG", None).is_err());
+    let config = Config::parse("[keys]\n10 = \"a\"").unwrap();
+    assert_eq!(config.keymap("PONG", None).err().unwrap().message.as_slice(), "invalid keypad key: 10");
+    let config = Config::parse("[keys]\n1 = 5").unwrap();
+    assert!(config.keymap("PONG", None).is_err());
+    let config = Config::parse("[keys]\n1 = [\"a\", \"escape\"]").unwrap();
+    assert_eq!(config.keymap("PONG", None).err().unwrap().message.as_slice(), "unknown key name: escape");
+}
+
+#[test]
+fn test_gamepad_bindings() {
+    let text = "[gamepad]\n\
+                6 = [\"button0\", \"button5\"]\n\
+                [rom.PONG.gamepad]\n\
+                c = \"button0\"\n";
+    let config = Config::parse(text).unwrap();
+
+    let gamepad = config.gamepad("BRIX").unwrap();
+    assert_eq!(gamepad.lookup("left"), Some(0x4));
+    assert_eq!(gamepad.lookup("button5"), Some(0x6));
+
+    // the user's bindings go on top of the built-in profile
+    let gamepad = config.gamepad("roms/PONG.ch8").unwrap();
+    assert_eq!(gamepad.lookup("up"), Some(0x1));
+    assert_eq!(gamepad.lookup("button0"), Some(0xC));
+    assert_eq!(gamepad.lookup("button5"), Some(0x6));
+}
+
+#[test]
+fn test_display_settings() {
+    let config = Config::parse("").unwrap();
+    let display = config.display("PONG", None).unwrap();
+    assert_eq!(display.palette, DEFAULT_PALETTE);
+    assert_eq!(display.scale, Some(10));
+
+    let text = "theme = \"amber\"\n\
+                scale = 4\n\
+                [rom.PONG]\n\
+                foreground = \"#33FF66\"\n\
+                scale = \"fit\"\n\
+                fullscreen = true\n";
+    let config = Config::parse(text).unwrap();
+    let display = config.display("BRIX", None).unwrap();
+    assert_eq!(display.palette, display::theme("amber").unwrap());
+    assert_eq!((display.scale, display.fullscreen), (Some(4), false));
+
+    let display = config.display("PONG.ch8", None).unwrap();
+    assert_eq!(display.palette[0], display::theme("amber").unwrap()[0]);
+    assert_eq!(display.palette[1], (0x33, 0xFF, 0x66));
+    assert_eq!((display.scale, display.fullscreen), (None, true));
+
+    // the command line theme wins over the colors in the file
+    let display = config.display("PONG.ch8", Some("lcd")).unwrap();
+    assert_eq!(display.palette, display::theme("lcd").unwrap());
+    assert_eq!((display.scale, display.fullscreen), (None, true));
+
+    let config = Config::parse("colors = [\"000000\", \"ffffff\", \"ff0000\", \"0000ff\"]").unwrap();
+    assert_eq!(config.display("PONG", None).unwrap().palette[3], (0x00, 0x00, 0xFF));
+}
+
+#[test]
+fn test_display_errors() {
+    let config = Config::parse("theme = \"sepia\"").unwrap();
+    assert_eq!(config.display("PONG", None).err().unwrap().message.as_slice(), "unknown theme: sepia");
+    let config = Config::parse("background = \"#12345\"").unwrap();
+    assert_eq!(config.display("PONG", None).err().unwrap().message.as_slice(), "invalid color: #12345");
+    let config = Config::parse("colors = [\"000000\"]").unwrap();
+    assert!(config.display("PONG", None).is_err());
+    let config = Config::parse("scale = 0").unwrap();
+    assert!(config.display("PONG", None).is_err());
+}
@@ -0,0 +1,157 @@
+use std::default::Default;
+use std::f32::consts::PI;
+use std::i16;
+use std::io::IoResult;
+use std::num::Float;
+use chip8impl::FRAME_RATE;
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+// number of samples making up a single 60Hz frame of sound
+pub const SAMPLES_PER_FRAME: uint = SAMPLE_RATE as uint / FRAME_RATE as uint;
+
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square"   => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            "sine"     => Some(Waveform::Sine),
+            _ => None
+        }
+    }
+}
+
+// Beep tone settings, volume ranges from 0.0 to 1.0
+#[derive(Copy, Clone)]
+pub struct AudioConfig {
+    pub frequency : f32,
+    pub volume    : f32,
+    pub waveform  : Waveform
+}
+
+impl Default for AudioConfig {
+    fn default() -> AudioConfig {
+        AudioConfig {
+            frequency : 440.0,
+            volume    : 0.25,
+            waveform  : Waveform::Square
+        }
+    }
+}
+
+// number of 1-bit samples in an XO-CHIP audio pattern
+const PATTERN_BITS: uint = 128;
+
+// Generates signed 16-bit mono samples of the beep tone, keeping the phase
+// continuous across buffers so there are no clicks between them. Once an
+// XO-CHIP audio pattern is set that is looped instead of the configured tone.
+pub struct ToneGenerator {
+    config  : AudioConfig,
+    phase   : f32,
+    pattern : Option<(Vec<u8>, f32)>
+}
+
+impl ToneGenerator {
+
+    pub fn new(config: AudioConfig) -> ToneGenerator {
+        ToneGenerator { config: config, phase: 0.0, pattern: None }
+    }
+
+    pub fn set_pattern(&mut self, pattern: &[u8], rate: f32) {
+        self.pattern = Some((pattern.to_vec(), rate));
+    }
+
+    pub fn fill(&mut self, out: &mut [i16]) {
+        let amplitude = self.config.volume * i16::MAX as f32;
+
+        // phase runs from 0 to 1 over a period of the tone, or over the
+        // whole length of the pattern
+        let step = match self.pattern {
+            Some((_, rate)) => rate / PATTERN_BITS as f32 / SAMPLE_RATE as f32,
+            None => self.config.frequency / SAMPLE_RATE as f32
+        };
+
+        for sample in out.iter_mut() {
+            let value = match self.pattern {
+                Some((ref bits, _)) => {
+                    let bit = (self.phase * PATTERN_BITS as f32) as uint % PATTERN_BITS;
+                    if bits[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 }
+                },
+                None => match self.config.waveform {
+                    Waveform::Square   => if self.phase < 0.5 { 1.0 } else { -1.0 },
+                    Waveform::Triangle => 4.0 * (self.phase - 0.5).abs() - 1.0,
+                    Waveform::Sawtooth => 2.0 * self.phase - 1.0,
+                    Waveform::Sine     => (2.0 * PI * self.phase).sin()
+                }
+            };
+            *sample = (value * amplitude) as i16;
+            self.phase = (self.phase + step) % 1.0;
+        }
+    }
+}
+
+// Renders the beep state of each 60Hz frame into samples so it can be
+// written out as a WAV file rather than played
+pub struct WavRecorder {
+    tone    : ToneGenerator,
+    samples : Vec<i16>
+}
+
+impl WavRecorder {
+
+    pub fn new(config: AudioConfig) -> WavRecorder {
+        WavRecorder { tone: ToneGenerator::new(config), samples: Vec::new() }
+    }
+
+    pub fn record_frame(&mut self, on: bool) {
+        let start = self.samples.len();
+        for _ in range(0u, SAMPLES_PER_FRAME) {
+            self.samples.push(0);
+        }
+        if on {
+            self.tone.fill(self.samples.slice_from_mut(start));
+        }
+    }
+
+    pub fn set_pattern(&mut self, pattern: &[u8], rate: f32) {
+        self.tone.set_pattern(pattern, rate);
+    }
+
+    pub fn samples(&self) -> &[i16] {
+        self.samples.as_slice()
+    }
+
+    // Write a 16-bit mono PCM WAV file
+    pub fn write(&self, w: &mut Writer) -> IoResult<()> {
+        let data_len = (self.samples.len() * 2) as u32;
+
+        try!(w.write_str("RIFF"));
+        try!(w.write_le_u32(36 + data_len));
+        try!(w.write_str("WAVE"));
+
+        try!(w.write_str("fmt "));
+        try!(w.write_le_u32(16));
+        try!(w.write_le_u16(1));                // PCM
+        try!(w.write_le_u16(1));                // mono
+        try!(w.write_le_u32(SAMPLE_RATE));
+        try!(w.write_le_u32(SAMPLE_RATE * 2));  // byte rate
+        try!(w.write_le_u16(2));                // block align
+        try!(w.write_le_u16(16));               // bits per sample
+
+        try!(w.write_str("data"));
+        try!(w.write_le_u32(data_len));
+        for sample in self.samples.iter() {
+            try!(w.write_le_i16(*sample));
+        }
+        Ok(())
+    }
+}
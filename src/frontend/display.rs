@@ -0,0 +1,84 @@
+use std::cmp;
+use std::default::Default;
+use std::num;
+use super::DEFAULT_PALETTE;
+
+// Colors for the four pixel values, see DEFAULT_PALETTE
+pub type Palette = [(u8, u8, u8); 4];
+
+// Built-in color themes by name. Plane 2 is drawn in a dimmer shade of the
+// foreground and both planes together in a brighter one.
+static THEMES : [(&'static str, Palette); 3] =
+    [("green", [(0x00, 0x14, 0x00), (0x33, 0xFF, 0x66), (0x19, 0x80, 0x33), (0xB3, 0xFF, 0xC6)]),
+     ("amber", [(0x1A, 0x0F, 0x00), (0xFF, 0xB0, 0x00), (0x80, 0x58, 0x00), (0xFF, 0xD8, 0x80)]),
+     ("lcd",   [(0x9B, 0xBC, 0x0F), (0x0F, 0x38, 0x0F), (0x8B, 0xAC, 0x0F), (0x30, 0x62, 0x30)])];
+
+// Window size when fitting the display to the window
+const DEFAULT_WINDOW_WIDTH:  uint = 640;
+const DEFAULT_WINDOW_HEIGHT: uint = 320;
+
+// Width and height of the low resolution display, which the scale is
+// relative to
+const LORES_WIDTH:  uint = 64;
+const LORES_HEIGHT: uint = 32;
+
+// One of the built-in themes: default, green, amber or lcd
+pub fn theme(name: &str) -> Option<Palette> {
+    if name == "default" {
+        return Some(DEFAULT_PALETTE);
+    }
+    THEMES.iter().find(|&&(theme, _)| theme == name).map(|&(_, palette)| palette)
+}
+
+// A color written as six hex digits, RRGGBB, optionally after a #
+pub fn parse_color(s: &str) -> Option<(u8, u8, u8)> {
+    let digits = if s.starts_with("#") { s.slice_from(1) } else { s };
+    if digits.len() != 6 {
+        return None;
+    }
+    match num::from_str_radix::<u32>(digits, 16) {
+        Some(rgb) => Some(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)),
+        None => None
+    }
+}
+
+// How the display is drawn. The scale is the number of window pixels across
+// each low resolution pixel, high resolution pixels are half as big, or None
+// to fit the display to the window.
+#[derive(Copy, Clone)]
+pub struct DisplayConfig {
+    pub palette    : Palette,
+    pub scale      : Option<uint>,
+    pub fullscreen : bool
+}
+
+impl Default for DisplayConfig {
+    fn default() -> DisplayConfig {
+        DisplayConfig {
+            palette    : DEFAULT_PALETTE,
+            scale      : Some(10),
+            fullscreen : false
+        }
+    }
+}
+
+impl DisplayConfig {
+
+    // Size the window opens at when not fullscreen
+    pub fn window_size(&self) -> (uint, uint) {
+        match self.scale {
+            Some(scale) => (LORES_WIDTH * scale, LORES_HEIGHT * scale),
+            None => (DEFAULT_WINDOW_WIDTH, DEFAULT_WINDOW_HEIGHT)
+        }
+    }
+
+    // Window pixels across each pixel of a width x height display, never
+    // less than 1
+    pub fn pixel_size(&self, width: uint, height: uint, window: (uint, uint)) -> uint {
+        let size = match self.scale {
+            Some(scale) => scale * LORES_WIDTH / width,
+            None => cmp::min(window.0 / width, window.1 / height)
+        };
+        cmp::max(size, 1)
+    }
+}
@@ -0,0 +1,61 @@
+use std::io::IoResult;
+use super::{Frontend, InputEvent};
+use super::audio::{AudioConfig, WavRecorder};
+
+// A frontend with no display, input or audio device. Time is simulated so
+// runs are as fast as the host allows, and the beep can be recorded.
+pub struct HeadlessFrontend {
+    clock_ns : u64,
+    audio    : Option<WavRecorder>
+}
+
+impl HeadlessFrontend {
+
+    pub fn new() -> HeadlessFrontend {
+        HeadlessFrontend { clock_ns: 0, audio: None }
+    }
+
+    // Capture the beep output of every frame from here on
+    pub fn record_audio(&mut self, config: AudioConfig) {
+        self.audio = Some(WavRecorder::new(config));
+    }
+
+    // Write the captured beep output as a WAV file
+    pub fn write_audio(&self, w: &mut Writer) -> IoResult<()> {
+        match self.audio {
+            Some(ref recorder) => recorder.write(w),
+            None => Ok(())
+        }
+    }
+}
+
+impl Frontend for HeadlessFrontend {
+
+    fn present(&mut self, _gfx: &[u8], _width: uint, _height: uint) {}
+
+    fn poll_input(&mut self) -> InputEvent {
+        InputEvent::None
+    }
+
+    fn set_beep(&mut self, on: bool) {
+        match self.audio {
+            Some(ref mut recorder) => recorder.record_frame(on),
+            None => {}
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8], rate: f32) {
+        match self.audio {
+            Some(ref mut recorder) => recorder.set_pattern(pattern, rate),
+            None => {}
+        }
+    }
+
+    fn time_ns(&self) -> u64 {
+        self.clock_ns
+    }
+
+    fn sleep_ms(&mut self, ms: u64) {
+        self.clock_ns += ms * 1000000;
+    }
+}
@@ -0,0 +1,215 @@
+use std::io::{File, IoResult, MemWriter};
+use std::io::fs::PathExtensions;
+
+// PNG files start with this signature, then a series of chunks
+static PNG_SIGNATURE : [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+// Largest block of uncompressed data a deflate stream can hold
+const STORED_BLOCK_SIZE: uint = 65535;
+
+// File formats images can be saved in
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+    Pbm
+}
+
+impl ImageFormat {
+
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "pbm" => Some(ImageFormat::Pbm),
+            _ => None
+        }
+    }
+
+    // The format that goes with a file's extension
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        path.extension_str().and_then(|ext| ImageFormat::from_name(ext))
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Pbm => "pbm"
+        }
+    }
+}
+
+// An RGB image of the display, with each pixel scaled up to a square of
+// scale x scale pixels in its palette color
+pub struct Image {
+    pub width  : uint,
+    pub height : uint,
+    pub pixels : Vec<u8>
+}
+
+impl Image {
+
+    pub fn from_display(gfx: &[u8], width: uint, height: uint, scale: uint,
+                        palette: &[(u8, u8, u8)]) -> Image {
+        let indices = scale_display(gfx, width, height, scale);
+        Image::from_indices(indices.as_slice(), width * scale, height * scale, palette)
+    }
+
+    // An image of palette indices that's already at its full size
+    pub fn from_indices(indices: &[u8], width: uint, height: uint,
+                        palette: &[(u8, u8, u8)]) -> Image {
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for &index in indices.iter() {
+            let (r, g, b) = palette[index as uint];
+            pixels.push_all(&[r, g, b]);
+        }
+        Image { width: width, height: height, pixels: pixels }
+    }
+
+    // Save the image in the format given by the file's extension
+    pub fn save(&self, path: &Path) -> IoResult<()> {
+        let format = ImageFormat::from_path(path).unwrap_or(ImageFormat::Png);
+        let mut file = try!(File::create(path));
+        self.write(format, &mut file)
+    }
+
+    pub fn write(&self, format: ImageFormat, w: &mut Writer) -> IoResult<()> {
+        match format {
+            ImageFormat::Png => self.write_png(w),
+            ImageFormat::Ppm => self.write_ppm(w),
+            ImageFormat::Pbm => self.write_pbm(w)
+        }
+    }
+
+    // 8 bit RGB, left uncompressed so there's no need for a zlib library
+    fn write_png(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write(&PNG_SIGNATURE));
+
+        let mut header = MemWriter::new();
+        try!(header.write_be_u32(self.width as u32));
+        try!(header.write_be_u32(self.height as u32));
+        try!(header.write(&[8, 2, 0, 0, 0]));
+        try!(write_chunk(w, "IHDR", header.get_ref()));
+
+        // each row starts with its filter type, 0 for none
+        let row_size = self.width * 3;
+        let mut raw = Vec::with_capacity((row_size + 1) * self.height);
+        for row in self.pixels.chunks(row_size) {
+            raw.push(0);
+            raw.push_all(row);
+        }
+
+        // a zlib stream of stored deflate blocks
+        let mut data = MemWriter::new();
+        try!(data.write(&[0x78, 0x01]));
+        let blocks: Vec<&[u8]> = raw.chunks(STORED_BLOCK_SIZE).collect();
+        for (idx, block) in blocks.iter().enumerate() {
+            try!(data.write_u8(if idx + 1 == blocks.len() { 1 } else { 0 }));
+            try!(data.write_le_u16(block.len() as u16));
+            try!(data.write_le_u16(!(block.len() as u16)));
+            try!(data.write(*block));
+        }
+        try!(data.write_be_u32(adler32(raw.as_slice())));
+        try!(write_chunk(w, "IDAT", data.get_ref()));
+
+        write_chunk(w, "IEND", &[])
+    }
+
+    fn write_ppm(&self, w: &mut Writer) -> IoResult<()> {
+        try!(write!(w, "P6\n{} {}\n255\n", self.width, self.height));
+        w.write(self.pixels.as_slice())
+    }
+
+    // Black and white, with dark colors black and light ones white
+    fn write_pbm(&self, w: &mut Writer) -> IoResult<()> {
+        try!(write!(w, "P4\n{} {}\n", self.width, self.height));
+        for row in self.pixels.chunks(self.width * 3) {
+            let mut packed = Vec::from_elem((self.width + 7) / 8, 0u8);
+            for (x, rgb) in row.chunks(3).enumerate() {
+                let luma = (rgb[0] as uint * 299 + rgb[1] as uint * 587 + rgb[2] as uint * 114) / 1000;
+                if luma < 128 {
+                    packed[x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+            try!(w.write(packed.as_slice()));
+        }
+        Ok(())
+    }
+}
+
+// Scale the display up by a whole number, keeping its palette indices
+pub fn scale_display(gfx: &[u8], width: uint, height: uint, scale: uint) -> Vec<u8> {
+    let mut indices = Vec::with_capacity(width * height * scale * scale);
+    for y in range(0u, height * scale) {
+        for x in range(0u, width * scale) {
+            indices.push(gfx[x / scale + (y / scale) * width] & 0x3);
+        }
+    }
+    indices
+}
+
+// Where screenshots taken with the hotkey are saved and how. They're named
+// after the prefix's file, minus any extension, followed by a count. With no
+// scale they're taken at the window's scale.
+pub struct ScreenshotConfig {
+    pub prefix : Path,
+    pub format : ImageFormat,
+    pub scale  : Option<uint>
+}
+
+impl ScreenshotConfig {
+
+    // Save the display to the next free file, returning its path
+    pub fn save(&self, gfx: &[u8], width: uint, height: uint, window_scale: uint,
+                palette: &[(u8, u8, u8)]) -> IoResult<Path> {
+        let scale = self.scale.unwrap_or(window_scale);
+        let image = Image::from_display(gfx, width, height, scale, palette);
+        let path = next_image_path(&self.prefix, self.format);
+        let mut file = try!(File::create(&path));
+        try!(image.write(self.format, &mut file));
+        Ok(path)
+    }
+}
+
+// The first path of prefix-1, prefix-2 and so on with the format's extension
+// that isn't taken yet
+fn next_image_path(prefix: &Path, format: ImageFormat) -> Path {
+    let name = prefix.filestem_str().unwrap_or("screenshot");
+    let mut count = 1u;
+    loop {
+        let path = prefix.with_filename(format!("{}-{}.{}", name, count, format.extension()));
+        if !path.exists() {
+            return path;
+        }
+        count += 1;
+    }
+}
+
+fn write_chunk(w: &mut Writer, kind: &str, data: &[u8]) -> IoResult<()> {
+    try!(w.write_be_u32(data.len() as u32));
+    try!(w.write(kind.as_bytes()));
+    try!(w.write(data));
+    w.write_be_u32(crc32(kind.as_bytes(), data))
+}
+
+// CRC-32 of the chunk type followed by its data, as used by PNG
+fn crc32(kind: &[u8], data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in kind.iter().chain(data.iter()) {
+        crc ^= *byte as u32;
+        for _ in range(0u, 8) {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data.iter() {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,137 @@
+use std::ascii::AsciiExt;
+use std::default::Default;
+use std::num;
+
+const KEY_COUNT: uint = 16;
+
+// Built-in layouts, giving the host keys for each keypad key 0x0 - 0xF.
+// Host keys are named by the character they type in lower case, or kp0 -
+// kp9, kp/, kp*, kp-, kp+, kp., kpenter, space, up, down, left and right.
+//
+//  Keypad        qwerty     azerty     dvorak     numpad
+//  1 2 3 C       1 2 3 4    1 2 3 4    1 2 3 4    7 8 9 -
+//  4 5 6 D       Q W E R    A Z E R    ' , . Y    4 5 6 +
+//  7 8 9 E       A S D F    Q S D F    A O E U    1 2 3 Enter
+//  A 0 B F       Z X C V    W X C V    ; Q J K    / 0 . *
+//
+// AZERTY keyboards also get the unshifted keys of their number row, and the
+// numpad layout keeps the keys in the same places as on the COSMAC VIP's hex
+// keypad, other than A and F which don't fit and go on the top row. P is the
+// pause key, so Dvorak's D goes on Y, just to the right of it.
+static QWERTY : [&'static str; KEY_COUNT] =
+    ["x", "1", "2", "3", "q", "w", "e", "a", "s", "d", "z", "c", "4", "r", "f", "v"];
+static AZERTY : [&'static str; KEY_COUNT] =
+    ["x", "1 &", "2 é", "3 \"", "a", "z", "e", "q", "s", "d", "w", "c", "4 '", "r", "f", "v"];
+static DVORAK : [&'static str; KEY_COUNT] =
+    ["q", "1", "2", "3", "'", ",", ".", "a", "o", "e", ";", "j", "4", "y", "u", "k"];
+static NUMPAD : [&'static str; KEY_COUNT] =
+    ["kp0", "kp7", "kp8", "kp9", "kp4", "kp5", "kp6", "kp1", "kp2", "kp3",
+     "kp/", "kp.", "kp-", "kp+", "kpenter", "kp*"];
+
+// Host keys the frontends keep for hotkeys, which can't be bound
+static HOTKEY_NAMES : [&'static str; 3] = ["p", "-", "="];
+
+// Gamepads are named up, down, left and right for the D-pad or first stick,
+// and button0, button1 and so on for the buttons. By default the D-pad is on
+// 5, 8, 7 and 9, with the first few buttons on 6, 4, 0 and F.
+static GAMEPAD : [&'static str; KEY_COUNT] =
+    ["button2", "", "", "", "button1", "up", "button0", "left", "down", "right",
+     "", "", "", "", "", "button3"];
+
+// Gamepad bindings for games whose controls don't fit the default, by ROM
+static GAMEPAD_PROFILES : [(&'static str, &'static str); 3] =
+    [("PONG",     "1:up 4:down"),
+     ("INVADERS", "4:left 6:right 5:button0"),
+     ("BRIX",     "4:left 6:right")];
+
+// Which host keys press each key of the Chip8 keypad, a keypad key can have
+// any number of host keys
+#[derive(Show, PartialEq, Clone)]
+pub struct KeyMap {
+    bindings : Vec<Vec<String>>
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        KeyMap::from_layout(&QWERTY)
+    }
+}
+
+impl KeyMap {
+
+    // One of the built-in layouts: qwerty, azerty, dvorak or numpad
+    pub fn layout(name: &str) -> Option<KeyMap> {
+        match name {
+            "qwerty" => Some(KeyMap::from_layout(&QWERTY)),
+            "azerty" => Some(KeyMap::from_layout(&AZERTY)),
+            "dvorak" => Some(KeyMap::from_layout(&DVORAK)),
+            "numpad" => Some(KeyMap::from_layout(&NUMPAD)),
+            _ => None
+        }
+    }
+
+    // The gamepad bindings for a ROM, given the name of its file without any
+    // extension
+    pub fn gamepad(rom: &str) -> KeyMap {
+        let mut keymap = KeyMap::from_layout(&GAMEPAD);
+        for &(name, profile) in GAMEPAD_PROFILES.iter() {
+            if name.eq_ignore_ascii_case(rom) {
+                for binding in profile.words() {
+                    let key = num::from_str_radix::<u8>(binding.slice_to(1), 16).unwrap();
+                    keymap.bind(key, vec![binding.slice_from(2).to_string()]).unwrap();
+                }
+            }
+        }
+        keymap
+    }
+
+    fn from_layout(layout: &[&'static str; KEY_COUNT]) -> KeyMap {
+        KeyMap {
+            bindings: layout.iter().map(|names| names.words().map(|name| name.to_string()).collect()).collect()
+        }
+    }
+
+    // Replace the host keys bound to a keypad key, taking them away from any
+    // other keypad key they were bound to. Fails on a name no host key or
+    // gamepad input has.
+    pub fn bind(&mut self, key: u8, names: Vec<String>) -> Result<(), String> {
+        let names: Vec<String> = names.into_iter().map(|name| name.chars().map(|c| c.to_lowercase()).collect()).collect();
+        for name in names.iter() {
+            if !KeyMap::valid_name(name.as_slice()) {
+                return Err(format!("unknown key name: {}", name));
+            }
+            if HOTKEY_NAMES.iter().any(|hotkey| *hotkey == name.as_slice()) {
+                return Err(format!("key is used by a hotkey: {}", name));
+            }
+        }
+        for bound in self.bindings.iter_mut() {
+            bound.retain(|name| !names.contains(name));
+        }
+        self.bindings[key as uint] = names;
+        Ok(())
+    }
+
+    // Whether name is one the frontends give a host key or gamepad input,
+    // printable keys are named by their Latin-1 character
+    fn valid_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => {
+                let code = c as u32;
+                (code > 32 && code < 127) || (code >= 160 && code < 256)
+            },
+            _ => match name {
+                "space" | "up" | "down" | "left" | "right" |
+                "kp/" | "kp*" | "kp-" | "kp+" | "kp." | "kpenter" => true,
+                _ if name.starts_with("kp") => name.len() == 3 && from_str::<uint>(name.slice_from(2)).is_some(),
+                _ if name.starts_with("button") => from_str::<uint>(name.slice_from(6)).is_some(),
+                _ => false
+            }
+        }
+    }
+
+    // The keypad key pressed by the named host key, if any
+    pub fn lookup(&self, name: &str) -> Option<u8> {
+        self.bindings.iter().position(|names| names.iter().any(|n| n.as_slice() == name)).map(|key| key as u8)
+    }
+}
@@ -0,0 +1,103 @@
+use std::io;
+
+pub use self::audio::{AudioConfig, Waveform};
+pub use self::display::{DisplayConfig, Palette};
+pub use self::headless::HeadlessFrontend;
+pub use self::image::{Image, ImageFormat, ScreenshotConfig};
+pub use self::keymap::KeyMap;
+pub use self::sdlfrontend::SdlFrontend;
+pub use self::tui::{TuiFrontend, TuiMode};
+pub use self::video::{VideoFrontend, VideoRecorder};
+
+pub mod audio;
+pub mod display;
+mod headless;
+mod image;
+mod keymap;
+mod sdlfrontend;
+mod tui;
+mod video;
+
+#[cfg(test)]
+mod tests;
+
+// Colors for the four pixel values of the two XO-CHIP planes: neither plane
+// set, plane 1 only, plane 2 only and both planes. Plain Chip8 programs only
+// ever use the first two, the background and foreground.
+pub static DEFAULT_PALETTE : Palette =
+    [(0x00, 0x00, 0x00),
+     (0xFF, 0xFF, 0xFF),
+     (0xFF, 0x66, 0x00),
+     (0x66, 0x22, 0x00)];
+
+// Emulator controls bound to host keys, outside of the Chip8 keypad
+pub enum Hotkey {
+    SpeedUp,
+    SpeedDown,
+    Pause,
+    FastForward(bool),
+    Rewind(bool),
+    SaveState,
+    LoadState,
+    NextSlot,
+    PrevSlot
+}
+
+// Input events delivered by a frontend, keys are already mapped onto the
+// 16-key Chip8 keypad
+pub enum InputEvent {
+    Key(u8, bool),
+    Hotkey(Hotkey),
+    Quit,
+    None
+}
+
+// Everything the emulator core needs from the host it runs on - somewhere to
+// present the display, a source of keypad input, sound output and a clock.
+pub trait Frontend {
+    // Present a width * height graphics buffer, one byte per pixel holding an
+    // index into a four color palette
+    fn present(&mut self, gfx: &[u8], width: uint, height: uint);
+
+    // Return the next pending input event, or InputEvent::None if there are none
+    fn poll_input(&mut self) -> InputEvent;
+
+    // Start or stop the beep tone, called at the end of every 60Hz frame and
+    // whenever emulation stops
+    fn set_beep(&mut self, on: bool);
+
+    // Replace the beep tone with an XO-CHIP audio pattern, 128 1-bit samples
+    // played back in a loop at rate samples per second
+    fn set_pattern(&mut self, pattern: &[u8], rate: f32);
+
+    // Monotonic time in nanoseconds
+    fn time_ns(&self) -> u64;
+
+    // Block the emulator for the given number of milliseconds
+    fn sleep_ms(&mut self, ms: u64);
+
+    // Called once at the end of every 60Hz frame, after its display and sound
+    // have been set
+    fn end_frame(&mut self) {}
+
+    // Prompt for a line of input for the debugger, None at the end of input
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        print!("{}", prompt);
+        let _ = io::stdout().flush();
+        io::stdin().read_line().ok()
+    }
+
+    // Print the debugger's output, one or more lines of text
+    fn write_output(&mut self, text: &str) {
+        println!("{}", text);
+    }
+
+    // Report a change in the emulator's state, like pausing or a new speed
+    fn show_status(&mut self, message: &str) {
+        println!("{}", message);
+    }
+
+    // Show the registers alongside the display while debugging, if there's
+    // room for them
+    fn show_registers(&mut self, _lines: Vec<String>) {}
+}
@@ -0,0 +1,359 @@
+extern crate sdl;
+extern crate time;
+
+use std::char;
+use std::collections::{HashMap, RingBuf};
+use std::io::Timer;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use super::{DisplayConfig, Frontend, Hotkey, InputEvent, KeyMap, ScreenshotConfig};
+use super::audio::{AudioConfig, ToneGenerator, SAMPLE_RATE};
+
+// How far a stick has to be pushed to count as a D-pad direction
+const AXIS_THRESHOLD: i16 = 16384;
+
+// Gamepad directions, in the order they're kept in SdlFrontend::directions
+static DIRECTIONS : [&'static str; 4] = ["up", "down", "left", "right"];
+
+// Where a gamepad direction came from: a hat on a joystick, or a joystick's
+// first stick
+#[derive(PartialEq, Eq, Hash)]
+enum DirectionSource {
+    Hat(int, int),
+    Stick(int)
+}
+
+pub struct SdlFrontend {
+    screen      : sdl::video::Surface,
+    display     : DisplayConfig,
+    timer       : Timer,
+    tone        : Arc<Mutex<ToneGenerator>>,
+    audio_open  : bool,
+    beeping     : bool,
+    keymap      : KeyMap,
+    gamepad     : KeyMap,
+    joysticks   : Vec<sdl::joy::Joystick>,
+    directions  : [bool; 4],
+    sources     : HashMap<DirectionSource, [bool; 4]>,
+    pending     : RingBuf<InputEvent>,
+    screenshots : Option<ScreenshotConfig>,
+    frame       : Vec<u8>,
+    frame_size  : (uint, uint)
+}
+
+impl SdlFrontend {
+
+    pub fn new(audio: AudioConfig, display: DisplayConfig, keymap: KeyMap, gamepad: KeyMap) -> SdlFrontend {
+        // initialize SDL for graphical output, keyboard and gamepad input and sound
+        sdl::init(&[sdl::InitFlag::Video, sdl::InitFlag::Audio, sdl::InitFlag::Joystick]);
+        sdl::wm::set_caption("RustyChip8", "");
+
+        let tone = Arc::new(Mutex::new(ToneGenerator::new(audio)));
+        SdlFrontend {
+            screen      : SdlFrontend::set_video_mode(display.window_size(), display.fullscreen),
+            display     : display,
+            timer       : Timer::new().unwrap(),
+            tone        : tone.clone(),
+            audio_open  : SdlFrontend::open_audio(tone),
+            beeping     : false,
+            keymap      : keymap,
+            gamepad     : gamepad,
+            joysticks   : SdlFrontend::open_joysticks(),
+            directions  : [false; 4],
+            sources     : HashMap::new(),
+            pending     : RingBuf::new(),
+            screenshots : None,
+            frame       : Vec::new(),
+            frame_size  : (0, 0)
+        }
+    }
+
+    // Let F12 save screenshots of the display
+    pub fn set_screenshots(&mut self, screenshots: ScreenshotConfig) {
+        self.screenshots = Some(screenshots);
+    }
+
+    // Save the last frame presented, at the window's scale unless the
+    // screenshot config says otherwise
+    fn save_screenshot(&self) {
+        let (width, height) = self.frame_size;
+        let screenshots = match self.screenshots {
+            Some(ref screenshots) if width > 0 => screenshots,
+            _ => return
+        };
+        let window_scale = self.display.pixel_size(width, height, self.window_size());
+        match screenshots.save(self.frame.as_slice(), width, height, window_scale, &self.display.palette) {
+            Ok(path) => println!("saved screenshot to {}", path.display()),
+            Err(err) => println!("failed to save screenshot: {}", err)
+        }
+    }
+
+    // Open a resizable window, or go fullscreen at the screen's current
+    // resolution
+    fn set_video_mode(size: (uint, uint), fullscreen: bool) -> sdl::video::Surface {
+        let (width, height, mode) = if fullscreen {
+            (0, 0, sdl::video::VideoFlag::Fullscreen)
+        } else {
+            (size.0 as int, size.1 as int, sdl::video::VideoFlag::Resizable)
+        };
+        match sdl::video::set_video_mode(width, height, 32, &[sdl::video::SurfaceFlag::HWSurface],
+                                         &[sdl::video::VideoFlag::DoubleBuf, mode]) {
+            Ok(screen) => screen,
+            Err(err) => panic!("failed to set video mode: {}", err)
+        }
+    }
+
+    // Resize the window or switch in and out of fullscreen, drawing the last
+    // frame again in the new mode
+    fn change_video_mode(&mut self, size: (uint, uint), fullscreen: bool) {
+        self.screen = SdlFrontend::set_video_mode(size, fullscreen);
+        self.display.fullscreen = fullscreen;
+        let (width, height) = self.frame_size;
+        if width > 0 {
+            let frame = self.frame.clone();
+            self.present(frame.as_slice(), width, height);
+        }
+    }
+
+    fn window_size(&self) -> (uint, uint) {
+        (self.screen.get_width() as uint, self.screen.get_height() as uint)
+    }
+
+    // Open every attached joystick, they all share the gamepad mapping
+    fn open_joysticks() -> Vec<sdl::joy::Joystick> {
+        let mut joysticks = Vec::new();
+        for idx in range(0, sdl::joy::get_num_joysticks()) {
+            match sdl::joy::Joystick::open(idx) {
+                Ok(joystick) => joysticks.push(joystick),
+                Err(err) => println!("failed to open joystick {}: {}", idx, err)
+            }
+        }
+        joysticks
+    }
+
+    // Open the audio device with a callback that always generates the tone,
+    // the device is left paused while the beep is off
+    fn open_audio(tone: Arc<Mutex<ToneGenerator>>) -> bool {
+        let spec = sdl::audio::DesiredAudioSpec {
+            freq     : SAMPLE_RATE as i32,
+            format   : sdl::audio::AudioFormat::S16Lsb,
+            channels : sdl::audio::Channels::Mono,
+            samples  : 1024,
+            callback : box move |&mut: buf: &mut [u8]| {
+                let mut samples = Vec::from_elem(buf.len() / 2, 0i16);
+                tone.lock().unwrap().fill(samples.as_mut_slice());
+                for (idx, sample) in samples.iter().enumerate() {
+                    buf[idx * 2] = *sample as u8;
+                    buf[idx * 2 + 1] = (*sample >> 8) as u8;
+                }
+            }
+        };
+
+        match sdl::audio::open(spec) {
+            Ok(_) => true,
+            Err(_) => {
+                println!("failed to open audio device, sound is disabled");
+                false
+            }
+        }
+    }
+
+    // The name a KeyMap knows a host key by
+    fn key_name(key: sdl::event::Key) -> Option<String> {
+        let code = key as uint;
+        let kp0 = sdl::event::Key::Kp0 as uint;
+        let name = match key {
+            sdl::event::Key::Space      => "space",
+            sdl::event::Key::Up         => "up",
+            sdl::event::Key::Down       => "down",
+            sdl::event::Key::Left       => "left",
+            sdl::event::Key::Right      => "right",
+            sdl::event::Key::KpDivide   => "kp/",
+            sdl::event::Key::KpMultiply => "kp*",
+            sdl::event::Key::KpMinus    => "kp-",
+            sdl::event::Key::KpPlus     => "kp+",
+            sdl::event::Key::KpPeriod   => "kp.",
+            sdl::event::Key::KpEnter    => "kpenter",
+            _ if code >= kp0 && code < kp0 + 10 => return Some(format!("kp{}", code - kp0)),
+            // the rest of the printable keys are numbered by the Latin-1
+            // character they type, so that's their name
+            _ if (code > 32 && code < 127) || (code >= 160 && code < 256) =>
+                return char::from_u32(code as u32).map(|c| c.to_string()),
+            _ => return None
+        };
+        Some(name.to_string())
+    }
+
+    fn map_key(&self, key: sdl::event::Key) -> Option<u8> {
+        SdlFrontend::key_name(key).and_then(|name| self.keymap.lookup(name.as_slice()))
+    }
+
+    // Update the directions held on one hat or stick. A direction stays held
+    // for as long as any of them is holding it.
+    fn update_directions(&mut self, source: DirectionSource, directions: [bool; 4]) {
+        self.sources.insert(source, directions);
+        let mut held = [false; 4];
+        for state in self.sources.values() {
+            for dir in range(0u, DIRECTIONS.len()) {
+                held[dir] = held[dir] || state[dir];
+            }
+        }
+        self.set_directions(held);
+    }
+
+    // Queue up keypad events for the gamepad directions that have changed
+    fn set_directions(&mut self, directions: [bool; 4]) {
+        for dir in range(0u, DIRECTIONS.len()) {
+            if directions[dir] != self.directions[dir] {
+                match self.gamepad.lookup(DIRECTIONS[dir]) {
+                    Some(key) => self.pending.push_back(InputEvent::Key(key, directions[dir])),
+                    None => {}
+                }
+            }
+        }
+        self.directions = directions;
+    }
+
+    fn map_hotkey(key: sdl::event::Key, pressed: bool) -> Option<Hotkey> {
+        return match (key, pressed) {
+            (sdl::event::Key::Equals, true) => Some(Hotkey::SpeedUp),
+            (sdl::event::Key::Minus, true)  => Some(Hotkey::SpeedDown),
+            (sdl::event::Key::P, true)      => Some(Hotkey::Pause),
+            (sdl::event::Key::Tab, _)       => Some(Hotkey::FastForward(pressed)),
+            (sdl::event::Key::Backspace, _) => Some(Hotkey::Rewind(pressed)),
+            (sdl::event::Key::F5, true)     => Some(Hotkey::SaveState),
+            (sdl::event::Key::F9, true)     => Some(Hotkey::LoadState),
+            (sdl::event::Key::F6, true)     => Some(Hotkey::PrevSlot),
+            (sdl::event::Key::F7, true)     => Some(Hotkey::NextSlot),
+            _ => None
+        }
+    }
+}
+
+impl Drop for SdlFrontend {
+    fn drop(&mut self) {
+        if self.audio_open {
+            sdl::audio::close();
+        }
+        self.joysticks.clear();
+        sdl::quit();
+    }
+}
+
+impl Frontend for SdlFrontend {
+
+    fn present(&mut self, gfx: &[u8], width: uint, height: uint) {
+        let window = self.window_size();
+        let pixelsize = self.display.pixel_size(width, height, window);
+        let mut colors = Vec::new();
+        for &(r, g, b) in self.display.palette.iter() {
+            colors.push(sdl::video::RGB(r, g, b));
+        }
+
+        // center the display in the window, with the background color around
+        // it, and only draw the pixels that aren't background
+        let left = (window.0 as int - (width * pixelsize) as int) / 2;
+        let top = (window.1 as int - (height * pixelsize) as int) / 2;
+        self.screen.fill_rect(None, colors[0]);
+        for row in range(0u, height) {
+            for col in range(0u, width) {
+                let value = gfx[col + row * width] as uint & 0x3;
+                if value == 0 {
+                    continue;
+                }
+                self.screen.fill_rect(Some(sdl::Rect {
+                    x: (left + (col * pixelsize) as int) as i16,
+                    y: (top + (row * pixelsize) as int) as i16,
+                    w: pixelsize as u16,
+                    h: pixelsize as u16
+                }), colors[value]);
+            }
+        }
+
+        self.screen.flip();
+
+        self.frame = gfx.to_vec();
+        self.frame_size = (width, height);
+    }
+
+    fn poll_input(&mut self) -> InputEvent {
+        loop {
+            match self.pending.pop_front() {
+                Some(event) => return event,
+                None => {}
+            }
+
+            match sdl::event::poll_event() {
+                sdl::event::Event::Quit => return InputEvent::Quit,
+                sdl::event::Event::None => return InputEvent::None,
+                sdl::event::Event::Resize(w, h) => if !self.display.fullscreen {
+                    self.change_video_mode((w as uint, h as uint), false)
+                },
+                sdl::event::Event::Key(k, pressed, _, _) =>
+                    match k {
+                        sdl::event::Key::Escape => return InputEvent::Quit,
+                        sdl::event::Key::F11 => if pressed {
+                            let (size, fullscreen) = (self.display.window_size(), !self.display.fullscreen);
+                            self.change_video_mode(size, fullscreen)
+                        },
+                        sdl::event::Key::F12 => if pressed { self.save_screenshot() },
+                        // hotkeys come first, so a keypad binding can't take one over
+                        _ => match SdlFrontend::map_hotkey(k, pressed) {
+                            Some(hotkey) => return InputEvent::Hotkey(hotkey),
+                            None => match self.map_key(k) {
+                                Some(mkey) => return InputEvent::Key(mkey, pressed),
+                                None => {}
+                            }
+                        }
+                    },
+                sdl::event::Event::JoyButton(_, button, pressed) =>
+                    match self.gamepad.lookup(format!("button{}", button).as_slice()) {
+                        Some(mkey) => return InputEvent::Key(mkey, pressed),
+                        None => {}
+                    },
+                sdl::event::Event::JoyHat(joystick, hat, state) => {
+                    let mut directions = [false; 4];
+                    for dir in state.iter() {
+                        match *dir {
+                            sdl::event::HatState::Up    => directions[0] = true,
+                            sdl::event::HatState::Down  => directions[1] = true,
+                            sdl::event::HatState::Left  => directions[2] = true,
+                            sdl::event::HatState::Right => directions[3] = true,
+                            _ => {}
+                        }
+                    }
+                    self.update_directions(DirectionSource::Hat(joystick, hat), directions);
+                },
+                // the first stick moves the same way as the D-pad
+                sdl::event::Event::JoyAxis(joystick, axis, value) if axis < 2 => {
+                    let source = DirectionSource::Stick(joystick);
+                    let mut directions = self.sources.get(&source).map_or([false; 4], |state| *state);
+                    let first = if axis == 0 { 2 } else { 0 };
+                    directions[first] = value < -AXIS_THRESHOLD;
+                    directions[first + 1] = value > AXIS_THRESHOLD;
+                    self.update_directions(source, directions);
+                },
+                _ => {}
+            }
+        }
+    }
+
+    fn set_beep(&mut self, on: bool) {
+        if self.audio_open && on != self.beeping {
+            sdl::audio::pause(!on);
+            self.beeping = on;
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8], rate: f32) {
+        self.tone.lock().unwrap().set_pattern(pattern, rate);
+    }
+
+    fn time_ns(&self) -> u64 {
+        time::precise_time_ns()
+    }
+
+    fn sleep_ms(&mut self, ms: u64) {
+        self.timer.sleep(Duration::milliseconds(ms as i64));
+    }
+}
@@ -0,0 +1,308 @@
+extern crate std;
+
+use std::io::{MemWriter, TempDir};
+use super::{DisplayConfig, Frontend, HeadlessFrontend, Image, ImageFormat, KeyMap, DEFAULT_PALETTE};
+use super::{VideoFrontend, VideoRecorder};
+use super::display::{parse_color, theme};
+use super::audio::{AudioConfig, ToneGenerator, WavRecorder, Waveform, SAMPLES_PER_FRAME};
+use super::{Hotkey, InputEvent};
+use super::tui::{TermKey, TuiMode, TuiScreen, hotkey_event, parse_input};
+use super::video::{lzw_encode, write_gif_image, write_y4m_header, write_y4m_frame};
+
+fn square_wave(frequency: f32) -> AudioConfig {
+    AudioConfig { frequency: frequency, volume: 0.5, waveform: Waveform::Square }
+}
+
+#[test]
+fn test_waveform_names() {
+    assert_eq!(Waveform::from_name("sine"), Some(Waveform::Sine));
+    assert_eq!(Waveform::from_name("square"), Some(Waveform::Square));
+    assert_eq!(Waveform::from_name("noise"), None);
+}
+
+#[test]
+fn test_display_themes() {
+    assert_eq!(theme("default"), Some(DEFAULT_PALETTE));
+    assert_eq!(theme("amber").unwrap()[1], (0xFF, 0xB0, 0x00));
+    assert_eq!(theme("sepia"), None);
+
+    assert_eq!(parse_color("#33ff66"), Some((0x33, 0xFF, 0x66)));
+    assert_eq!(parse_color("102030"), Some((0x10, 0x20, 0x30)));
+    assert_eq!(parse_color("#1234"), None);
+    assert_eq!(parse_color("zzzzzz"), None);
+}
+
+#[test]
+fn test_display_pixel_size() {
+    let mut display = DisplayConfig { palette: DEFAULT_PALETTE, scale: Some(8), fullscreen: false };
+    assert_eq!(display.window_size(), (512, 256));
+    assert_eq!(display.pixel_size(64, 32, (512, 256)), 8);
+    assert_eq!(display.pixel_size(128, 64, (512, 256)), 4);
+    assert_eq!(display.pixel_size(64, 32, (1920, 1080)), 8);
+
+    // fitting picks the largest whole number of pixels
+    display.scale = None;
+    assert_eq!(display.pixel_size(64, 32, (1920, 1080)), 30);
+    assert_eq!(display.pixel_size(128, 64, (1920, 1080)), 15);
+    assert_eq!(display.pixel_size(128, 64, (100, 50)), 1);
+}
+
+#[test]
+fn test_keymap_layouts() {
+    let keymap = KeyMap::layout("qwerty").unwrap();
+    assert_eq!(keymap.lookup("1"), Some(0x1));
+    assert_eq!(keymap.lookup("4"), Some(0xC));
+    assert_eq!(keymap.lookup("x"), Some(0x0));
+    assert_eq!(keymap.lookup("p"), None);
+
+    // several host keys can press the same keypad key
+    let keymap = KeyMap::layout("azerty").unwrap();
+    assert_eq!(keymap.lookup("a"), Some(0x4));
+    assert_eq!(keymap.lookup("&"), Some(0x1));
+    assert_eq!(keymap.lookup("1"), Some(0x1));
+
+    let keymap = KeyMap::layout("numpad").unwrap();
+    assert_eq!(keymap.lookup("kp7"), Some(0x1));
+    assert_eq!(keymap.lookup("kp0"), Some(0x0));
+    assert!(KeyMap::layout("colemak").is_none());
+}
+
+#[test]
+fn test_keymap_bind() {
+    let mut keymap = KeyMap::layout("qwerty").unwrap();
+    keymap.bind(0x5, vec!["W".to_string(), "up".to_string()]).unwrap();
+    keymap.bind(0x8, vec![]).unwrap();
+    keymap.bind(0x9, vec!["up".to_string(), "d".to_string()]).unwrap();
+    assert_eq!(keymap.lookup("w"), Some(0x5));
+    assert_eq!(keymap.lookup("up"), Some(0x9));
+    assert_eq!(keymap.lookup("s"), None);
+
+    // hotkeys can't be taken over by the keypad
+    assert_eq!(keymap.bind(0xD, vec!["P".to_string()]), Err("key is used by a hotkey: p".to_string()));
+    assert!(keymap.bind(0xD, vec!["=".to_string()]).is_err());
+
+    // only names the frontends use can be bound
+    assert!(keymap.bind(0x1, vec!["KP7".to_string(), "button12".to_string(), "é".to_string()]).is_ok());
+    assert!(keymap.bind(0x1, vec!["kp10".to_string()]).is_err());
+    assert!(keymap.bind(0x1, vec!["esc".to_string()]).is_err());
+    assert!(keymap.bind(0x1, vec!["buttons".to_string()]).is_err());
+    assert_eq!(keymap.lookup("kp7"), Some(0x1));
+}
+
+#[test]
+fn test_gamepad_profiles() {
+    let gamepad = KeyMap::gamepad("BLINKY");
+    assert_eq!(gamepad.lookup("up"), Some(0x5));
+    assert_eq!(gamepad.lookup("right"), Some(0x9));
+    assert_eq!(gamepad.lookup("button0"), Some(0x6));
+
+    // Pong's paddle is on 1 and 4, Invaders moves with 4 and 6 and fires with 5
+    let gamepad = KeyMap::gamepad("Pong");
+    assert_eq!(gamepad.lookup("up"), Some(0x1));
+    assert_eq!(gamepad.lookup("down"), Some(0x4));
+    let gamepad = KeyMap::gamepad("INVADERS");
+    assert_eq!(gamepad.lookup("left"), Some(0x4));
+    assert_eq!(gamepad.lookup("right"), Some(0x6));
+    assert_eq!(gamepad.lookup("button0"), Some(0x5));
+}
+
+#[test]
+fn test_square_wave() {
+    // 11025Hz gives a period of exactly 4 samples
+    let mut tone = ToneGenerator::new(square_wave(11025.0));
+    let mut samples = [0i16; 8];
+    tone.fill(&mut samples);
+
+    let high = (0.5 * std::i16::MAX as f32) as i16;
+    assert_eq!(samples.as_slice(), [high, high, -high, -high, high, high, -high, -high].as_slice());
+}
+
+#[test]
+fn test_wav_recorder() {
+    let mut recorder = WavRecorder::new(square_wave(440.0));
+    recorder.record_frame(false);
+    recorder.record_frame(true);
+
+    let samples = recorder.samples();
+    assert_eq!(samples.len(), SAMPLES_PER_FRAME * 2);
+    assert!(samples.slice_to(SAMPLES_PER_FRAME).iter().all(|s| *s == 0));
+    assert!(samples.slice_from(SAMPLES_PER_FRAME).iter().any(|s| *s != 0));
+
+    let mut w = MemWriter::new();
+    recorder.write(&mut w).unwrap();
+    let wav = w.into_inner();
+    assert_eq!(wav.slice_to(4), "RIFF".as_bytes());
+    assert_eq!(wav.len(), 44 + SAMPLES_PER_FRAME * 2 * 2);
+}
+
+#[test]
+fn test_headless_clock() {
+    let mut frontend = HeadlessFrontend::new();
+    assert_eq!(frontend.time_ns(), 0);
+    frontend.sleep_ms(16);
+    assert_eq!(frontend.time_ns(), 16000000);
+}
+
+fn draw_to_string(screen: &mut TuiScreen, gfx: &[u8], width: uint, height: uint) -> String {
+    let mut w = MemWriter::new();
+    screen.draw(gfx, width, height, &mut w).unwrap();
+    String::from_utf8(w.into_inner()).unwrap()
+}
+
+#[test]
+fn test_tui_half_blocks() {
+    let mut screen = TuiScreen::new(TuiMode::HalfBlock, DEFAULT_PALETTE);
+    // a 2x4 display fits in 2x2 cells, the top left pixel is set
+    let mut gfx = [0u8, 0, 0, 0, 0, 0, 0, 0];
+    gfx[0] = 1;
+    let out = draw_to_string(&mut screen, &gfx, 2, 4);
+    assert!(out.starts_with("\x1b[0m\x1b[2J\x1b[1;1H"));
+    assert_eq!(out.chars().filter(|c| *c == '\u{2580}').count(), 1);
+    assert_eq!(screen.rows(), 2);
+
+    // nothing changed, so nothing is drawn
+    assert_eq!(draw_to_string(&mut screen, &gfx, 2, 4).as_slice(), "\x1b[0m");
+
+    // only the changed cell in the bottom right is redrawn
+    gfx[7] = 2;
+    let out = draw_to_string(&mut screen, &gfx, 2, 4);
+    assert!(out.starts_with("\x1b[2;2H"));
+    assert_eq!(out.chars().filter(|c| *c == '\u{2580}').count(), 1);
+
+    // the status line goes just below the display, and survives it changing size
+    let mut w = MemWriter::new();
+    screen.draw_status("paused", &mut w).unwrap();
+    assert_eq!(String::from_utf8(w.into_inner()).unwrap().as_slice(), "\x1b[3;1H\x1b[Kpaused");
+    let out = draw_to_string(&mut screen, &[0u8; 4], 2, 2);
+    assert!(out.ends_with("\x1b[2;1H\x1b[Kpaused"));
+}
+
+#[test]
+fn test_tui_braille() {
+    let mut screen = TuiScreen::new(TuiMode::Braille, DEFAULT_PALETTE);
+    // the left column and bottom right pixel of a 2x4 cell
+    let gfx = [1u8, 0, 1, 0, 1, 0, 1, 1];
+    let out = draw_to_string(&mut screen, &gfx, 2, 4);
+    assert!(out.contains("\u{28C7}"));
+    assert_eq!(screen.rows(), 1);
+}
+
+#[test]
+fn test_tui_hotkeys() {
+    // Dvorak's P still pauses, its D key moves over to Y
+    let keymap = KeyMap::layout("dvorak").unwrap();
+    assert_eq!(keymap.lookup("p"), None);
+    assert_eq!(keymap.lookup("y"), Some(0xD));
+    assert!(match hotkey_event(TermKey::Char('p')) {
+        Some(InputEvent::Hotkey(Hotkey::Pause)) => true,
+        _ => false
+    });
+    assert!(match hotkey_event(TermKey::Escape) { Some(InputEvent::Quit) => true, _ => false });
+    assert!(hotkey_event(TermKey::Char('y')).is_none());
+}
+
+#[test]
+fn test_tui_parse_input() {
+    let (keys, used) = parse_input("q\x1b[A\x1b[15~\u{e9}".as_bytes());
+    assert_eq!(keys, vec![TermKey::Char('q'), TermKey::Up, TermKey::Function(5), TermKey::Char('\u{e9}')]);
+    assert_eq!(used, 11);
+
+    // incomplete sequences are left for the next read
+    let (keys, used) = parse_input(&[0x61, 0x1B, 0x5B, 0x31]);
+    assert_eq!(keys, vec![TermKey::Char('a')]);
+    assert_eq!(used, 1);
+    let (keys, used) = parse_input(&[0xC3]);
+    assert!(keys.is_empty());
+    assert_eq!(used, 0);
+
+    // an escape could be the start of a sequence until something else follows
+    let (keys, used) = parse_input(&[0x1B]);
+    assert!(keys.is_empty());
+    assert_eq!(used, 0);
+    assert_eq!(parse_input(&[0x1B, 0x71]).0, vec![TermKey::Escape, TermKey::Char('q')]);
+}
+
+fn write_image(image: &Image, format: ImageFormat) -> Vec<u8> {
+    let mut w = MemWriter::new();
+    image.write(format, &mut w).unwrap();
+    w.into_inner()
+}
+
+#[test]
+fn test_image_from_display() {
+    let image = Image::from_display(&[0, 1, 2, 3], 2, 2, 2, &DEFAULT_PALETTE);
+    assert_eq!((image.width, image.height), (4, 4));
+    assert_eq!(image.pixels.slice(0, 12), [0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF].as_slice());
+    assert_eq!(image.pixels.slice(36, 39), [0xFF, 0x66, 0x00].as_slice());
+}
+
+#[test]
+fn test_image_ppm_and_pbm() {
+    let image = Image::from_display(&[1, 0, 0, 1], 2, 2, 1, &DEFAULT_PALETTE);
+    let ppm = write_image(&image, ImageFormat::Ppm);
+    assert_eq!(ppm.slice_to(11), "P6\n2 2\n255\n".as_bytes());
+    assert_eq!(ppm.slice_from(11), [0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF].as_slice());
+
+    // dark pixels are set
+    let pbm = write_image(&image, ImageFormat::Pbm);
+    assert_eq!(pbm.slice_to(7), "P4\n2 2\n".as_bytes());
+    assert_eq!(pbm.slice_from(7), [0x40, 0x80].as_slice());
+}
+
+#[test]
+fn test_image_png() {
+    let image = Image::from_display(&[0; 64 * 32], 64, 32, 1, &DEFAULT_PALETTE);
+    let png = write_image(&image, ImageFormat::Png);
+    assert_eq!(png.slice_to(8), [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A].as_slice());
+    assert_eq!(png.slice(12, 16), "IHDR".as_bytes());
+    assert_eq!(png.slice(16, 24), [0, 0, 0, 64, 0, 0, 0, 32].as_slice());
+    assert_eq!(png.slice_from(png.len() - 12), [0, 0, 0, 0, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82].as_slice());
+    assert_eq!(ImageFormat::from_path(&Path::new("shot.pbm")), Some(ImageFormat::Pbm));
+}
+
+#[test]
+fn test_gif_lzw() {
+    assert_eq!(lzw_encode(&[0, 0, 0, 0, 1, 1, 1, 1, 2, 3]), vec![0x84, 0x11, 0x19, 0x32, 0x05]);
+    // the code size grows as the table fills, widening the end code
+    assert_eq!(lzw_encode(&[1; 64]), vec![0x8C, 0x8F, 0xA9, 0xCB, 0xED, 0x5D, 0x00]);
+}
+
+#[test]
+fn test_gif_image() {
+    let mut w = MemWriter::new();
+    write_gif_image(&mut w, &[1; 64], 8, 8, 2).unwrap();
+    let gif = w.into_inner();
+    assert_eq!(gif.slice_to(8), [0x21, 0xF9, 0x04, 0x00, 0x02, 0x00, 0x00, 0x00].as_slice());
+    assert_eq!(gif.slice(8, 20), [0x2C, 0, 0, 0, 0, 8, 0, 8, 0, 0x00, 0x02, 0x07].as_slice());
+    assert_eq!(gif.slice_from(27), [0x00].as_slice());
+}
+
+#[test]
+fn test_y4m_frames() {
+    let mut w = MemWriter::new();
+    write_y4m_header(&mut w, 128, 64).unwrap();
+    assert_eq!(w.get_ref(), "YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n".as_bytes());
+
+    let mut w = MemWriter::new();
+    let image = Image::from_display(&[0, 1], 2, 1, 1, &DEFAULT_PALETTE);
+    write_y4m_frame(&mut w, &image).unwrap();
+    assert_eq!(w.into_inner(), vec![0x46, 0x52, 0x41, 0x4D, 0x45, 0x0A, 16, 235, 128, 128, 128, 128]);
+}
+
+#[test]
+fn test_video_frames() {
+    let dir = TempDir::new("video").unwrap();
+    let mut recorder = VideoRecorder::create(&dir.path().join("out.y4m"), 1, &DEFAULT_PALETTE).unwrap();
+    {
+        let mut headless = HeadlessFrontend::new();
+        let mut frontend = VideoFrontend::new(&mut headless, &mut recorder);
+        // only the end of a frame records one, not the beep stopping at a
+        // breakpoint or pause
+        frontend.set_beep(false);
+        frontend.end_frame();
+        frontend.present(&[1u8; 64 * 32], 64, 32);
+        frontend.set_beep(false);
+        frontend.end_frame();
+    }
+    assert_eq!(recorder.finish().unwrap(), 2);
+}
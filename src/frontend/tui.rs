@@ -0,0 +1,529 @@
+extern crate time;
+
+use std::char;
+use std::cmp;
+use std::collections::RingBuf;
+use std::io;
+use std::io::{IoResult, Timer};
+use std::io::process::{Command, InheritFd};
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::thread::Thread;
+use std::time::Duration;
+use super::{Frontend, Hotkey, InputEvent, KeyMap, Palette};
+
+// Terminals only report keys going down, and then repeat them while they're
+// held, so a key counts as released once it hasn't repeated for this long.
+// The first repeat takes a lot longer to come than the ones after it.
+const KEY_HOLD_NS:       u64 = 150000000;
+const KEY_FIRST_HOLD_NS: u64 = 600000000;
+
+// How long read_line waits between checks for more input
+const INPUT_POLL_MS: i64 = 10;
+
+// An escape on its own could be the start of a sequence still being read, so
+// it only counts as the escape key once nothing has followed it for this long
+const ESCAPE_TIMEOUT_NS: u64 = 50000000;
+
+// Columns between the display and the register panel
+const PANEL_GAP: uint = 2;
+
+// How the display is packed into character cells: half blocks show two
+// pixels in each cell in any of the four colors, Braille shows a 2x4 block
+// of pixels but only one color per cell
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum TuiMode {
+    HalfBlock,
+    Braille
+}
+
+impl TuiMode {
+
+    pub fn from_name(name: &str) -> Option<TuiMode> {
+        match name {
+            "halfblock" => Some(TuiMode::HalfBlock),
+            "braille"   => Some(TuiMode::Braille),
+            _ => None
+        }
+    }
+
+    // Width and height in pixels of a character cell
+    fn cell_size(&self) -> (uint, uint) {
+        match *self {
+            TuiMode::HalfBlock => (1, 2),
+            TuiMode::Braille   => (2, 4)
+        }
+    }
+}
+
+// A character cell with the palette colors it's drawn in
+#[derive(Copy, Clone, PartialEq, Show)]
+struct Cell {
+    ch : char,
+    fg : u8,
+    bg : u8
+}
+
+// Draws graphics buffers with ANSI escape sequences, writing out only the
+// cells that changed since the last frame. The register panel goes to the
+// right of the display, and the status line just below it.
+pub struct TuiScreen {
+    mode    : TuiMode,
+    palette : Palette,
+    cols    : uint,
+    rows    : uint,
+    cells   : Vec<Cell>,
+    panel   : Vec<String>,
+    status  : String
+}
+
+impl TuiScreen {
+
+    pub fn new(mode: TuiMode, palette: Palette) -> TuiScreen {
+        TuiScreen { mode: mode, palette: palette, cols: 0, rows: 0, cells: Vec::new(), panel: Vec::new(),
+                    status: String::new() }
+    }
+
+    // Number of terminal rows taken up by the display
+    pub fn rows(&self) -> uint {
+        self.rows
+    }
+
+    pub fn draw(&mut self, gfx: &[u8], width: uint, height: uint, w: &mut Writer) -> IoResult<()> {
+        let (cell_width, cell_height) = self.mode.cell_size();
+        let (cols, rows) = (width / cell_width, height / cell_height);
+        let cells = self.render(gfx, width, cols, rows);
+
+        let full = cols != self.cols || rows != self.rows;
+        if full {
+            try!(w.write_str("\x1b[0m\x1b[2J"));
+        }
+
+        // only move the cursor when skipping over unchanged cells, and only
+        // change colors when they differ from the last cell written
+        let mut cursor = None;
+        let mut colors = None;
+        for row in range(0u, rows) {
+            for col in range(0u, cols) {
+                let cell = cells[col + row * cols];
+                if !full && cell == self.cells[col + row * cols] {
+                    continue;
+                }
+                if cursor != Some((row, col)) {
+                    try!(write!(w, "\x1b[{};{}H", row + 1, col + 1));
+                }
+                if colors != Some((cell.fg, cell.bg)) {
+                    try!(write_colors(w, self.palette[cell.fg as uint], self.palette[cell.bg as uint]));
+                    colors = Some((cell.fg, cell.bg));
+                }
+                try!(write!(w, "{}", cell.ch));
+                cursor = Some((row, col + 1));
+            }
+        }
+        try!(w.write_str("\x1b[0m"));
+
+        self.cols = cols;
+        self.rows = rows;
+        self.cells = cells;
+        if full {
+            let panel = self.panel.clone();
+            try!(self.draw_panel(panel, w));
+            let status = self.status.clone();
+            try!(self.draw_status(status.as_slice(), w));
+        }
+        w.flush()
+    }
+
+    // Replace the status line
+    pub fn draw_status(&mut self, status: &str, w: &mut Writer) -> IoResult<()> {
+        try!(write!(w, "\x1b[{};1H\x1b[K{}", self.rows + 1, status));
+        self.status = status.to_string();
+        w.flush()
+    }
+
+    // Replace the lines of the register panel
+    pub fn draw_panel(&mut self, lines: Vec<String>, w: &mut Writer) -> IoResult<()> {
+        for row in range(0u, cmp::max(lines.len(), self.panel.len())) {
+            try!(write!(w, "\x1b[{};{}H\x1b[K", row + 1, self.cols + PANEL_GAP + 1));
+            if row < lines.len() {
+                try!(w.write_str(lines[row].as_slice()));
+            }
+        }
+        self.panel = lines;
+        w.flush()
+    }
+
+    fn render(&self, gfx: &[u8], width: uint, cols: uint, rows: uint) -> Vec<Cell> {
+        let pixel = |&: x: uint, y: uint| gfx[x + y * width] & 0x3;
+        let mut cells = Vec::with_capacity(cols * rows);
+        for row in range(0u, rows) {
+            for col in range(0u, cols) {
+                cells.push(match self.mode {
+                    TuiMode::HalfBlock => {
+                        let (top, bottom) = (pixel(col, row * 2), pixel(col, row * 2 + 1));
+                        if top == bottom {
+                            Cell { ch: ' ', fg: top, bg: bottom }
+                        } else {
+                            Cell { ch: '\u{2580}', fg: top, bg: bottom }
+                        }
+                    },
+                    TuiMode::Braille => {
+                        // the highest numbered color in the cell is the one shown
+                        let mut dots = 0u32;
+                        let mut fg = 0u8;
+                        for &(dx, dy, bit) in BRAILLE_DOTS.iter() {
+                            let value = pixel(col * 2 + dx, row * 4 + dy);
+                            if value != 0 {
+                                dots |= bit;
+                                fg = cmp::max(fg, value);
+                            }
+                        }
+                        let ch = if dots == 0 { ' ' } else { char::from_u32(0x2800 + dots).unwrap() };
+                        Cell { ch: ch, fg: fg, bg: 0 }
+                    }
+                });
+            }
+        }
+        cells
+    }
+}
+
+// The bit for each pixel of a Braille cell, by position
+static BRAILLE_DOTS : [(uint, uint, u32); 8] =
+    [(0, 0, 0x01), (0, 1, 0x02), (0, 2, 0x04), (1, 0, 0x08),
+     (1, 1, 0x10), (1, 2, 0x20), (0, 3, 0x40), (1, 3, 0x80)];
+
+fn write_colors(w: &mut Writer, fore: (u8, u8, u8), back: (u8, u8, u8)) -> IoResult<()> {
+    write!(w, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m", fore.0, fore.1, fore.2, back.0, back.1, back.2)
+}
+
+// A key read from the terminal
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum TermKey {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Function(uint),
+    Escape
+}
+
+// Decode the keys at the start of some terminal input, returning them and
+// the number of bytes used. A key split across reads is left for later.
+pub fn parse_input(bytes: &[u8]) -> (Vec<TermKey>, uint) {
+    let mut keys = Vec::new();
+    let mut pos = 0u;
+    while pos < bytes.len() {
+        let (key, len) = match parse_key(bytes.slice_from(pos)) {
+            Some(parsed) => parsed,
+            None => break
+        };
+        match key {
+            Some(key) => keys.push(key),
+            None => {}
+        }
+        pos += len;
+    }
+    (keys, pos)
+}
+
+// The key at the start of bytes and its length, the key is None for escape
+// sequences that aren't understood. A lone escape is treated as incomplete,
+// it's up to the caller to decide when it's really the escape key.
+fn parse_key(bytes: &[u8]) -> Option<(Option<TermKey>, uint)> {
+    match bytes[0] {
+        // a CSI sequence, parameters then a final byte
+        0x1B if bytes.len() > 1 && bytes[1] == '[' as u8 => {
+            let end = match bytes.iter().skip(2).position(|b| *b >= 0x40 && *b <= 0x7E) {
+                Some(end) => end + 2,
+                None => return None
+            };
+            let params = str::from_utf8(bytes.slice(2, end)).ok().and_then(|p| from_str::<uint>(p));
+            let key = match (bytes[end] as char, params) {
+                ('A', _) => Some(TermKey::Up),
+                ('B', _) => Some(TermKey::Down),
+                ('C', _) => Some(TermKey::Right),
+                ('D', _) => Some(TermKey::Left),
+                ('~', Some(15)) => Some(TermKey::Function(5)),
+                ('~', Some(n)) if n >= 17 && n <= 21 => Some(TermKey::Function(n - 11)),
+                _ => None
+            };
+            Some((key, end + 1))
+        },
+        0x1B if bytes.len() == 1 => None,
+        0x1B => Some((Some(TermKey::Escape), 1)),
+        byte => {
+            // UTF-8 characters are one to four bytes long
+            let len = if byte < 0xC0 { 1 } else if byte < 0xE0 { 2 } else if byte < 0xF0 { 3 } else { 4 };
+            if bytes.len() < len {
+                return None;
+            }
+            match str::from_utf8(bytes.slice_to(len)) {
+                Ok(s) => Some((Some(TermKey::Char(s.char_at(0))), len)),
+                Err(_) => Some((None, 1))
+            }
+        }
+    }
+}
+
+// A frontend for ANSI terminals, so games can be played over SSH or where
+// there's no SDL. The terminal is put into non-canonical mode with stty and
+// keys are read from stdin by a separate thread.
+pub struct TuiFrontend {
+    screen        : TuiScreen,
+    keymap        : KeyMap,
+    input         : Arc<Mutex<RingBuf<u8>>>,
+    escape_since  : Option<u64>,
+    // when each held key was last seen, and whether it's repeated yet
+    held          : [Option<(u64, bool)>; 16],
+    pending       : RingBuf<InputEvent>,
+    timer         : Timer,
+    beeping       : bool,
+    reset_console : bool,
+    saved_tty     : Option<String>
+}
+
+impl TuiFrontend {
+
+    pub fn new(mode: TuiMode, palette: Palette, keymap: KeyMap) -> TuiFrontend {
+        let saved_tty = TuiFrontend::stty(&["-g"]).map(|s| s.as_slice().trim().to_string());
+        TuiFrontend::stty(&["-icanon", "-echo", "-isig", "min", "1"]);
+        print!("\x1b[?25l");
+
+        let input = Arc::new(Mutex::new(RingBuf::new()));
+        let buffer = input.clone();
+        Thread::spawn(move || {
+            let mut stdin = io::stdin();
+            loop {
+                match stdin.read_byte() {
+                    Ok(byte) => buffer.lock().unwrap().push_back(byte),
+                    Err(_) => return
+                }
+            }
+        }).detach();
+
+        TuiFrontend {
+            screen        : TuiScreen::new(mode, palette),
+            keymap        : keymap,
+            input         : input,
+            escape_since  : None,
+            held          : [None; 16],
+            pending       : RingBuf::new(),
+            timer         : Timer::new().unwrap(),
+            beeping       : false,
+            reset_console : true,
+            saved_tty     : saved_tty
+        }
+    }
+
+    // Move to the debugger's console below the display, clearing it if the
+    // display has changed since it was last used
+    fn start_console(&mut self) {
+        if self.reset_console {
+            let mut stdout = io::stdout();
+            let _ = write!(&mut stdout, "\x1b[{};1H\x1b[J", self.screen.rows() + 2);
+            let _ = stdout.flush();
+            self.reset_console = false;
+        }
+    }
+
+    // Run stty on the terminal, returning what it printed
+    fn stty(args: &[&str]) -> Option<String> {
+        match Command::new("stty").args(args).stdin(InheritFd(0)).output() {
+            Ok(ref output) if output.status.success() => String::from_utf8(output.output.clone()).ok(),
+            _ => None
+        }
+    }
+
+    // Take the keys typed since the last call
+    fn read_keys(&mut self) -> Vec<TermKey> {
+        let now = self.time_ns();
+        let mut input = self.input.lock().unwrap();
+        let bytes: Vec<u8> = input.iter().map(|b| *b).collect();
+        let (mut keys, used) = parse_input(bytes.as_slice());
+        for _ in range(0u, used) {
+            input.pop_front();
+        }
+
+        // an escape left waiting long enough was the escape key
+        if used + 1 == bytes.len() && bytes[used] == 0x1B {
+            match self.escape_since {
+                Some(since) if now - since >= ESCAPE_TIMEOUT_NS => {
+                    keys.push(TermKey::Escape);
+                    input.pop_front();
+                    self.escape_since = None;
+                },
+                Some(_) => {},
+                None => self.escape_since = Some(now)
+            }
+        } else {
+            self.escape_since = None;
+        }
+        keys
+    }
+
+    // Queue up the input events for a key typed at time now. Hotkeys come
+    // first, so a keypad binding can't take one over.
+    fn handle_key(&mut self, key: TermKey, now: u64) {
+        match hotkey_event(key) {
+            Some(event) => {
+                self.pending.push_back(event);
+                return;
+            },
+            None => {}
+        }
+
+        let name = match key {
+            TermKey::Char(' ') => "space".to_string(),
+            TermKey::Char(c) => c.to_lowercase().to_string(),
+            TermKey::Up => "up".to_string(),
+            TermKey::Down => "down".to_string(),
+            TermKey::Left => "left".to_string(),
+            TermKey::Right => "right".to_string(),
+            _ => String::new()
+        };
+        match self.keymap.lookup(name.as_slice()) {
+            Some(k) => {
+                let repeated = self.held[k as uint].is_some();
+                if !repeated {
+                    self.pending.push_back(InputEvent::Key(k, true));
+                }
+                self.held[k as uint] = Some((now, repeated));
+                return;
+            },
+            None => {}
+        }
+    }
+}
+
+// The event for a key that's one of the emulator's controls. Without key
+// releases, only the hotkeys that are pressed once work.
+pub fn hotkey_event(key: TermKey) -> Option<InputEvent> {
+    match key {
+        TermKey::Escape | TermKey::Char('\x03') => Some(InputEvent::Quit),
+        TermKey::Char('=') => Some(InputEvent::Hotkey(Hotkey::SpeedUp)),
+        TermKey::Char('-') => Some(InputEvent::Hotkey(Hotkey::SpeedDown)),
+        TermKey::Char('p') => Some(InputEvent::Hotkey(Hotkey::Pause)),
+        TermKey::Function(5) => Some(InputEvent::Hotkey(Hotkey::SaveState)),
+        TermKey::Function(9) => Some(InputEvent::Hotkey(Hotkey::LoadState)),
+        TermKey::Function(6) => Some(InputEvent::Hotkey(Hotkey::PrevSlot)),
+        TermKey::Function(7) => Some(InputEvent::Hotkey(Hotkey::NextSlot)),
+        _ => None
+    }
+}
+
+impl Drop for TuiFrontend {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[{};1H\x1b[J\x1b[?25h", self.screen.rows() + 1);
+        let _ = io::stdout().flush();
+        match self.saved_tty {
+            Some(ref settings) => { TuiFrontend::stty(&[settings.as_slice()]); },
+            None => { TuiFrontend::stty(&["sane"]); }
+        }
+    }
+}
+
+impl Frontend for TuiFrontend {
+
+    fn present(&mut self, gfx: &[u8], width: uint, height: uint) {
+        let _ = self.screen.draw(gfx, width, height, &mut io::stdout());
+        self.reset_console = true;
+    }
+
+    fn poll_input(&mut self) -> InputEvent {
+        if self.pending.is_empty() {
+            let now = self.time_ns();
+            for key in self.read_keys().into_iter() {
+                self.handle_key(key, now);
+            }
+            for k in range(0u, self.held.len()) {
+                match self.held[k] {
+                    Some((seen, repeated)) => {
+                        let timeout = if repeated { KEY_HOLD_NS } else { KEY_FIRST_HOLD_NS };
+                        if now - seen > timeout {
+                            self.held[k] = None;
+                            self.pending.push_back(InputEvent::Key(k as u8, false));
+                        }
+                    },
+                    None => {}
+                }
+            }
+        }
+        self.pending.pop_front().unwrap_or(InputEvent::None)
+    }
+
+    // There's no tone, but the terminal bell rings as each beep starts
+    fn set_beep(&mut self, on: bool) {
+        if on && !self.beeping {
+            print!("\x07");
+            let _ = io::stdout().flush();
+        }
+        self.beeping = on;
+    }
+
+    fn set_pattern(&mut self, _pattern: &[u8], _rate: f32) {}
+
+    fn time_ns(&self) -> u64 {
+        time::precise_time_ns()
+    }
+
+    fn sleep_ms(&mut self, ms: u64) {
+        self.timer.sleep(Duration::milliseconds(ms as i64));
+    }
+
+    // The debugger's prompt and output go below the display, starting afresh
+    // each time the display changes
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        let mut stdout = io::stdout();
+        self.start_console();
+        let _ = write!(&mut stdout, "\x1b[?25h{}", prompt);
+        let _ = stdout.flush();
+
+        let mut line = String::new();
+        loop {
+            for key in self.read_keys().into_iter() {
+                match key {
+                    TermKey::Char('\r') | TermKey::Char('\n') => {
+                        let _ = write!(&mut stdout, "\r\n\x1b[?25l");
+                        let _ = stdout.flush();
+                        line.push('\n');
+                        return Some(line);
+                    },
+                    TermKey::Char('\x04') if line.is_empty() => return None,
+                    TermKey::Char('\x7F') | TermKey::Char('\x08') => {
+                        if line.pop().is_some() {
+                            let _ = write!(&mut stdout, "\x08 \x08");
+                        }
+                    },
+                    TermKey::Char(c) if c >= ' ' => {
+                        line.push(c);
+                        let _ = write!(&mut stdout, "{}", c);
+                    },
+                    _ => {}
+                }
+            }
+            let _ = stdout.flush();
+            self.timer.sleep(Duration::milliseconds(INPUT_POLL_MS));
+        }
+    }
+
+    // Lines end with a carriage return too, as the terminal may not add one
+    fn write_output(&mut self, text: &str) {
+        let mut stdout = io::stdout();
+        self.start_console();
+        for line in text.lines() {
+            let _ = write!(&mut stdout, "{}\x1b[K\r\n", line);
+        }
+        let _ = stdout.flush();
+    }
+
+    fn show_registers(&mut self, lines: Vec<String>) {
+        let _ = self.screen.draw_panel(lines, &mut io::stdout());
+    }
+
+    // Printing would scroll the display, so status goes on a line of its own
+    fn show_status(&mut self, message: &str) {
+        let _ = self.screen.draw_status(message, &mut io::stdout());
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::io::{BufferedWriter, File, IoError, IoResult, InvalidInput};
+use chip8impl::FRAME_RATE;
+use super::{Frontend, InputEvent};
+use super::image::{Image, ImageFormat, scale_display};
+
+// Video frames at scale 1 are the size of the high resolution display, so
+// they stay the same size when a ROM switches resolution
+const VIDEO_WIDTH:  uint = 128;
+const VIDEO_HEIGHT: uint = 64;
+
+// GIF viewers slow down frames shorter than this many hundredths of a second,
+// so displays that change any faster have frames dropped
+const MIN_GIF_DELAY: uint = 2;
+
+// Bits per pixel of GIF frames, enough for the four palette colors, and the
+// largest LZW code size
+const GIF_CODE_SIZE: uint = 2;
+const MAX_CODE_SIZE: uint = 12;
+
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum VideoFormat {
+    Gif,
+    PngSequence,
+    Y4m
+}
+
+impl VideoFormat {
+
+    // The format that goes with a file's extension, .gif, .png or .y4m
+    pub fn from_path(path: &Path) -> Option<VideoFormat> {
+        match path.extension_str() {
+            Some("gif") => Some(VideoFormat::Gif),
+            Some("png") => Some(VideoFormat::PngSequence),
+            Some("y4m") => Some(VideoFormat::Y4m),
+            _ => None
+        }
+    }
+}
+
+// Records the display of every 60Hz frame as an animated GIF, a Y4M stream or
+// a sequence of PNG files numbered from 1. Frames are the size of the high
+// resolution display times the scale, with low resolution pixels doubled.
+pub struct VideoRecorder {
+    format    : VideoFormat,
+    path      : Path,
+    out       : Option<BufferedWriter<File>>,
+    width     : uint,
+    height    : uint,
+    palette   : Vec<(u8, u8, u8)>,
+    display   : Vec<u8>,
+    frames    : uint,
+    gif_frame : Option<(Vec<u8>, uint)>,
+    error     : Option<IoError>
+}
+
+impl VideoRecorder {
+
+    pub fn create(path: &Path, scale: uint, palette: &[(u8, u8, u8)]) -> IoResult<VideoRecorder> {
+        let format = match VideoFormat::from_path(path) {
+            Some(format) => format,
+            None => return Err(IoError {
+                kind: InvalidInput, desc: "video files must end in .gif, .png or .y4m", detail: None
+            })
+        };
+        let (width, height) = (VIDEO_WIDTH * scale, VIDEO_HEIGHT * scale);
+        let out = match format {
+            VideoFormat::Gif => {
+                let mut out = BufferedWriter::new(try!(File::create(path)));
+                try!(write_gif_header(&mut out, width, height, palette));
+                Some(out)
+            },
+            VideoFormat::Y4m => {
+                let mut out = BufferedWriter::new(try!(File::create(path)));
+                try!(write_y4m_header(&mut out, width, height));
+                Some(out)
+            },
+            VideoFormat::PngSequence => None
+        };
+
+        Ok(VideoRecorder {
+            format    : format,
+            path      : path.clone(),
+            out       : out,
+            width     : width,
+            height    : height,
+            palette   : palette.to_vec(),
+            display   : Vec::from_elem(width * height, 0u8),
+            frames    : 0,
+            gif_frame : None,
+            error     : None
+        })
+    }
+
+    // The display shown from this frame on
+    pub fn present(&mut self, gfx: &[u8], width: uint, height: uint) {
+        self.display = scale_display(gfx, width, height, self.width / width);
+    }
+
+    // Record the display as it was at the end of a frame
+    pub fn end_frame(&mut self) {
+        self.record_frame();
+    }
+
+    // Finish the file, returning how many frames were recorded
+    pub fn finish(mut self) -> IoResult<uint> {
+        match self.error.take() {
+            Some(err) => return Err(err),
+            None => {}
+        }
+        if self.format == VideoFormat::Gif {
+            let frames = self.frames;
+            try!(self.write_gif_frame(frames));
+            try!(self.out.as_mut().unwrap().write_u8(0x3B));
+        }
+        match self.out {
+            Some(ref mut out) => try!(out.flush()),
+            None => {}
+        }
+        Ok(self.frames)
+    }
+
+    // Once writing fails, the rest of the frames are skipped and the error
+    // is returned by finish
+    fn record_frame(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = match self.format {
+            VideoFormat::Gif => self.record_gif_frame(),
+            VideoFormat::Y4m => {
+                let image = self.image();
+                write_y4m_frame(self.out.as_mut().unwrap(), &image)
+            },
+            VideoFormat::PngSequence => {
+                let name = self.path.filestem_str().unwrap_or("frame");
+                let path = self.path.with_filename(format!("{}-{:05}.png", name, self.frames + 1));
+                File::create(&path).and_then(|mut file| self.image().write(ImageFormat::Png, &mut file))
+            }
+        };
+        match result {
+            Ok(()) => self.frames += 1,
+            Err(err) => self.error = Some(err)
+        }
+    }
+
+    fn image(&self) -> Image {
+        Image::from_indices(self.display.as_slice(), self.width, self.height, self.palette.as_slice())
+    }
+
+    // GIF frames last until the display next changes, so a frame is only
+    // written once it's known how long it was shown for
+    fn record_gif_frame(&mut self) -> IoResult<()> {
+        let frame = self.frames;
+        let (changed, start) = match self.gif_frame {
+            Some((ref image, start)) => (*image != self.display, start),
+            None => (true, frame)
+        };
+        if !changed {
+            return Ok(());
+        }
+        let start = if gif_time(frame) - gif_time(start) < MIN_GIF_DELAY {
+            start
+        } else {
+            try!(self.write_gif_frame(frame));
+            frame
+        };
+        self.gif_frame = Some((self.display.clone(), start));
+        Ok(())
+    }
+
+    // Write the GIF frame that's waiting for the display to change, which it
+    // did at the start of the given frame
+    fn write_gif_frame(&mut self, end: uint) -> IoResult<()> {
+        match self.gif_frame.take() {
+            Some((image, start)) =>
+                write_gif_image(self.out.as_mut().unwrap(), image.as_slice(), self.width, self.height,
+                                gif_time(end) - gif_time(start)),
+            None => Ok(())
+        }
+    }
+}
+
+// Passes everything through to another frontend, recording the frames it
+// presents along the way
+pub struct VideoFrontend<'a> {
+    frontend : &'a mut (Frontend + 'a),
+    recorder : &'a mut VideoRecorder
+}
+
+impl<'a> VideoFrontend<'a> {
+
+    pub fn new(frontend: &'a mut (Frontend + 'a), recorder: &'a mut VideoRecorder) -> VideoFrontend<'a> {
+        VideoFrontend { frontend: frontend, recorder: recorder }
+    }
+}
+
+impl<'a> Frontend for VideoFrontend<'a> {
+
+    fn present(&mut self, gfx: &[u8], width: uint, height: uint) {
+        self.recorder.present(gfx, width, height);
+        self.frontend.present(gfx, width, height);
+    }
+
+    fn poll_input(&mut self) -> InputEvent {
+        self.frontend.poll_input()
+    }
+
+    fn set_beep(&mut self, on: bool) {
+        self.frontend.set_beep(on);
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8], rate: f32) {
+        self.frontend.set_pattern(pattern, rate);
+    }
+
+    fn time_ns(&self) -> u64 {
+        self.frontend.time_ns()
+    }
+
+    fn sleep_ms(&mut self, ms: u64) {
+        self.frontend.sleep_ms(ms);
+    }
+
+    fn end_frame(&mut self) {
+        self.recorder.end_frame();
+        self.frontend.end_frame();
+    }
+
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        self.frontend.read_line(prompt)
+    }
+
+    fn write_output(&mut self, text: &str) {
+        self.frontend.write_output(text);
+    }
+
+    fn show_registers(&mut self, lines: Vec<String>) {
+        self.frontend.show_registers(lines);
+    }
+
+    fn show_status(&mut self, message: &str) {
+        self.frontend.show_status(message);
+    }
+}
+
+// The start of a frame in hundredths of a second, the unit of GIF delays
+fn gif_time(frame: uint) -> uint {
+    (frame * 100 + FRAME_RATE as uint / 2) / FRAME_RATE as uint
+}
+
+// GIF89a with a four color palette, looping forever
+pub fn write_gif_header(w: &mut Writer, width: uint, height: uint, palette: &[(u8, u8, u8)]) -> IoResult<()> {
+    try!(w.write_str("GIF89a"));
+    try!(w.write_le_u16(width as u16));
+    try!(w.write_le_u16(height as u16));
+    try!(w.write(&[0xF1, 0x00, 0x00]));
+    for &(r, g, b) in palette.iter().take(1 << GIF_CODE_SIZE) {
+        try!(w.write(&[r, g, b]));
+    }
+    try!(w.write(&[0x21, 0xFF, 0x0B]));
+    try!(w.write_str("NETSCAPE2.0"));
+    w.write(&[0x03, 0x01, 0x00, 0x00, 0x00])
+}
+
+// A full size image of palette indices shown for delay hundredths of a second
+pub fn write_gif_image(w: &mut Writer, indices: &[u8], width: uint, height: uint, delay: uint) -> IoResult<()> {
+    try!(w.write(&[0x21, 0xF9, 0x04, 0x00]));
+    try!(w.write_le_u16(delay as u16));
+    try!(w.write(&[0x00, 0x00, 0x2C, 0x00, 0x00, 0x00, 0x00]));
+    try!(w.write_le_u16(width as u16));
+    try!(w.write_le_u16(height as u16));
+    try!(w.write(&[0x00, GIF_CODE_SIZE as u8]));
+    for block in lzw_encode(indices).chunks(255) {
+        try!(w.write_u8(block.len() as u8));
+        try!(w.write(block));
+    }
+    w.write_u8(0x00)
+}
+
+// Compress palette indices the way GIF does, starting with a clear code and
+// starting over with another one whenever the code table fills up
+pub fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << GIF_CODE_SIZE;
+    let end = clear + 1;
+    let mut packer = BitPacker { bytes: Vec::new(), bits: 0, count: 0 };
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = GIF_CODE_SIZE + 1;
+
+    packer.push(clear, size);
+    let mut prefix = indices[0] as u16;
+    for &index in indices.slice_from(1).iter() {
+        match codes.get(&(prefix, index)) {
+            Some(&code) => {
+                prefix = code;
+                continue;
+            },
+            None => {}
+        }
+        packer.push(prefix, size);
+        if next as uint == 1 << MAX_CODE_SIZE {
+            packer.push(clear, size);
+            codes.clear();
+            next = end + 1;
+            size = GIF_CODE_SIZE + 1;
+        } else {
+            codes.insert((prefix, index), next);
+            next += 1;
+            if next as uint > 1 << size && size < MAX_CODE_SIZE {
+                size += 1;
+            }
+        }
+        prefix = index as u16;
+    }
+    packer.push(prefix, size);
+
+    // the decoder adds a code after the last one too, which can widen the end
+    // code
+    if next as uint == 1 << size && size < MAX_CODE_SIZE {
+        size += 1;
+    }
+    packer.push(end, size);
+    packer.finish()
+}
+
+// Packs variable sized codes into bytes, least significant bit first
+struct BitPacker {
+    bytes : Vec<u8>,
+    bits  : u32,
+    count : uint
+}
+
+impl BitPacker {
+
+    fn push(&mut self, code: u16, size: uint) {
+        self.bits |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+// Uncompressed 4:4:4 YCbCr at 60 frames per second, which ffmpeg reads
+// directly
+pub fn write_y4m_header(w: &mut Writer, width: uint, height: uint) -> IoResult<()> {
+    write!(w, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444\n", width, height, FRAME_RATE)
+}
+
+// The Y, Cb and Cr planes of an image, with BT.601 video range colors
+pub fn write_y4m_frame(w: &mut Writer, image: &Image) -> IoResult<()> {
+    try!(w.write_str("FRAME\n"));
+    let mut planes = [Vec::new(), Vec::new(), Vec::new()];
+    for rgb in image.pixels.chunks(3) {
+        let (r, g, b) = (rgb[0] as int, rgb[1] as int, rgb[2] as int);
+        planes[0].push((((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8);
+        planes[1].push((((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8);
+        planes[2].push((((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8);
+    }
+    for plane in planes.iter() {
+        try!(w.write(plane.as_slice()));
+    }
+    Ok(())
+}
@@ -1,10 +1,372 @@
 extern crate getopts;
 extern crate sdl;
+extern crate time;
+extern crate toml;
 
 use std::os;
-use getopts::{optopt,optflag,getopts};
+use std::io;
+use std::io::File;
+use std::io::fs::PathExtensions;
+use std::default::Default;
+use getopts::{optopt,optflag,optflagopt,getopts};
 
+mod assembler;
 mod chip8impl;
+mod config;
+mod frontend;
+
+// cycle budget for headless runs when --cycles isn't given
+const DEFAULT_HEADLESS_CYCLES: uint = 100000;
+
+// config file looked for in the home directory when --config isn't given
+const CONFIG_FILE: &'static str = ".rustychip8.toml";
+
+// seconds of gameplay kept for rewinding when --rewind isn't given
+const DEFAULT_REWIND_SECONDS: uint = 10;
+
+fn parse_hex(s: &str) -> Option<u16> {
+    let digits = if s.starts_with("0x") { s.slice_from(2) } else { s };
+    std::num::from_str_radix::<u16>(digits, 16)
+}
+
+fn opt_hex(matches: &getopts::Matches, name: &str) -> Option<u16> {
+    matches.opt_str(name).map(|s| match parse_hex(s.as_slice()) {
+        Some(n) => n,
+        None => panic!("invalid value for --{}: {}", name, s)
+    })
+}
+
+fn opt_uint(matches: &getopts::Matches, name: &str) -> Option<uint> {
+    matches.opt_str(name).map(|s| match from_str::<uint>(s.as_slice()) {
+        Some(n) => n,
+        None => panic!("invalid value for --{}: {}", name, s)
+    })
+}
+
+// A scale factor, which has to be at least 1
+fn opt_scale(matches: &getopts::Matches, name: &str) -> Option<uint> {
+    matches.opt_str(name).map(|s| match from_str::<uint>(s.as_slice()) {
+        Some(n) if n > 0 => n,
+        _ => panic!("invalid value for --{}: {}", name, s)
+    })
+}
+
+fn opt_color(matches: &getopts::Matches, name: &str) -> Option<(u8, u8, u8)> {
+    matches.opt_str(name).map(|s| match frontend::display::parse_color(s.as_slice()) {
+        Some(color) => color,
+        None => panic!("invalid value for --{}: {}", name, s)
+    })
+}
+
+fn audio_config(matches: &getopts::Matches) -> frontend::AudioConfig {
+    let mut config: frontend::AudioConfig = Default::default();
+    match matches.opt_str("tone-freq") {
+        Some(s) => config.frequency = match from_str::<f32>(s.as_slice()) {
+            Some(freq) => freq,
+            None => panic!("invalid value for --tone-freq: {}", s)
+        },
+        None => {}
+    }
+    match opt_uint(matches, "volume") {
+        Some(volume) => config.volume = std::cmp::min(volume, 100) as f32 / 100.0,
+        None => {}
+    }
+    match matches.opt_str("waveform") {
+        Some(s) => config.waveform = match frontend::Waveform::from_name(s.as_slice()) {
+            Some(waveform) => waveform,
+            None => panic!("unknown waveform: {}", s)
+        },
+        None => {}
+    }
+    config
+}
+
+// The display settings from the config, with the ones given on the command
+// line on top
+fn display_config(config: &config::Config, romfile: &str,
+                  matches: &getopts::Matches) -> Option<frontend::DisplayConfig> {
+    let theme = matches.opt_str("theme");
+    let mut display = match config.display(romfile, theme.as_ref().map(|s| s.as_slice())) {
+        Ok(display) => display,
+        Err(err) => {
+            println!("invalid display settings: {}", err);
+            return None;
+        }
+    };
+    match opt_color(matches, "bg") {
+        Some(color) => display.palette[0] = color,
+        None => {}
+    }
+    match opt_color(matches, "fg") {
+        Some(color) => display.palette[1] = color,
+        None => {}
+    }
+    match matches.opt_str("scale") {
+        Some(ref s) if s.as_slice() == "fit" => display.scale = None,
+        Some(_) => display.scale = opt_scale(matches, "scale"),
+        None => {}
+    }
+    if matches.opt_present("fullscreen") {
+        display.fullscreen = true;
+    }
+    Some(display)
+}
+
+fn run_headless(emu: &mut chip8impl::Chip8, matches: &getopts::Matches, movie_cycles: Option<uint>,
+                palette: &frontend::Palette) {
+    // a movie is played to the end unless told otherwise
+    let limits = chip8impl::RunLimits {
+        cycles : opt_uint(matches, "cycles").or(movie_cycles).unwrap_or(DEFAULT_HEADLESS_CYCLES),
+        pc     : opt_hex(matches, "until-pc"),
+        opcode : opt_hex(matches, "until-opcode")
+    };
+
+    let mut frontend = frontend::HeadlessFrontend::new();
+    if matches.opt_present("audio-out") {
+        frontend.record_audio(audio_config(matches));
+    }
+
+    let result = match open_video(matches, palette) {
+        Some(mut recorder) => {
+            let result = emu.run_until(&limits, &mut frontend::VideoFrontend::new(&mut frontend, &mut recorder));
+            finish_video(recorder);
+            result
+        },
+        None => emu.run_until(&limits, &mut frontend)
+    };
+    match result {
+        Ok((cycles, reason)) => println!("stopped after {} cycles: {}", cycles, reason),
+        Err(err) => println!("emulation halted: {}", err)
+    }
+
+    match matches.opt_str("audio-out") {
+        Some(f) => match File::create(&Path::new(f.as_slice())) {
+            Ok(mut file) => match frontend.write_audio(&mut file) {
+                Ok(()) => {},
+                Err(err) => println!("failed to write audio: {}", err)
+            },
+            Err(err) => println!("failed to write audio: {}", err)
+        },
+        None => {}
+    }
+
+    match matches.opt_str("screenshot") {
+        Some(f) => save_screenshot(emu, f.as_slice(), opt_scale(matches, "screenshot-scale").unwrap_or(1), palette),
+        None => {}
+    }
+
+    let result = match matches.opt_str("dump") {
+        Some(f) => match File::create(&Path::new(f.as_slice())) {
+            Ok(mut file) => emu.dump_state(&mut file),
+            Err(err) => Err(err)
+        },
+        None => emu.dump_state(&mut io::stdout())
+    };
+    match result {
+        Ok(()) => {},
+        Err(err) => println!("failed to write machine state: {}", err)
+    }
+}
+
+// Save the display as an image, in the format given by the file's extension
+fn save_screenshot(emu: &chip8impl::Chip8, filename: &str, scale: uint, palette: &frontend::Palette) {
+    let (width, height) = emu.screen_size();
+    let image = frontend::Image::from_display(emu.display(), width, height, scale, palette);
+    match image.save(&Path::new(filename)) {
+        Ok(()) => {},
+        Err(err) => println!("failed to write screenshot: {}", err)
+    }
+}
+
+// How F12 saves screenshots, next to the ROM
+fn screenshot_config(romfile: &str, matches: &getopts::Matches) -> frontend::ScreenshotConfig {
+    let format = match matches.opt_str("screenshot-format") {
+        Some(name) => match frontend::ImageFormat::from_name(name.as_slice()) {
+            Some(format) => format,
+            None => panic!("unknown screenshot format: {}", name)
+        },
+        None => frontend::ImageFormat::Png
+    };
+    frontend::ScreenshotConfig {
+        prefix : Path::new(romfile),
+        format : format,
+        scale  : opt_scale(matches, "screenshot-scale")
+    }
+}
+
+// Start recording the video given with --video, if any
+fn open_video(matches: &getopts::Matches, palette: &frontend::Palette) -> Option<frontend::VideoRecorder> {
+    let filename = match matches.opt_str("video") {
+        Some(f) => f,
+        None => return None
+    };
+    let scale = opt_scale(matches, "video-scale").unwrap_or(1);
+    match frontend::VideoRecorder::create(&Path::new(filename.as_slice()), scale, palette) {
+        Ok(recorder) => Some(recorder),
+        Err(err) => {
+            println!("failed to record video: {}", err);
+            None
+        }
+    }
+}
+
+fn finish_video(recorder: frontend::VideoRecorder) {
+    match recorder.finish() {
+        Ok(frames) => println!("recorded {} frames of video", frames),
+        Err(err) => println!("failed to write video: {}", err)
+    }
+}
+
+// Run in real time on a frontend, recording video with --video
+fn run_interactive(emu: &mut chip8impl::Chip8, frontend: &mut frontend::Frontend,
+                   matches: &getopts::Matches, palette: &frontend::Palette) -> Result<(), chip8impl::Chip8Error> {
+    match open_video(matches, palette) {
+        Some(mut recorder) => {
+            let result = run_frontend(emu, &mut frontend::VideoFrontend::new(frontend, &mut recorder), matches);
+            finish_video(recorder);
+            result
+        },
+        None => run_frontend(emu, frontend, matches)
+    }
+}
+
+// Run in real time, or in the debugger with --debug
+fn run_frontend(emu: &mut chip8impl::Chip8, frontend: &mut frontend::Frontend,
+                matches: &getopts::Matches) -> Result<(), chip8impl::Chip8Error> {
+    if matches.opt_present("debug") {
+        let mut debugger = chip8impl::Debugger::new();
+        match matches.opt_str("map").and_then(|f| load_source_map(f.as_slice())) {
+            Some(map) => debugger.set_source_map(map),
+            None => {}
+        }
+        debugger.run(emu, frontend);
+        Ok(())
+    } else {
+        emu.run(frontend)
+    }
+}
+
+// Read the config file given with --config, or the one in the home directory
+// if there is one
+fn load_config(matches: &getopts::Matches) -> Option<config::Config> {
+    let path = match matches.opt_str("config") {
+        Some(f) => Path::new(f.as_slice()),
+        None => match os::homedir().map(|home| home.join(CONFIG_FILE)) {
+            Some(ref path) if path.exists() => path.clone(),
+            _ => return Some(Default::default())
+        }
+    };
+    let text = match File::open(&path).read_to_string() {
+        Ok(text) => text,
+        Err(err) => {
+            println!("failed to read config: {}", err);
+            return None;
+        }
+    };
+    match config::Config::parse(text.as_slice()) {
+        Ok(config) => Some(config),
+        Err(err) => {
+            println!("{}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+// Read a movie written by --record
+fn load_movie(filename: &str) -> Option<chip8impl::Movie> {
+    match File::open(&Path::new(filename)).and_then(|mut file| chip8impl::Movie::read(&mut file)) {
+        Ok(movie) => Some(movie),
+        Err(err) => {
+            println!("failed to read movie: {}", err);
+            None
+        }
+    }
+}
+
+fn save_movie(filename: &str, movie: &chip8impl::Movie) {
+    match File::create(&Path::new(filename)).and_then(|mut file| movie.write(&mut file)) {
+        Ok(()) => println!("recorded {} frames to {}", movie.frames.len(), filename),
+        Err(err) => println!("failed to write movie: {}", err)
+    }
+}
+
+// Print a listing of the ROM, decoding the extensions of the quirks profile
+fn run_disasm(romfile: &String, matches: &getopts::Matches) {
+    let set = match matches.opt_str("quirks") {
+        Some(name) => match chip8impl::InstructionSet::from_name(name.as_slice()) {
+            Some(set) => set,
+            None => panic!("unknown quirks profile: {}", name)
+        },
+        None => chip8impl::InstructionSet::Chip8
+    };
+    let rom = match File::open(&Path::new(romfile.as_slice())).read_to_end() {
+        Ok(data) => data,
+        Err(err) => {
+            println!("failed to load ROM file: {}", err);
+            return;
+        }
+    };
+    match chip8impl::disassemble(rom.as_slice(), set, &mut io::stdout()) {
+        Ok(()) => {},
+        Err(err) => println!("failed to write disassembly: {}", err)
+    }
+}
+
+// Assemble Octo source to a ROM, and optionally a source map for the debugger
+fn run_asm(srcfile: &String, matches: &getopts::Matches) {
+    let source = match File::open(&Path::new(srcfile.as_slice())).read_to_string() {
+        Ok(source) => source,
+        Err(err) => {
+            println!("failed to read source file: {}", err);
+            return;
+        }
+    };
+    let program = match assembler::assemble(source.as_slice()) {
+        Ok(program) => program,
+        Err(err) => {
+            println!("{}: {}", srcfile, err);
+            return;
+        }
+    };
+
+    let outfile = match matches.opt_str("out") {
+        Some(f) => Path::new(f.as_slice()),
+        None => Path::new(srcfile.as_slice()).with_extension("ch8")
+    };
+    match File::create(&outfile).write(program.bytes.as_slice()) {
+        Ok(()) => println!("wrote {} bytes to {}", program.bytes.len(), outfile.display()),
+        Err(err) => println!("failed to write ROM: {}", err)
+    }
+
+    match matches.opt_str("map") {
+        Some(f) => match File::create(&Path::new(f.as_slice())) {
+            Ok(mut file) => match program.source_map.write(&mut file) {
+                Ok(()) => {},
+                Err(err) => println!("failed to write source map: {}", err)
+            },
+            Err(err) => println!("failed to write source map: {}", err)
+        },
+        None => {}
+    }
+}
+
+// Load a source map written by the asm subcommand
+fn load_source_map(filename: &str) -> Option<assembler::SourceMap> {
+    let text = match File::open(&Path::new(filename)).read_to_string() {
+        Ok(text) => text,
+        Err(err) => {
+            println!("failed to read source map: {}", err);
+            return None;
+        }
+    };
+    match assembler::SourceMap::parse(text.as_slice()) {
+        Ok(map) => Some(map),
+        Err(err) => {
+            println!("failed to read source map: {}", err);
+            None
+        }
+    }
+}
 
 fn main() {
     let args: Vec<String> = os::args();
@@ -12,6 +374,39 @@ fn main() {
     let program = args[0].clone();
     let opts = [
         optopt("f", "ROM filename", "ROM file to load", "FILENAME"),
+        optopt("", "ips", "CPU speed in instructions per second (default 600)", "N"),
+        optopt("", "cpf", "CPU speed in instructions per 60Hz frame (default 10)", "N"),
+        optopt("", "quirks", "interpreter quirks: vip, chip48, schip or xochip", "PROFILE"),
+        optflag("", "waitkey-on-press", "complete FX0A when a key is pressed, not released"),
+        optopt("", "seed", "seed for the random number generator (default: random)", "N"),
+        optopt("", "tone-freq", "beep frequency in Hz (default 440)", "HZ"),
+        optopt("", "volume", "beep volume from 0 to 100 (default 25)", "N"),
+        optopt("", "waveform", "beep waveform: square, triangle, sawtooth or sine", "WAVE"),
+        optopt("", "config", "config file (default: ~/.rustychip8.toml)", "FILENAME"),
+        optopt("", "layout", "keyboard layout: qwerty, azerty, dvorak or numpad", "LAYOUT"),
+        optopt("", "theme", "color theme: default, green, amber or lcd", "THEME"),
+        optopt("", "fg", "foreground color as RRGGBB hex", "COLOR"),
+        optopt("", "bg", "background color as RRGGBB hex", "COLOR"),
+        optopt("", "scale", "window pixels per Chip8 pixel, or fit to the window (default 10)", "N|fit"),
+        optflag("", "fullscreen", "start fullscreen, F11 switches back to a window"),
+        optflag("", "debug", "start in the interactive debugger"),
+        optopt("", "rewind", "seconds of gameplay kept for rewinding (default 10)", "N"),
+        optopt("", "record", "record the input of the run to a movie file", "FILENAME"),
+        optopt("", "play", "replay the input recorded in a movie file", "FILENAME"),
+        optopt("", "map", "source map written by asm, shown by the debugger", "FILENAME"),
+        optopt("", "out", "ROM file written by asm (default: source name with .ch8)", "FILENAME"),
+        optflagopt("", "tui", "play in the terminal, drawing with halfblock or braille characters", "MODE"),
+        optflag("", "headless", "run without a window and dump the final machine state"),
+        optopt("", "cycles", "number of cycles to execute in headless mode", "N"),
+        optopt("", "until-pc", "stop a headless run when PC reaches this address", "ADDR"),
+        optopt("", "until-opcode", "stop a headless run before executing this opcode", "OPCODE"),
+        optopt("", "dump", "file to write the final headless state to", "FILENAME"),
+        optopt("", "audio-out", "record headless beep output to a WAV file", "FILENAME"),
+        optopt("", "video", "record every frame to a GIF, Y4M or numbered PNG files", "FILENAME"),
+        optopt("", "video-scale", "pixels per high resolution pixel in videos (default 1)", "N"),
+        optopt("", "screenshot", "save the final headless display to a PNG, PPM or PBM file", "FILENAME"),
+        optopt("", "screenshot-format", "format of F12 screenshots: png, ppm or pbm (default png)", "FORMAT"),
+        optopt("", "screenshot-scale", "pixels per Chip8 pixel in screenshots (default: window scale, 1 headless)", "N"),
         optflag("h", "help", "print this help")
     ];
     let matches = match getopts(args.tail(), &opts) {
@@ -24,27 +419,140 @@ fn main() {
     };
     if matches.opt_present("h") || romfile.len() == 0 {
         println!("{}", getopts::short_usage(program.as_slice(), &opts));
+        println!("Subcommands:\n    disasm    print a disassembly of the ROM instead of running it");
+        println!("    asm       assemble the Octo source given with -f into a ROM");
         return;
     }
 
-    // initialize SDL for graphical output and keyboard input
-    sdl::init(&[sdl::InitFlag::Video]);
-    sdl::wm::set_caption("RustyChip8", "");
+    if matches.free.len() > 0 {
+        match matches.free[0].as_slice() {
+            "disasm" => run_disasm(&romfile, &matches),
+            "asm" => run_asm(&romfile, &matches),
+            cmd => println!("unknown subcommand: {}", cmd)
+        }
+        return;
+    }
 
-    let mut screen = match sdl::video::set_video_mode(
-        640, 320, 32, &[sdl::video::SurfaceFlag::HWSurface], &[sdl::video::VideoFlag::DoubleBuf])
-    {
-        Ok(screen) => screen,
-        Err(err) => panic!("failed to set video mode: {}", err)
+    let config = match load_config(&matches) {
+        Some(config) => config,
+        None => return
     };
 
     // fire up the emulator
     let mut emu = chip8impl::Chip8::new();
-    if ! emu.load_program( &romfile ) {
-        println!("failed to load ROM file");
+    if matches.opt_present("ips") && matches.opt_present("cpf") {
+        panic!("--ips and --cpf can't be used together");
+    }
+    match opt_uint(&matches, "ips") {
+        Some(ips) => emu.set_instructions_per_second(ips),
+        None => {}
     }
+    match opt_uint(&matches, "cpf") {
+        Some(cpf) => emu.set_cycles_per_frame(cpf),
+        None => {}
+    }
+    match matches.opt_str("quirks") {
+        Some(name) => match chip8impl::Quirks::from_name(name.as_slice()) {
+            Some(quirks) => emu.set_quirks(quirks),
+            None => panic!("unknown quirks profile: {}", name)
+        },
+        None => {}
+    }
+    if matches.opt_present("waitkey-on-press") {
+        let mut quirks = emu.quirks();
+        quirks.waitkey_on_release = false;
+        emu.set_quirks(quirks);
+    }
+    // report the seed that was picked, so the run can be reproduced
+    let seed = match matches.opt_str("seed") {
+        Some(s) => match from_str::<u32>(s.as_slice()) {
+            Some(seed) => seed,
+            None => panic!("invalid value for --seed: {}", s)
+        },
+        None => {
+            let seed = time::precise_time_ns() as u32;
+            println!("random seed: {}", seed);
+            seed
+        }
+    };
+    emu.set_seed(seed);
+    match emu.load_program( &romfile ) {
+        Ok(()) => {},
+        Err(err) => {
+            println!("failed to load ROM file: {}", err);
+            return;
+        }
+    }
+    emu.set_state_path(Path::new(romfile.as_slice()));
 
-    emu.run(&mut screen);
+    // movies replace the seed, quirks and speed with the ones they were
+    // recorded with
+    if matches.opt_present("record") && matches.opt_present("play") {
+        panic!("--record and --play can't be used together");
+    }
+    let mut movie_cycles = None;
+    match matches.opt_str("play") {
+        Some(f) => match load_movie(f.as_slice()) {
+            Some(movie) => {
+                movie_cycles = Some(movie.cycles());
+                emu.play_movie(movie);
+            },
+            None => return
+        },
+        None => {}
+    }
+    if matches.opt_present("record") {
+        emu.start_recording();
+    }
+
+    let display = match display_config(&config, romfile.as_slice(), &matches) {
+        Some(display) => display,
+        None => return
+    };
 
-    sdl::quit();
+    if matches.opt_present("headless") {
+        run_headless(&mut emu, &matches, movie_cycles, &display.palette);
+    } else {
+        let rewind = opt_uint(&matches, "rewind").unwrap_or(DEFAULT_REWIND_SECONDS);
+        emu.set_rewind_frames(rewind * chip8impl::FRAME_RATE as uint);
+        let layout = matches.opt_str("layout");
+        let keymap = match config.keymap(romfile.as_slice(), layout.as_ref().map(|s| s.as_slice())) {
+            Ok(keymap) => keymap,
+            Err(err) => {
+                println!("invalid key mapping: {}", err);
+                return;
+            }
+        };
+        let result = if matches.opt_present("tui") {
+            let mode = match matches.opt_str("tui") {
+                Some(name) => match frontend::TuiMode::from_name(name.as_slice()) {
+                    Some(mode) => mode,
+                    None => panic!("unknown terminal drawing mode: {}", name)
+                },
+                None => frontend::TuiMode::HalfBlock
+            };
+            let mut frontend = frontend::TuiFrontend::new(mode, display.palette, keymap);
+            run_interactive(&mut emu, &mut frontend, &matches, &display.palette)
+        } else {
+            let gamepad = match config.gamepad(romfile.as_slice()) {
+                Ok(gamepad) => gamepad,
+                Err(err) => {
+                    println!("invalid gamepad mapping: {}", err);
+                    return;
+                }
+            };
+            let mut frontend = frontend::SdlFrontend::new(audio_config(&matches), display, keymap, gamepad);
+            frontend.set_screenshots(screenshot_config(romfile.as_slice(), &matches));
+            run_interactive(&mut emu, &mut frontend, &matches, &display.palette)
+        };
+        match result {
+            Ok(()) => {},
+            Err(err) => println!("emulation halted: {}", err)
+        }
+    }
+
+    match (matches.opt_str("record"), emu.stop_recording()) {
+        (Some(f), Some(movie)) => save_movie(f.as_slice(), &movie),
+        _ => {}
+    }
 }